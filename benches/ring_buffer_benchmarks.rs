@@ -0,0 +1,188 @@
+use coalescing_buffer::ring::{new_ring_buffer, Receiver, RingBufferBuilder, Sender};
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use std::collections::hash_map::RandomState;
+use std::thread;
+use std::time::Instant;
+
+const CAPACITIES: [usize; 3] = [16, 256, 4096];
+
+fn filled_buffer(capacity: usize) -> (Sender<usize, usize>, Receiver<usize, usize>) {
+    let (sender, receiver) = new_ring_buffer(capacity);
+    for key in 0..capacity {
+        sender.offer(key, key);
+    }
+    (sender, receiver)
+}
+
+fn offer_new_key(c: &mut Criterion) {
+    let mut group = c.benchmark_group("offer_new_key");
+    for &capacity in CAPACITIES.iter() {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(capacity),
+            &capacity,
+            |b, &capacity| {
+                let (sender, receiver) = new_ring_buffer::<usize, usize>(capacity);
+                let mut key = 0;
+                b.iter(|| {
+                    if !sender.offer(key, key) {
+                        receiver.poll_all();
+                        sender.offer(key, key);
+                    }
+                    key += 1;
+                });
+            },
+        );
+    }
+    group.finish();
+}
+
+fn offer_coalescing_hit(c: &mut Criterion) {
+    let mut group = c.benchmark_group("offer_coalescing_hit");
+    for &capacity in CAPACITIES.iter() {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(capacity),
+            &capacity,
+            |b, &capacity| {
+                // the hot key sits in the last slot so every offer scans the whole buffer
+                let (sender, _receiver) = filled_buffer(capacity);
+                let hot_key = capacity - 1;
+                b.iter(|| sender.offer(hot_key, hot_key));
+            },
+        );
+    }
+    group.finish();
+}
+
+fn offer_coalescing_hit_indexed(c: &mut Criterion) {
+    let mut group = c.benchmark_group("offer_coalescing_hit_indexed");
+    for &capacity in CAPACITIES.iter() {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(capacity),
+            &capacity,
+            |b, &capacity| {
+                let (sender, _receiver) = RingBufferBuilder::new(capacity)
+                    .with_hash_index(RandomState::new())
+                    .build();
+                for key in 0..capacity {
+                    sender.offer(key, key);
+                }
+                let hot_key = capacity - 1;
+                b.iter(|| sender.offer(hot_key, hot_key));
+            },
+        );
+    }
+    group.finish();
+}
+
+// A few hot keys updated over and over, as with a handful of busy instruments: nearly every
+// offer coalesces into a slot near the front, and the consumer only drains now and then.
+fn offer_few_hot_keys(c: &mut Criterion) {
+    const HOT_KEYS: usize = 8;
+    const OFFERS: usize = 1_000;
+    let mut group = c.benchmark_group("offer_few_hot_keys");
+    group.throughput(Throughput::Elements(OFFERS as u64));
+    for &capacity in CAPACITIES.iter() {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(capacity),
+            &capacity,
+            |b, &capacity| {
+                let (sender, receiver) = new_ring_buffer::<usize, usize>(capacity);
+                b.iter(|| {
+                    for value in 0..OFFERS {
+                        sender.offer(value % HOT_KEYS, value);
+                    }
+                    receiver.poll_all()
+                });
+            },
+        );
+    }
+    group.finish();
+}
+
+fn poll_one(c: &mut Criterion) {
+    let mut group = c.benchmark_group("poll_one");
+    for &capacity in CAPACITIES.iter() {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(capacity),
+            &capacity,
+            |b, &capacity| {
+                let (sender, receiver) = new_ring_buffer::<usize, usize>(capacity);
+                let mut value = 0;
+                b.iter(|| {
+                    sender.offer_value_only(value);
+                    value += 1;
+                    receiver.poll(1)
+                });
+            },
+        );
+    }
+    group.finish();
+}
+
+fn poll_all_full(c: &mut Criterion) {
+    let mut group = c.benchmark_group("poll_all_full");
+    for &capacity in CAPACITIES.iter() {
+        group.throughput(Throughput::Elements(capacity as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(capacity),
+            &capacity,
+            |b, &capacity| {
+                b.iter_batched(
+                    || filled_buffer(capacity),
+                    |(_sender, receiver)| receiver.poll_all(),
+                    BatchSize::LargeInput,
+                );
+            },
+        );
+    }
+    group.finish();
+}
+
+// Producer and consumer on their own threads, so that the cursors bounce between cores the way
+// they do in real use. This is where false sharing between the cursors shows up.
+fn spsc_throughput(c: &mut Criterion) {
+    const MESSAGES: usize = 100_000;
+    let mut group = c.benchmark_group("spsc_throughput");
+    group.throughput(Throughput::Elements(MESSAGES as u64));
+    group.sample_size(10);
+    for &capacity in CAPACITIES.iter() {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(capacity),
+            &capacity,
+            |b, &capacity| {
+                b.iter_custom(|iters| {
+                    let start = Instant::now();
+                    for _ in 0..iters {
+                        let (sender, receiver) = new_ring_buffer::<usize, usize>(capacity);
+                        let producer = thread::spawn(move || {
+                            for value in 0..MESSAGES {
+                                while !sender.offer_value_only(value) {
+                                    thread::yield_now();
+                                }
+                            }
+                        });
+                        let mut received = 0;
+                        while received < MESSAGES {
+                            received += receiver.poll_all().len();
+                        }
+                        producer.join().unwrap();
+                    }
+                    start.elapsed()
+                });
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    offer_new_key,
+    offer_coalescing_hit,
+    offer_coalescing_hit_indexed,
+    offer_few_hot_keys,
+    poll_one,
+    poll_all_full,
+    spsc_throughput
+);
+criterion_main!(benches);
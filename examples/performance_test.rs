@@ -1,29 +1,38 @@
-use coalescing_buffer::ring::{Sender, Receiver, new_ring_buffer};
-use std::time::{Instant, Duration, SystemTime};
-use chrono::Local;
-use std::thread;
-use std::sync::{Barrier, Arc};
-use std::sync::atomic::{AtomicU64, Ordering};
+#![allow(non_snake_case)]
+
+use coalescing_buffer::ring::{BusySpin, Receiver, RingBufferBuilder, Sender};
 use lazy_static::lazy_static;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
 static BILLION: i64 = 1000000000;
-static POISON_PILL: MarketSnapshot = MarketSnapshot { instrumentId: -1, bestBid: -1, bestAsk: -1 };
+static POISON_PILL: MarketSnapshot = MarketSnapshot {
+    instrumentId: -1,
+    bestBid: -1,
+    bestAsk: -1,
+};
 static NUMBER_OF_INSTRUMENTS: i32 = 10;
-static SECONDS: i32 = 1000;
 lazy_static! {
     static ref STOP_WATCH: StopWatch = StopWatch {
-            startingGate: Arc::new(Barrier::new(2)),
-            init_time: SystemTime::now(),
-            start_time: AtomicU64::new(0),
-            end_time: AtomicU64::new(0),
-        };
+        startingGate: Arc::new(Barrier::new(2)),
+        init_time: SystemTime::now(),
+        start_time: AtomicU64::new(0),
+        end_time: AtomicU64::new(0),
+    };
 }
 
-pub fn main() {PerformanceTest::main();}
+pub fn main() {
+    PerformanceTest::main();
+}
 
 #[derive(Debug, Clone, Eq, PartialEq)]
-pub struct MarketSnapshot { instrumentId: i64, bestBid: i64, bestAsk: i64 }
-
+pub struct MarketSnapshot {
+    instrumentId: i64,
+    bestBid: i64,
+    bestAsk: i64,
+}
 
 pub struct Producer<'a> {
     sender: Sender<i64, MarketSnapshot>,
@@ -35,7 +44,7 @@ pub struct Producer<'a> {
 }
 
 impl<'a> Producer<'a> {
-    pub fn createSnapshots( numberOfInstruments: usize) -> Vec<MarketSnapshot> {
+    pub fn createSnapshots(numberOfInstruments: usize) -> Vec<MarketSnapshot> {
         let mut snapshots: Vec<MarketSnapshot> = Vec::with_capacity(numberOfInstruments);
 
         for i in 0..numberOfInstruments {
@@ -49,17 +58,18 @@ impl<'a> Producer<'a> {
             });
         }
 
-        return snapshots;
+        snapshots
     }
 
-
     fn run(&mut self) {
         STOP_WATCH.producerIsReady();
         for i in 1..=self.number_of_updates {
             let next = self.nextSnapshot();
             self.put(self.nextId(i) as i64, next);
         }
-        self.put(self.poisonPill.instrumentId, self.poisonPill.clone());
+        if !self.sender.offer_control(self.poisonPill.clone()) {
+            panic!("failed to deliver the poison pill");
+        }
     }
 
     /**
@@ -74,7 +84,7 @@ impl<'a> Producer<'a> {
             }
             register >>= 1;
         }
-        return self.numberOfInstruments;
+        self.numberOfInstruments
     }
 
     fn nextSnapshot(&mut self) -> MarketSnapshot {
@@ -90,12 +100,11 @@ impl<'a> Producer<'a> {
         let instId = snapshot.instrumentId;
         let success = self.sender.offer(id, snapshot);
         if !success {
-            panic!(format!("failed to add instrument id {}", instId));
+            panic!("failed to add instrument id {}", instId);
         }
     }
 }
 
-
 #[derive()]
 pub struct StopWatch {
     startingGate: Arc<Barrier>,
@@ -115,37 +124,47 @@ impl StopWatch {
 
     fn producerIsReady(&self) {
         self.awaitStart();
-        self.start_time.store(self.init_time.elapsed().unwrap().as_nanos() as u64, Ordering::Relaxed);
+        self.start_time.store(
+            self.init_time.elapsed().unwrap().as_nanos() as u64,
+            Ordering::Relaxed,
+        );
     }
 
     fn consumerIsDone(&self) {
-        self.end_time.store(self.init_time.elapsed().unwrap().as_nanos() as u64, Ordering::Relaxed);
+        self.end_time.store(
+            self.init_time.elapsed().unwrap().as_nanos() as u64,
+            Ordering::Relaxed,
+        );
     }
 
     fn nanosTaken(&self) -> Duration {
         let start = self.start_time.load(Ordering::Relaxed);
         let end = self.end_time.load(Ordering::Relaxed);
-        return Duration::from_nanos(end - start);
+        Duration::from_nanos(end - start)
     }
 }
 
-
 struct Consumer<'a> {
     receiver: Receiver<i64, MarketSnapshot>,
     numberOfInstruments: i32,
     poisonPill: &'a MarketSnapshot,
     latestSnapshots: Vec<MarketSnapshot>,
     readCounter: i64,
+    maxLatency: Duration,
 }
 
 impl<'a> Consumer<'a> {
-    pub fn run(mut self) -> Self{
+    pub fn run(mut self) -> Self {
         STOP_WATCH.consumerIsReady();
+        let mut bucket = Vec::with_capacity(self.numberOfInstruments as usize);
         loop {
-            let bucket = self.receiver.poll(self.numberOfInstruments as usize);
-            for i in 0..bucket.len() {
+            self.receiver
+                .poll_into(&mut bucket, self.numberOfInstruments as usize);
+            for latency in self.receiver.last_poll_latencies() {
+                self.maxLatency = self.maxLatency.max(latency);
+            }
+            for snapshot in &bucket {
                 self.readCounter += 1;
-                let snapshot = &bucket[i];
                 if snapshot == self.poisonPill {
                     STOP_WATCH.consumerIsDone();
                     return self;
@@ -153,7 +172,6 @@ impl<'a> Consumer<'a> {
                 self.latestSnapshots[snapshot.instrumentId as usize] = snapshot.clone();
             }
             self.simulateProcessing();
-            // allocation happens in poll, to be removed
         }
     }
 
@@ -185,38 +203,49 @@ impl PerformanceTest {
             snapshots: Producer::createSnapshots(NUMBER_OF_INSTRUMENTS as usize),
             nextSnapshot: 0,
         };
-        let mut consumer = Consumer {
+        let consumer = Consumer {
             receiver: self.receiver,
             numberOfInstruments: NUMBER_OF_INSTRUMENTS,
             poisonPill: &POISON_PILL,
-            latestSnapshots: (0..NUMBER_OF_INSTRUMENTS).map(|_| POISON_PILL.clone()).collect(),
+            latestSnapshots: (0..NUMBER_OF_INSTRUMENTS)
+                .map(|_| POISON_PILL.clone())
+                .collect(),
             readCounter: 0,
+            maxLatency: Duration::from_nanos(0),
         };
 
-
-        let producer_t = thread::spawn(move || { producer.run(); });
-        let consumer_t = thread::spawn(move || { consumer.run() });
+        let _producer_t = thread::spawn(move || {
+            producer.run();
+        });
+        let consumer_t = thread::spawn(move || consumer.run());
 
         let consumer = consumer_t.join().unwrap();
 
-        return Self::computeAndPrintResults(numberOfUpdates, &consumer, STOP_WATCH.nanosTaken().as_nanos() as i64);
+        Self::computeAndPrintResults(
+            numberOfUpdates,
+            &consumer,
+            STOP_WATCH.nanosTaken().as_nanos() as i64,
+        )
     }
 
-
-    fn computeAndPrintResults(numberOfUpdates:i64, consumer: &Consumer, nanosTaken: i64) -> i64 {
+    fn computeAndPrintResults(numberOfUpdates: i64, consumer: &Consumer, nanosTaken: i64) -> i64 {
         for i in 0..consumer.latestSnapshots.len() {
             println!("{:?}", consumer.latestSnapshots[i]);
         }
 
-        println!("time {}s", Duration::from_nanos(nanosTaken as u64).as_secs());
+        println!(
+            "time {}s",
+            Duration::from_nanos(nanosTaken as u64).as_secs()
+        );
 
         let compressionRatio = (1.0 * numberOfUpdates as f64) / consumer.readCounter as f64;
         println!("compression ratio = {:.1}", compressionRatio);
 
         let megaOpsPerSecond = (1000.0 * numberOfUpdates as f64) / nanosTaken as f64;
         println!("mops = {:.1}", megaOpsPerSecond);
+        println!("max latency = {:?}", consumer.maxLatency);
 
-        return megaOpsPerSecond as i64;
+        megaOpsPerSecond as i64
     }
 
     fn main() {
@@ -238,7 +267,11 @@ impl PerformanceTest {
     }
 
     fn run_int(runNumber: i32, numberOfUpdates: i64) -> i64 {
-        let (sender, receiver) = new_ring_buffer(1 << 20);
+        let (mut sender, receiver) = RingBufferBuilder::new(1 << 20)
+            .measure_latency()
+            .wait_strategy(BusySpin)
+            .build();
+        sender.reserve_control_slots(1);
 
         let test = PerformanceTest {
             receiver,
@@ -247,7 +280,7 @@ impl PerformanceTest {
         };
 
         println!("======================================= run {} =======================================", runNumber);
-        return test.run();
+        test.run()
     }
 
     fn update(results: &mut Vec<i64>, result: i64) {
@@ -255,17 +288,15 @@ impl PerformanceTest {
         results.push(result);
     }
 
-    fn areAllResultsTheSame(results: &Vec<i64>) -> bool {
+    fn areAllResultsTheSame(results: &[i64]) -> bool {
         let oldestResult = results[0];
 
-        for i in 1..results.len() {
-            let result = results[i];
-
+        for &result in results.iter().skip(1) {
             if result != oldestResult {
                 return false;
             }
         }
 
-        return true;
+        true
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,143 @@
+use core::fmt;
+#[cfg(feature = "std")]
+use std::error::Error as StdError;
+
+/// Every way the buffer's fallible calls can fail, one error type for application code to
+/// `?` the specific ones into. The offers that can fail return an `OfferError` instead, which
+/// hands the key and value back; converting it into an `Error` drops them, so match on it
+/// first to get them back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The buffer has no free slot and the key is not already pending.
+    Full,
+    /// Nothing is pending right now, but the sender may still offer more.
+    Empty,
+    /// The sender has gone away and everything it offered has been read.
+    Disconnected,
+    /// The requested capacity cannot be used to build a buffer.
+    InvalidCapacity(usize),
+    /// The receiver has gone away, or the buffer was closed.
+    Closed,
+}
+
+/// The name `Error` first went by, kept for the `try_*` methods' callers.
+pub type CoalescingError = Error;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Full => write!(f, "buffer is full"),
+            Error::Empty => write!(f, "buffer is empty"),
+            Error::Disconnected => write!(f, "sender is disconnected"),
+            Error::InvalidCapacity(capacity) => write!(f, "invalid buffer capacity {}", capacity),
+            Error::Closed => write!(f, "buffer is closed"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl StdError for Error {}
+
+/// An offer that was not accepted. The key and value are handed back so nothing is dropped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OfferError<K, V> {
+    /// The buffer has no free slot and the key is not already pending.
+    Full { key: K, value: V },
+    /// The receiver has gone away.
+    Closed { key: K, value: V },
+}
+
+impl<K, V> OfferError<K, V> {
+    pub(crate) fn new(error: Error, key: K, value: V) -> Self {
+        match error {
+            Error::Closed => OfferError::Closed { key, value },
+            _ => OfferError::Full { key, value },
+        }
+    }
+
+    /// Takes the rejected key and value back.
+    pub fn into_inner(self) -> (K, V) {
+        match self {
+            OfferError::Full { key, value } | OfferError::Closed { key, value } => (key, value),
+        }
+    }
+}
+
+impl<K, V> fmt::Display for OfferError<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&Error::from(self), f)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<K: fmt::Debug, V: fmt::Debug> StdError for OfferError<K, V> {}
+
+impl<K, V> From<&OfferError<K, V>> for Error {
+    fn from(error: &OfferError<K, V>) -> Self {
+        match error {
+            OfferError::Full { .. } => Error::Full,
+            OfferError::Closed { .. } => Error::Closed,
+        }
+    }
+}
+
+impl<K, V> From<OfferError<K, V>> for Error {
+    fn from(error: OfferError<K, V>) -> Self {
+        Error::from(&error)
+    }
+}
+
+/// The sender has gone away and everything it offered has been read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Disconnected;
+
+impl fmt::Display for Disconnected {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&Error::Disconnected, f)
+    }
+}
+
+#[cfg(feature = "std")]
+impl StdError for Disconnected {}
+
+/// Why `Receiver::try_recv` returned no value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryRecvError {
+    /// Nothing is pending right now, but the sender may still offer more.
+    Empty,
+    /// The sender has gone away and everything it offered has been read.
+    Disconnected,
+}
+
+impl fmt::Display for TryRecvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TryRecvError::Empty => fmt::Display::fmt(&Error::Empty, f),
+            TryRecvError::Disconnected => fmt::Display::fmt(&Disconnected, f),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl StdError for TryRecvError {}
+
+impl From<Disconnected> for TryRecvError {
+    fn from(_: Disconnected) -> Self {
+        TryRecvError::Disconnected
+    }
+}
+
+impl From<Disconnected> for Error {
+    fn from(_: Disconnected) -> Self {
+        Error::Disconnected
+    }
+}
+
+impl From<TryRecvError> for Error {
+    fn from(error: TryRecvError) -> Self {
+        match error {
+            TryRecvError::Empty => Error::Empty,
+            TryRecvError::Disconnected => Error::Disconnected,
+        }
+    }
+}
@@ -1,15 +1,19 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+mod error;
+
 /// Coalescing ring buffer is a circular buffer of key and value pair(like a map). A update with
 /// same key will replace the value if the value is not yet read
 ///
+/// `new_ring_buffer` returns the producer and consumer handles directly. A buffer built with
+/// `CoalescingRingBuffer::new` can be inspected first and then `split` into the same handles.
+///
 /// ```
 /// extern crate coalescing_buffer;
 ///
 /// use coalescing_buffer::ring::*;
 /// use std::thread;
 ///
-/// const POISON_PILL: i32 = -1;
-///
-///
 /// fn main() {
 ///     let (sender, receiver) = new_ring_buffer(25); // This will be changed to 32 nearest 2^x
 ///     let producer = thread::spawn(move || producer_task(sender));
@@ -25,23 +29,22 @@
 ///         for message in 0..10 {
 ///             let success = sender.offer(message, run * 10 + message);
 ///             if !success {
-///                 sender.offer_value_only(POISON_PILL);
-///                 return true;
+///                 return true; // dropping the sender ends the stream as well
 ///             }
 ///         }
 ///     }
-///     sender.offer_value_only(POISON_PILL);
+///     sender.close();
 ///     return false;
 /// }
 ///
 /// fn consumer_task(receiver: Receiver<i32, i32>) {
-///     loop {
-///         let values = receiver.poll(100);
-///         if values.contains(&POISON_PILL) {
-///             return;
-///         }
-///     }
+///     // only comes back empty once the sender is closed and everything has been read
+///     while !receiver.poll_blocking(100).is_empty() {}
 /// }
 /// ```
 ///
 pub mod ring;
+
+extern crate alloc;
+
+pub use self::error::{CoalescingError, Disconnected, Error, OfferError, TryRecvError};
@@ -0,0 +1,325 @@
+use super::spsc_coalescing_ring_buffer::next_power_of_two;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::cell::UnsafeCell;
+use core::cmp;
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use crossbeam_utils::{Backoff, CachePadded};
+
+// Neither pinned by a receiver nor being replaced by the producer. Positions start at 1, so
+// that this is never one of them.
+const NO_POSITION: usize = 0;
+
+// The cursor of a receiver that is gone, which no longer holds any slot back.
+const GONE: usize = usize::MAX;
+
+// The read side of one receiver.
+struct Cursor {
+    // the next position to read, or `GONE`
+    next_read: AtomicUsize,
+    // the position being read, or `NO_POSITION`
+    peeking: AtomicUsize,
+}
+
+// One ring for every receiver. The producer appends to it, and each receiver reads it with a
+// cursor of its own, cloning the values out. A slot is free again once the slowest receiver
+// has passed it.
+//
+// A value is replaced in place only while no receiver has read its slot, under the same sort
+// of handshake as `CoalescingRingBuffer::replace_value`: the producer announces the position
+// in `writing` and then checks that no receiver pins it, while a receiver announces it in its
+// `peeking` and then checks `writing`. Once some receiver has read the slot, the new value is
+// appended instead and the old slot is marked superseded, for the receivers that have not
+// read it yet to skip.
+struct BroadcastRing<K, V> {
+    // only ever touched by the producer
+    keys: UnsafeCell<Vec<Option<K>>>,
+    values: Vec<UnsafeCell<Option<V>>>,
+    superseded: Vec<AtomicBool>,
+    mask: usize,
+    next_write: CachePadded<AtomicUsize>,
+    writing: CachePadded<AtomicUsize>,
+    cursors: Vec<CachePadded<Cursor>>,
+    rejection_count: AtomicUsize,
+    sender_dropped: AtomicBool,
+}
+
+// The keys stay with the producer. The values are cloned by several receivers at once, so
+// they have to be `Sync` as well as `Send`.
+unsafe impl<K: Send, V: Send + Sync> Send for BroadcastRing<K, V> {}
+unsafe impl<K: Send, V: Send + Sync> Sync for BroadcastRing<K, V> {}
+
+impl<K, V> BroadcastRing<K, V> {
+    fn new(capacity: usize, num_receivers: usize) -> Self {
+        assert!(capacity > 0, "capacity must be greater than zero");
+        let capacity = next_power_of_two(capacity);
+        BroadcastRing {
+            keys: UnsafeCell::new((0..capacity).map(|_| None).collect()),
+            values: (0..capacity).map(|_| UnsafeCell::new(None)).collect(),
+            superseded: (0..capacity).map(|_| AtomicBool::new(false)).collect(),
+            mask: capacity - 1,
+            next_write: CachePadded::new(AtomicUsize::new(1)),
+            writing: CachePadded::new(AtomicUsize::new(NO_POSITION)),
+            cursors: (0..num_receivers)
+                .map(|_| {
+                    CachePadded::new(Cursor {
+                        next_read: AtomicUsize::new(1),
+                        peeking: AtomicUsize::new(NO_POSITION),
+                    })
+                })
+                .collect(),
+            rejection_count: AtomicUsize::new(0),
+            sender_dropped: AtomicBool::new(false),
+        }
+    }
+
+    fn capacity(&self) -> usize {
+        self.mask + 1
+    }
+
+    fn index(&self, position: usize) -> usize {
+        position & self.mask
+    }
+
+    // The positions the slowest and the fastest receiver read next, or `None` once they are
+    // all gone.
+    fn read_bounds(&self) -> Option<(usize, usize)> {
+        self.cursors
+            .iter()
+            .map(|cursor| cursor.next_read.load(Ordering::SeqCst))
+            .filter(|&next_read| next_read != GONE)
+            .fold(None, |bounds, next_read| match bounds {
+                None => Some((next_read, next_read)),
+                Some((slowest, fastest)) => {
+                    Some((cmp::min(slowest, next_read), cmp::max(fastest, next_read)))
+                }
+            })
+    }
+
+    fn receiver_count(&self) -> usize {
+        self.cursors
+            .iter()
+            .filter(|cursor| cursor.next_read.load(Ordering::SeqCst) != GONE)
+            .count()
+    }
+
+    fn reject(&self) -> bool {
+        self.rejection_count.fetch_add(1, Ordering::Relaxed);
+        false
+    }
+
+    fn offer(&self, key: Option<K>, value: V) -> bool
+    where
+        K: Eq,
+    {
+        let (slowest, _) = match self.read_bounds() {
+            Some(bounds) => bounds,
+            None => return self.reject(),
+        };
+        let next_write = self.next_write.load(Ordering::SeqCst);
+        // the newest entry for the key that some receiver has not read yet
+        let pending = key.as_ref().and_then(|key| {
+            let keys = unsafe { &*self.keys.get() };
+            (slowest..next_write)
+                .rev()
+                .find(|&position| keys[self.index(position)].as_ref() == Some(key))
+        });
+        let value = match pending {
+            Some(position) => match self.replace(position, value) {
+                Ok(()) => return true,
+                Err(value) => value,
+            },
+            None => value,
+        };
+        if next_write - slowest >= self.capacity() {
+            return self.reject();
+        }
+        // every receiver is past the previous occupant of the slot, and none reads this one
+        // before `next_write` moves
+        let index = self.index(next_write);
+        unsafe {
+            let keys = &mut *self.keys.get();
+            keys[index] = key;
+            *self.values[index].get() = Some(value);
+        }
+        self.superseded[index].store(false, Ordering::SeqCst);
+        self.next_write.store(next_write + 1, Ordering::SeqCst);
+        // The receivers that have not read the older entry yet skip it for this one. One that
+        // is reading it right now gets both.
+        if let Some(position) = pending {
+            self.superseded[self.index(position)].store(true, Ordering::SeqCst);
+        }
+        true
+    }
+
+    // Replaces the value at `position` if no receiver has read it, or is reading it, and hands
+    // the value back otherwise.
+    fn replace(&self, position: usize, value: V) -> Result<(), V> {
+        self.writing.store(position, Ordering::SeqCst);
+        // `peeking` before `next_read`: a receiver that stopped peeking has moved on already
+        let unread = self.cursors.iter().all(|cursor| {
+            cursor.peeking.load(Ordering::SeqCst) != position && {
+                let next_read = cursor.next_read.load(Ordering::SeqCst);
+                next_read == GONE || next_read <= position
+            }
+        });
+        let result = if unread {
+            unsafe { *self.values[self.index(position)].get() = Some(value) };
+            Ok(())
+        } else {
+            Err(value)
+        };
+        self.writing.store(NO_POSITION, Ordering::SeqCst);
+        result
+    }
+
+    // Clones the value at `position` for the receiver with `cursor`, unless it was superseded,
+    // and moves the cursor past it.
+    fn read(&self, cursor: &Cursor, position: usize) -> Option<V>
+    where
+        V: Clone,
+    {
+        let backoff = Backoff::new();
+        loop {
+            cursor.peeking.store(position, Ordering::SeqCst);
+            if self.writing.load(Ordering::SeqCst) != position {
+                break;
+            }
+            cursor.peeking.store(NO_POSITION, Ordering::SeqCst);
+            backoff.snooze();
+        }
+        let index = self.index(position);
+        let value = if self.superseded[index].load(Ordering::SeqCst) {
+            None
+        } else {
+            unsafe { (*self.values[index].get()).clone() }
+        };
+        cursor.next_read.store(position + 1, Ordering::SeqCst);
+        cursor.peeking.store(NO_POSITION, Ordering::SeqCst);
+        value
+    }
+}
+
+/// The producer handle of a broadcast buffer, see `new_broadcast_ring_buffer`.
+pub struct BroadcastSender<K, V> {
+    ring: Arc<BroadcastRing<K, V>>,
+    _phantom_data: PhantomData<*mut ()>, // one producer thread, like `Sender`
+}
+
+unsafe impl<K: Send, V: Send + Sync> Send for BroadcastSender<K, V> {}
+
+impl<K: Send + Eq, V: Send + Sync> BroadcastSender<K, V> {
+    /// Offers the value to every receiver. It replaces the pending value for the key if no
+    /// receiver has read that yet, and is appended otherwise, in which case the receivers that
+    /// have not read the older value skip it. Fails when the slowest receiver leaves no room,
+    /// or every receiver is gone.
+    pub fn offer(&self, key: K, value: V) -> bool {
+        self.ring.offer(Some(key), value)
+    }
+
+    /// Keyless counterpart of `offer`, the value never coalesces.
+    pub fn offer_value_only(&self, value: V) -> bool {
+        self.ring.offer(None, value)
+    }
+
+    /// How many receivers are still around to read what is offered.
+    pub fn receiver_count(&self) -> usize {
+        self.ring.receiver_count()
+    }
+
+    /// Whether every receiver is gone, after which nothing offered will ever be read.
+    pub fn is_closed(&self) -> bool {
+        self.receiver_count() == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.ring.capacity()
+    }
+
+    pub fn rejection_count(&self) -> usize {
+        self.ring.rejection_count.load(Ordering::Relaxed)
+    }
+}
+
+impl<K, V> Drop for BroadcastSender<K, V> {
+    fn drop(&mut self) {
+        self.ring.sender_dropped.store(true, Ordering::SeqCst);
+    }
+}
+
+/// One of the consumer handles of a broadcast buffer. It reads every value offered, cloned out
+/// of the shared ring, at its own pace.
+pub struct BroadcastReceiver<K, V> {
+    ring: Arc<BroadcastRing<K, V>>,
+    // which of the ring's cursors is this receiver's
+    cursor: usize,
+    _phantom_data: PhantomData<*mut ()>, // one consumer thread, like `Receiver`
+}
+
+unsafe impl<K: Send, V: Send + Sync> Send for BroadcastReceiver<K, V> {}
+
+impl<K: Send, V: Send + Sync + Clone> BroadcastReceiver<K, V> {
+    /// Reads up to `max_items` entries this receiver has not read yet. Entries superseded by a
+    /// later offer for their key are skipped, but count towards `max_items`.
+    pub fn poll(&self, max_items: usize) -> Vec<V> {
+        let cursor = &self.ring.cursors[self.cursor];
+        let first = cursor.next_read.load(Ordering::SeqCst);
+        let next_write = self.ring.next_write.load(Ordering::SeqCst);
+        let read_up_to = cmp::min(first.saturating_add(max_items), next_write);
+        (first..read_up_to)
+            .filter_map(|position| self.ring.read(cursor, position))
+            .collect()
+    }
+
+    pub fn poll_all(&self) -> Vec<V> {
+        self.poll(usize::MAX)
+    }
+}
+
+impl<K, V> BroadcastReceiver<K, V> {
+    /// The entries this receiver has not read yet, including superseded ones.
+    pub fn size(&self) -> usize {
+        let first = self.ring.cursors[self.cursor]
+            .next_read
+            .load(Ordering::SeqCst);
+        self.ring.next_write.load(Ordering::SeqCst) - first
+    }
+
+    pub fn is_disconnected(&self) -> bool {
+        self.ring.sender_dropped.load(Ordering::SeqCst)
+    }
+}
+
+impl<K, V> Drop for BroadcastReceiver<K, V> {
+    fn drop(&mut self) {
+        self.ring.cursors[self.cursor]
+            .next_read
+            .store(GONE, Ordering::SeqCst);
+    }
+}
+
+/// Creates `num_receivers` receivers that each see every value offered to the returned
+/// `BroadcastSender`, coalesced per key among the entries they have not read yet. They share
+/// one ring of `capacity` rounded up to a power of two, so a receiver that falls behind holds
+/// the slots it has not read back from the others.
+///
+/// `let (sender, receivers) = new_broadcast_ring_buffer(64, 3);`
+pub fn new_broadcast_ring_buffer<K: Send + Eq, V: Send + Sync + Clone>(
+    capacity: usize,
+    num_receivers: usize,
+) -> (BroadcastSender<K, V>, Vec<BroadcastReceiver<K, V>>) {
+    let ring = Arc::new(BroadcastRing::new(capacity, num_receivers));
+    let receivers = (0..num_receivers)
+        .map(|cursor| BroadcastReceiver {
+            ring: ring.clone(),
+            cursor,
+            _phantom_data: PhantomData,
+        })
+        .collect();
+    let sender = BroadcastSender {
+        ring,
+        _phantom_data: PhantomData,
+    };
+    (sender, receivers)
+}
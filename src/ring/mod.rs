@@ -1,2 +1,33 @@
+mod broadcast_coalescing_ring_buffer;
+#[cfg(feature = "std")]
+mod mpsc_coalescing_ring_buffer;
+#[cfg(feature = "async")]
+mod receiver_stream;
+#[cfg(feature = "std")]
+mod selector;
+mod single_threaded_coalescing_buffer;
 mod spsc_coalescing_ring_buffer;
-pub use self::spsc_coalescing_ring_buffer::{new_ring_buffer, Receiver, Sender};
+#[cfg(feature = "std")]
+mod wait_strategy;
+pub use self::broadcast_coalescing_ring_buffer::{
+    new_broadcast_ring_buffer, BroadcastReceiver, BroadcastSender,
+};
+#[cfg(feature = "std")]
+pub use self::mpsc_coalescing_ring_buffer::{new_mpsc_ring_buffer, MpscSender};
+#[cfg(feature = "async")]
+pub use self::receiver_stream::ReceiverStream;
+#[cfg(feature = "std")]
+pub use self::selector::Selector;
+pub use self::single_threaded_coalescing_buffer::{
+    LocalCoalescingBuffer, SingleThreadedCoalescingBuffer,
+};
+#[cfg(feature = "std")]
+pub use self::spsc_coalescing_ring_buffer::StallAction;
+pub use self::spsc_coalescing_ring_buffer::{
+    new_fixed_ring_buffer, new_ring_buffer, new_shared_ring_buffer, try_new_ring_buffer,
+    BufferMetrics, BufferSnapshot, CoalescePolicy, CoalescingRingBuffer, Drain, KeyedSender,
+    PollStats, Receiver, RejectReason, RingBufferBuilder, Sender, StatsHandle,
+};
+#[cfg(feature = "std")]
+pub use self::wait_strategy::{Blocking, BusySpin, WaitStrategy, Yielding};
+pub use crate::{CoalescingError, Disconnected, Error, OfferError, TryRecvError};
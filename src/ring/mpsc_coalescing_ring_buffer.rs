@@ -0,0 +1,105 @@
+use super::spsc_coalescing_ring_buffer::{new_ring_buffer, Receiver, Sender};
+use crate::{Error, OfferError};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// A producer handle that can be cloned and used from several threads at once. Offers from
+/// all clones are serialised by a lock around a single `Sender`, so coalescing stays correct
+/// per key and the consumer side is exactly the SPSC `Receiver`.
+pub struct MpscSender<K, V>
+where
+    V: Send,
+{
+    sender: Arc<Mutex<Sender<K, V>>>,
+}
+
+impl<K, V> Clone for MpscSender<K, V>
+where
+    V: Send,
+{
+    fn clone(&self) -> Self {
+        MpscSender {
+            sender: self.sender.clone(),
+        }
+    }
+}
+
+impl<K: Send + Eq, V: Send> MpscSender<K, V> {
+    fn lock(&self) -> MutexGuard<'_, Sender<K, V>> {
+        // a producer that panicked mid offer leaves the buffer consistent, so carry on
+        self.sender
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn offer(&self, key: K, value: V) -> bool {
+        self.lock().offer(key, value)
+    }
+
+    /// Same as `Sender::offer_all`. The lock is held for the whole batch, so offers from other
+    /// clones never interleave with it.
+    pub fn offer_all<I>(&self, items: I) -> usize
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        self.lock().offer_all(items)
+    }
+
+    /// Fallible form of `offer`. A rejected key and value are handed back in the error.
+    pub fn try_offer(&self, key: K, value: V) -> Result<(), OfferError<K, V>> {
+        self.lock().try_offer(key, value)
+    }
+
+    /// Offers the value, merging it into a pending value for the same key, see
+    /// `Sender::offer_with`.
+    pub fn offer_with<F>(&self, key: K, value: V, merge: F) -> bool
+    where
+        F: FnOnce(&V, V) -> V,
+    {
+        self.lock().offer_with(key, value, merge)
+    }
+
+    pub fn offer_value_only(&self, value: V) -> bool {
+        self.lock().offer_value_only(value)
+    }
+
+    /// Fallible form of `offer_value_only`, for use with `?`.
+    pub fn try_offer_value_only(&self, value: V) -> Result<(), Error> {
+        self.lock().try_offer_value_only(value)
+    }
+
+    pub fn size(&self) -> usize {
+        self.lock().size()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.lock().capacity()
+    }
+
+    pub fn rejection_count(&self) -> usize {
+        self.lock().rejection_count()
+    }
+
+    pub fn coalesced_count(&self) -> usize {
+        self.lock().coalesced_count()
+    }
+
+    /// Whether the receiver is gone, after which nothing offered will ever be read.
+    pub fn is_closed(&self) -> bool {
+        self.lock().is_closed()
+    }
+}
+
+/// Creates a ring buffer that several producer threads can offer to: clone the returned
+/// `MpscSender` for each of them. The receive side is the same as for `new_ring_buffer`, and
+/// the receiver sees the sender disconnect once the last clone is dropped.
+///
+/// `let (sender, receiver) = new_mpsc_ring_buffer(64);`
+pub fn new_mpsc_ring_buffer<K: Send + Eq, V: Send>(
+    capacity: usize,
+) -> (MpscSender<K, V>, Receiver<K, V>) {
+    let (sender, receiver) = new_ring_buffer(capacity);
+    let sender = MpscSender {
+        sender: Arc::new(Mutex::new(sender)),
+    };
+    (sender, receiver)
+}
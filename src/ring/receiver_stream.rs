@@ -0,0 +1,68 @@
+use super::spsc_coalescing_ring_buffer::Receiver;
+use futures_core::Stream;
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// A `Stream` of the values offered to a buffer, see `Receiver::into_stream`. It ends once the
+/// sender is gone and everything it offered has been yielded.
+pub struct ReceiverStream<K, V, const N: usize = 0>
+where
+    V: Send,
+{
+    receiver: Receiver<K, V, N>,
+    batch: VecDeque<V>,
+}
+
+// Values are never pinned in place, they are only ever moved out of the batch.
+impl<K, V: Send, const N: usize> Unpin for ReceiverStream<K, V, N> {}
+
+impl<K: Send + Eq, V: Send, const N: usize> Receiver<K, V, N> {
+    /// Turns the receiver into a `Stream`, for consuming from an async task instead of polling
+    /// in a loop. Offers wake the task when it is waiting for data.
+    pub fn into_stream(self) -> ReceiverStream<K, V, N> {
+        ReceiverStream {
+            receiver: self,
+            batch: VecDeque::new(),
+        }
+    }
+}
+
+impl<K: Send + Eq, V: Send, const N: usize> ReceiverStream<K, V, N> {
+    /// Gives the receiver back. Values already taken out of the buffer but not yet yielded
+    /// are returned alongside it.
+    pub fn into_inner(self) -> (Receiver<K, V, N>, Vec<V>) {
+        (self.receiver, self.batch.into())
+    }
+}
+
+impl<K: Send + Eq, V: Send, const N: usize> Stream for ReceiverStream<K, V, N> {
+    type Item = V;
+
+    // Drains everything pending in one go, like `poll_all`, and then hands the batch out one
+    // value at a time.
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<V>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(value) = this.batch.pop_front() {
+                return Poll::Ready(Some(value));
+            }
+            // read the flag first: whatever the sender stored before going away is then seen
+            let disconnected = this.receiver.is_disconnected();
+            this.batch.extend(this.receiver.poll_all());
+            if !this.batch.is_empty() {
+                continue;
+            }
+            if disconnected {
+                return Poll::Ready(None);
+            }
+            if this.receiver.register_waker(cx.waker()) {
+                return Poll::Pending;
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.batch.len(), None)
+    }
+}
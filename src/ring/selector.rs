@@ -0,0 +1,188 @@
+use super::spsc_coalescing_ring_buffer::Receiver;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::cell::Cell;
+use std::sync::{Condvar, Mutex};
+use std::task::{Wake, Waker};
+use std::time::{Duration, Instant};
+
+// What a `Selector` needs of a receiver, whatever its key and value types.
+trait Selectable {
+    fn ready(&self) -> bool;
+
+    // How long until the flush window lets a held back entry through, if it holds one back.
+    fn ready_in(&self) -> Option<Duration>;
+
+    // Leaves `waker` for the next offer, returns `false` if the receiver is ready already.
+    fn wake_with(&self, waker: &Waker) -> bool;
+}
+
+impl<K: Send + Eq, V: Send, const N: usize> Selectable for Receiver<K, V, N> {
+    fn ready(&self) -> bool {
+        self.is_ready()
+    }
+
+    fn ready_in(&self) -> Option<Duration> {
+        self.ready_in()
+    }
+
+    fn wake_with(&self, waker: &Waker) -> bool {
+        self.register_waker(waker)
+    }
+}
+
+// Woken by the producer of any registered receiver, through the waker it was left.
+#[derive(Default)]
+struct Signal {
+    // a wake not waited for yet
+    notified: Mutex<bool>,
+    condvar: Condvar,
+}
+
+impl Wake for Signal {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref()
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        *self.notified.lock().unwrap() = true;
+        self.condvar.notify_all();
+    }
+}
+
+/// Waits on several receivers at once, like `crossbeam_channel::Select`, for a consumer thread
+/// that reads from more than one buffer. The receivers may have different key and value types.
+/// `select` tells which one is ready, and polling it is up to the caller.
+///
+/// The receivers are scanned from just after the last one selected, so that a busy buffer does
+/// not starve the others. A receiver whose sender is gone counts as ready, for its last values
+/// and then for good, so `remove` it once `recv_all` fails.
+///
+/// ```
+/// # use coalescing_buffer::ring::{new_ring_buffer, Selector};
+/// let (prices, price_updates) = new_ring_buffer::<u32, f64>(8);
+/// let (_trades, trade_updates) = new_ring_buffer::<u32, u64>(8);
+/// let mut selector = Selector::new();
+/// let price_index = selector.register(&price_updates);
+/// selector.register(&trade_updates);
+///
+/// prices.offer(1, 101.5);
+/// assert_eq!(price_index, selector.select());
+/// assert_eq!(vec![101.5], price_updates.poll_all());
+/// assert_eq!(None, selector.try_select());
+/// ```
+pub struct Selector<'a> {
+    // `None` once removed, so that the other indices stay put
+    receivers: Vec<Option<&'a dyn Selectable>>,
+    // where the next scan starts
+    next: Cell<usize>,
+    signal: Arc<Signal>,
+    waker: Waker,
+}
+
+impl<'a> Selector<'a> {
+    pub fn new() -> Self {
+        let signal = Arc::new(Signal::default());
+        Selector {
+            receivers: Vec::new(),
+            next: Cell::new(0),
+            waker: Waker::from(signal.clone()),
+            signal,
+        }
+    }
+
+    /// Adds `receiver` and returns the index `select` reports it by.
+    pub fn register<K: Send + Eq, V: Send, const N: usize>(
+        &mut self,
+        receiver: &'a Receiver<K, V, N>,
+    ) -> usize {
+        self.receivers.push(Some(receiver));
+        self.receivers.len() - 1
+    }
+
+    /// Stops selecting the receiver at `index`. The indices of the others do not change.
+    pub fn remove(&mut self, index: usize) {
+        self.receivers[index] = None;
+    }
+
+    /// The index of a receiver that is ready, without waiting.
+    pub fn try_select(&self) -> Option<usize> {
+        let count = self.receivers.len();
+        let start = self.next.get();
+        (0..count)
+            .map(|offset| (start + offset) % count)
+            .find(|&index| self.receivers[index].is_some_and(|receiver| receiver.ready()))
+            .inspect(|&index| self.next.set(index + 1))
+    }
+
+    /// Blocks until one of the receivers is ready and returns its index. Panics when there
+    /// are none to select from, as it would block forever.
+    pub fn select(&self) -> usize {
+        assert!(
+            self.receivers.iter().any(Option::is_some),
+            "no receivers to select from"
+        );
+        self.select_until(None).unwrap()
+    }
+
+    /// Same as `select` but gives up after `timeout`.
+    pub fn select_timeout(&self, timeout: Duration) -> Option<usize> {
+        self.select_until(Some(Instant::now() + timeout))
+    }
+
+    fn select_until(&self, deadline: Option<Instant>) -> Option<usize> {
+        loop {
+            if let Some(index) = self.try_select() {
+                return Some(index);
+            }
+            // An offer made after a receiver was checked wakes the signal, so none is missed.
+            // Nothing wakes it for an entry the flush window holds back, so that bounds the
+            // wait instead.
+            let mut idle = true;
+            let mut ripe_at: Option<Instant> = None;
+            for receiver in self.receivers.iter().flatten() {
+                if receiver.wake_with(&self.waker) {
+                    continue;
+                }
+                match receiver.ready_in() {
+                    Some(ripe_in) => {
+                        let at = Instant::now() + ripe_in;
+                        ripe_at = Some(ripe_at.map_or(at, |earliest| earliest.min(at)));
+                    }
+                    None => idle = false,
+                }
+            }
+            if !idle {
+                continue;
+            }
+            let wake_at = match (deadline, ripe_at) {
+                (Some(deadline), Some(ripe_at)) => Some(deadline.min(ripe_at)),
+                (deadline, ripe_at) => deadline.or(ripe_at),
+            };
+            let notified = self.signal.notified.lock().unwrap();
+            let mut notified = match wake_at {
+                None => self.signal.condvar.wait_while(notified, |n| !*n).unwrap(),
+                Some(wake_at) => {
+                    let now = Instant::now();
+                    if deadline.is_some_and(|deadline| now >= deadline) {
+                        return None;
+                    }
+                    let timeout = wake_at.saturating_duration_since(now);
+                    let (notified, _) = self
+                        .signal
+                        .condvar
+                        .wait_timeout_while(notified, timeout, |n| !*n)
+                        .unwrap();
+                    notified
+                }
+            };
+            *notified = false;
+        }
+    }
+}
+
+impl Default for Selector<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
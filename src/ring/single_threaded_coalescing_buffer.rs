@@ -0,0 +1,197 @@
+use super::spsc_coalescing_ring_buffer::next_power_of_two;
+use alloc::vec::Vec;
+use core::cmp;
+
+/// The coalescing rules of `CoalescingRingBuffer` without any atomics, for one thread that both
+/// offers and polls. It uses the same positions, masking and capacity rounding, so it can serve
+/// as a deterministic reference when testing the concurrent buffers. Neither keys nor values
+/// have to be `Send`, so it also coalesces values holding an `Rc`.
+pub struct SingleThreadedCoalescingBuffer<K, V> {
+    // `None` for keyless entries, which never coalesce
+    keys: Vec<Option<K>>,
+    values: Vec<Option<V>>,
+    next_write: usize,
+    last_read: usize,
+    rejection_count: usize,
+}
+
+/// The name the single-threaded buffer goes by next to `CoalescingRingBuffer`, for code that
+/// coalesces on one thread only.
+pub type LocalCoalescingBuffer<K, V> = SingleThreadedCoalescingBuffer<K, V>;
+
+impl<K: Eq, V> SingleThreadedCoalescingBuffer<K, V> {
+    /// Rounds `capacity` up to a power of two, like `CoalescingRingBuffer::new`.
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "capacity must be greater than zero");
+        let capacity = next_power_of_two(capacity);
+        SingleThreadedCoalescingBuffer {
+            keys: (0..capacity).map(|_| None).collect(),
+            values: (0..capacity).map(|_| None).collect(),
+            next_write: 1,
+            last_read: 0,
+            rejection_count: 0,
+        }
+    }
+
+    /// Replaces the unread value for `key` if there is one, or appends it if there is room.
+    pub fn offer(&mut self, key: K, value: V) -> bool {
+        let pending = (self.last_read + 1..self.next_write)
+            .find(|&position| self.keys[self.mask(position)].as_ref() == Some(&key));
+        match pending {
+            Some(position) => {
+                let index = self.mask(position);
+                self.values[index] = Some(value);
+                true
+            }
+            None => self.store(Some(key), value),
+        }
+    }
+
+    pub fn offer_value_only(&mut self, value: V) -> bool {
+        self.store(None, value)
+    }
+
+    fn store(&mut self, key: Option<K>, value: V) -> bool {
+        if self.size() == self.capacity() {
+            self.rejection_count += 1;
+            return false;
+        }
+        let index = self.mask(self.next_write);
+        self.keys[index] = key;
+        self.values[index] = Some(value);
+        self.next_write += 1;
+        true
+    }
+
+    pub fn poll(&mut self, max_items: usize) -> Vec<V> {
+        let claim_up_to = cmp::min(
+            self.next_write,
+            (self.last_read + 1).saturating_add(max_items),
+        );
+        let mut bucket = Vec::new();
+        for position in self.last_read + 1..claim_up_to {
+            let index = self.mask(position);
+            self.keys[index] = None;
+            bucket.extend(self.values[index].take());
+        }
+        self.last_read = claim_up_to - 1;
+        bucket
+    }
+
+    pub fn poll_all(&mut self) -> Vec<V> {
+        self.poll(usize::MAX)
+    }
+
+    pub fn size(&self) -> usize {
+        self.next_write - self.last_read - 1
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size() == 0
+    }
+
+    pub fn rejection_count(&self) -> usize {
+        self.rejection_count
+    }
+
+    fn mask(&self, position: usize) -> usize {
+        position & (self.capacity() - 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ring::new_ring_buffer;
+    use alloc::rc::Rc;
+
+    // xorshift, so that a failing sequence can be replayed from its seed
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, bound: u64) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0 % bound
+        }
+    }
+
+    #[test]
+    fn should_coalesce_unread_keys_and_keep_order() {
+        let mut buffer = SingleThreadedCoalescingBuffer::new(3);
+        assert_eq!(4, buffer.capacity());
+        assert!(buffer.offer(1, 10));
+        assert!(buffer.offer(2, 20));
+        assert!(buffer.offer(1, 11));
+        assert!(buffer.offer_value_only(30));
+        assert_eq!(vec![11], buffer.poll(1));
+        assert!(buffer.offer(1, 12));
+        assert!(buffer.offer(3, 40));
+        assert!(!buffer.offer(4, 50));
+        assert_eq!(1, buffer.rejection_count());
+        assert_eq!(vec![20, 30, 12, 40], buffer.poll_all());
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn should_coalesce_values_that_are_not_send() {
+        let mut buffer: LocalCoalescingBuffer<_, Rc<str>> = LocalCoalescingBuffer::new(2);
+        let halted: Rc<str> = Rc::from("halted");
+        assert!(buffer.offer("VOD", Rc::from("open")));
+        assert!(buffer.offer("VOD", halted.clone()));
+        assert!(buffer.offer_value_only(Rc::from("heartbeat")));
+        assert_eq!(
+            1,
+            buffer
+                .poll(1)
+                .iter()
+                .filter(|v| Rc::ptr_eq(v, &halted))
+                .count()
+        );
+        assert_eq!(vec![Rc::<str>::from("heartbeat")], buffer.poll_all());
+    }
+
+    #[test]
+    fn should_agree_with_the_concurrent_buffer_on_random_sequences() {
+        for seed in 1..200 {
+            let mut rng = Rng(seed);
+            let capacity = 1 + rng.below(8) as usize;
+            let mut oracle = SingleThreadedCoalescingBuffer::new(capacity);
+            let (sender, receiver) = new_ring_buffer::<u64, u64>(capacity);
+
+            for step in 0..500 {
+                let key = rng.below(6);
+                match rng.below(10) {
+                    0..=5 => assert_eq!(oracle.offer(key, step), sender.offer(key, step)),
+                    6 => assert_eq!(oracle.offer_value_only(step), sender.offer_value_only(step)),
+                    _ => {
+                        let max_items = rng.below(4) as usize;
+                        let polled = oracle.poll(max_items);
+                        assert_eq!(polled, receiver.poll(max_items), "seed {}", seed);
+
+                        // a pending key only ever has one unread entry
+                        let mut keys: Vec<u64> = receiver
+                            .snapshot()
+                            .entries
+                            .iter()
+                            .filter_map(|(key, _)| *key)
+                            .collect();
+                        let pending = keys.len();
+                        keys.sort_unstable();
+                        keys.dedup();
+                        assert_eq!(pending, keys.len(), "seed {}", seed);
+                    }
+                }
+                assert_eq!(oracle.size(), sender.size(), "seed {}", seed);
+                assert!(oracle.size() <= oracle.capacity());
+            }
+            assert_eq!(oracle.rejection_count(), sender.rejection_count());
+            assert_eq!(oracle.poll_all(), receiver.poll_all());
+        }
+    }
+}
@@ -1,23 +1,386 @@
+#[cfg(feature = "std")]
+use super::wait_strategy::WaitStrategy;
+use crate::{Disconnected, Error, OfferError, TryRecvError};
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::cell::{RefCell, UnsafeCell};
+use core::marker::PhantomData;
+use core::ops::Deref;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use core::{cmp, fmt, iter, mem};
 use crossbeam_utils::atomic::AtomicCell;
-use std::cell::UnsafeCell;
-use std::marker::PhantomData;
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::Arc;
-use std::{cmp, mem};
+use crossbeam_utils::{Backoff, CachePadded};
+#[cfg(feature = "smallvec")]
+use smallvec::SmallVec;
+#[cfg(feature = "std")]
+use std::cell::Cell;
+#[cfg(feature = "std")]
+use std::collections::hash_map::RandomState;
+#[cfg(feature = "std")]
+use std::collections::HashMap;
+#[cfg(feature = "std")]
+use std::hash::{BuildHasher, Hash};
+#[cfg(feature = "std")]
+use std::sync::{Condvar, Mutex};
+#[cfg(feature = "std")]
+use std::task::Waker;
+#[cfg(feature = "std")]
+use std::thread;
+#[cfg(feature = "std")]
+use std::time::{Duration, Instant};
 
-struct CoalescingRingBuffer<K, V>
+// Memory ordering. The cursors, `next_write`, `first_write` and `last_read`, stay `SeqCst`:
+// besides publishing slots (a store of `next_write` makes the key and value written before it
+// visible to the consumer that loads it, and `last_read` does the same for freed slots in the
+// other direction) they take part in the store-then-load handshakes with `consumer_waiting`,
+// `producer_waiting`, `reading`/`excluding` and `peeking`/`writing`. A handshake only works
+// if neither side's store can be reordered after its following load, which takes `SeqCst` on
+// both. Everything else is `Relaxed`:
+// - the counters are statistics that order nothing, a reader on another thread gets a
+//   consistent value once it has synchronised with the producer, e.g. by joining it;
+// - `last_cleaned`, `control_slots` and `capacity` are only written by the producer, which
+//   always sees its own writes, and the consumer only ever reports `capacity`;
+// - a latency stamp is written before the `next_write` store that publishes its slot, and a
+//   coalescing restamp may race with the reader, which then reads either stamp.
+/// The buffer shared by a `Sender` and a `Receiver`. On its own it only reports its state;
+/// offering and polling go through the handles returned by `split`. Every value it accepts is
+/// dropped exactly once: when polled, coalesced away, removed, or along with the buffer.
+pub struct CoalescingRingBuffer<K, V, const N: usize = 0>
 where
-    V: Send + Clone,
+    V: Send,
 {
-    next_write: AtomicUsize,
-    last_cleaned: AtomicUsize,
+    // The cursors are padded to a cache line each, so that the producer writing `next_write`
+    // and the consumer writing `last_read` do not keep invalidating each other's line.
+    //
+    // They count every entry ever stored and are compared as plain integers, so they must not
+    // wrap: wrapping arithmetic would not help, as `%` with an exact capacity does not carry
+    // over the boundary. A 64-bit `usize` lasts centuries at a billion offers per second; a
+    // 32-bit one wraps after about 4 billion stored entries, unless `clear` starts over.
+    next_write: CachePadded<AtomicUsize>,
+    last_cleaned: CachePadded<AtomicUsize>,
     rejection_count: AtomicUsize,
-    keys: Vec<KeyCell<KeyHolder<K>>>,
+    value_only_rejection_count: AtomicUsize,
+    overwritten_count: AtomicUsize,
+    coalesced_count: AtomicUsize,
+    high_water_mark: AtomicUsize,
+    expired_count: AtomicUsize,
+    // per `RejectReason`, in declaration order
+    rejections_by_reason: [AtomicUsize; 3],
+    // `RejectReason as u8 + 1`, or 0 before the first rejection
+    last_rejection: AtomicU8,
+    // the `coalesced_count` as of the last `poll_stats`, consumer side only
+    coalesced_seen: AtomicUsize,
+    control_slots: AtomicUsize,
+    slots: UnsafeCell<Slots<K, V, N>>,
+    capacity: AtomicUsize,
+    grow_limit: Option<usize>,
+    reading: AtomicBool,
+    excluding: AtomicBool,
+    peeking: AtomicUsize,
+    writing: AtomicUsize,
+    #[cfg(feature = "std")]
+    consumer_waiting: AtomicBool,
+    // also holds the waker of a consumer waiting asynchronously, see `register_waker`
+    #[cfg(feature = "std")]
+    data_lock: Mutex<Option<Waker>>,
+    #[cfg(feature = "std")]
+    data_available: Condvar,
+    #[cfg(feature = "std")]
+    producer_waiting: AtomicBool,
+    #[cfg(feature = "std")]
+    room_lock: Mutex<()>,
+    #[cfg(feature = "std")]
+    room_available: Condvar,
+    // Replace the flags and condition variables above for the blocking calls, if set. Each
+    // side waits on a strategy of its own, so that one cannot take the other's signal.
+    #[cfg(feature = "std")]
+    data_wait: Option<Box<dyn WaitStrategy>>,
+    #[cfg(feature = "std")]
+    room_wait: Option<Box<dyn WaitStrategy>>,
+    requested_capacity: usize,
+    first_write: CachePadded<AtomicUsize>,
+    last_read: CachePadded<AtomicUsize>,
+    next_auto_key: AtomicU64,
+    // sequence number for the next value written to a slot, producer side only
+    next_seq: AtomicU64,
+    closed: AtomicBool,
+    sender_dropped: AtomicBool,
+    #[cfg(feature = "std")]
+    watchdog: Option<Watchdog>,
+    #[cfg(feature = "std")]
+    latency: Option<LatencyTracker>,
+    #[cfg(feature = "std")]
+    flush_window: Option<FlushWindow>,
+    #[cfg(feature = "std")]
+    expiry: Option<Expiry>,
+    key_index: Option<RefCell<Box<dyn KeyIndex<K>>>>,
+    policy: CoalescePolicy,
+    // Pending positions that `remove` emptied, which `KeepFirst` must fill rather than skip.
+    // Producer side only, like the key index.
+    holes: RefCell<Vec<usize>>,
+    // each value with its sequence number
+    priority: Option<PriorityLane<(u64, V)>>,
+    byte_limit: Option<ByteLimit<V>>,
+}
+
+// Maps pending keys to the position they were last stored at, so that coalescing does not have
+// to scan the ring. Only ever touched from the producer's thread.
+trait KeyIndex<K>: Send {
+    fn get(&self, key: &K) -> Option<usize>;
+    fn insert(&mut self, key: &K, position: usize);
+    // Forgets the key, unless it has been stored again at a later position since.
+    fn remove(&mut self, key: &K, position: usize);
+    fn clear(&mut self);
+}
+
+#[cfg(feature = "std")]
+struct HashKeyIndex<K, S> {
+    positions: HashMap<K, usize, S>,
+}
+
+#[cfg(feature = "std")]
+impl<K: Hash + Eq + Clone + Send, S: BuildHasher + Send> KeyIndex<K> for HashKeyIndex<K, S> {
+    fn get(&self, key: &K) -> Option<usize> {
+        self.positions.get(key).cloned()
+    }
+
+    fn insert(&mut self, key: &K, position: usize) {
+        self.positions.insert(key.clone(), position);
+    }
+
+    fn remove(&mut self, key: &K, position: usize) {
+        if self.positions.get(key) == Some(&position) {
+            self.positions.remove(key);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.positions.clear();
+    }
+}
+
+// Holds entries back from `poll` until they have been in the ring for `window`, see
+// `RingBufferBuilder::flush_window`. The producer stamps the slots it appends to, in
+// `Slots::stored_at`; unlike the latency stamps, coalescing leaves them alone.
+#[cfg(feature = "std")]
+struct FlushWindow {
+    window: Duration,
+    epoch: Instant,
+}
+
+#[cfg(feature = "std")]
+impl FlushWindow {
+    fn tick(&self) -> u64 {
+        self.epoch.elapsed().as_nanos() as u64
+    }
+
+    // How much longer an entry stored at `stored_at` is held back, if at all.
+    fn remaining(&self, stored_at: u64) -> Option<Duration> {
+        let resident = Duration::from_nanos(self.tick().saturating_sub(stored_at));
+        self.window
+            .checked_sub(resident)
+            .filter(|remaining| !remaining.is_zero())
+    }
+}
+
+// Drops entries that have not been written to for `ttl` as the consumer takes them out, see
+// `RingBufferBuilder::ttl`. Goes by the same stamps as latency tracking, which coalescing
+// renews, and shares its epoch when both are on.
+#[cfg(feature = "std")]
+struct Expiry {
+    ttl: Duration,
+    epoch: Instant,
+}
+
+// End to end latency tracking. The producer stamps every slot it writes, in `Slots::stamps`,
+// and the consumer turns the stamps of each polled range into latencies.
+#[cfg(feature = "std")]
+struct LatencyTracker {
+    epoch: Instant,
+    last_poll: RefCell<Vec<Duration>>,
+}
+
+#[cfg(feature = "std")]
+impl LatencyTracker {
+    fn new() -> LatencyTracker {
+        LatencyTracker {
+            epoch: Instant::now(),
+            last_poll: RefCell::new(Vec::new()),
+        }
+    }
+
+    fn tick(&self) -> u64 {
+        self.epoch.elapsed().as_nanos() as u64
+    }
+}
+
+/// What the stall watchdog should do once the consumer has not freed any room for longer than
+/// the configured timeout.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StallAction {
+    /// Keep rejecting offers until the consumer catches up.
+    Wait,
+    /// Drop every unread entry so that offers succeed again. Dropped entries are counted by
+    /// `overwritten_count`.
+    Clear,
+}
+
+/// Which value wins when an offer finds its key still pending and unread.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CoalescePolicy {
+    /// The new value replaces the pending one, so the consumer sees the latest update.
+    #[default]
+    KeepLast,
+    /// The pending value stays and the new one is dropped, for deduplication, or handed back
+    /// by `offer_replace`. The offer still succeeds and counts as coalesced.
+    KeepFirst,
+}
+
+#[cfg(feature = "std")]
+type StallCallback = Box<dyn FnMut(Duration) -> StallAction + Send>;
+
+// Producer side stall detection. Only ever touched from the sender's thread.
+#[cfg(feature = "std")]
+struct Watchdog {
+    timeout: Duration,
+    on_stall: RefCell<StallCallback>,
+    stalled_since: Cell<Option<Instant>>,
+    reported: Cell<bool>,
+}
+
+// The slots of a buffer with a fixed capacity `N`, see `new_fixed`, are kept inline, so that
+// they take no allocation of their own. `N` is 0 for every other buffer, whose slots are on
+// the heap.
+struct SlotArray<T, const N: usize> {
+    inline: [T; N],
+    heap: Vec<T>,
+}
+
+impl<T, const N: usize> SlotArray<T, N> {
+    fn new(capacity: usize, mut slot: impl FnMut() -> T) -> SlotArray<T, N> {
+        let inline = core::array::from_fn(|_| slot());
+        let heap = if N == 0 {
+            (0..capacity).map(|_| slot()).collect()
+        } else {
+            Vec::new()
+        };
+        SlotArray { inline, heap }
+    }
+}
+
+impl<T, const N: usize> Deref for SlotArray<T, N> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        if N == 0 {
+            &self.heap
+        } else {
+            &self.inline
+        }
+    }
+}
+
+// The backing arrays. They are only ever replaced by `grow`, which keeps the consumer out
+// while it does so.
+struct Slots<K, V, const N: usize> {
+    keys: SlotArray<KeyCell<KeyHolder<K>>, N>,
+    values: SlotArray<AtomicCell<Option<V>>, N>,
+    stamps: Vec<AtomicU64>,
+    // when the entry was appended, for the flush window
+    stored_at: Vec<AtomicU64>,
+    // the sequence number of the write that left the value in the slot, see `poll_seq`
+    seqs: SlotArray<AtomicU64, N>,
+    // `None` for capacities that are not a power of two, which have to wrap with `%`
+    mask: Option<usize>,
+}
+
+impl<K, V, const N: usize> Slots<K, V, N> {
+    fn new(capacity: usize, stamped: bool, timed: bool) -> Slots<K, V, N> {
+        debug_assert!(N == 0 || N == capacity);
+        let stamps_if = |enabled: bool| {
+            let count = if enabled { capacity } else { 0 };
+            (0..count).map(|_| AtomicU64::new(0)).collect()
+        };
+
+        Slots {
+            keys: SlotArray::new(capacity, || KeyCell::new(KeyHolder::Empty)),
+            values: SlotArray::new(capacity, || AtomicCell::new(None)),
+            stamps: stamps_if(stamped),
+            stored_at: stamps_if(timed),
+            seqs: SlotArray::new(capacity, || AtomicU64::new(0)),
+            mask: Some(capacity - 1).filter(|_| capacity.is_power_of_two()),
+        }
+    }
+
+    fn index(&self, position: usize) -> usize {
+        match self.mask {
+            Some(mask) => position & mask,
+            None => position % self.keys.len(),
+        }
+    }
+}
+
+// Keyless values that polls take ahead of anything in the ring, see `offer_priority`. A plain
+// SPSC queue: only the producer moves `tail` and only the consumer moves `head`, each after
+// filling or emptying the slot it points at.
+struct PriorityLane<V> {
     values: Vec<AtomicCell<Option<V>>>,
-    mask: usize,
-    capacity: usize,
-    first_write: AtomicUsize,
-    last_read: AtomicUsize,
+    head: AtomicUsize,
+    tail: AtomicUsize,
+}
+
+impl<V> PriorityLane<V> {
+    fn new(capacity: usize) -> PriorityLane<V> {
+        PriorityLane {
+            values: (0..capacity).map(|_| AtomicCell::new(None)).collect(),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    fn push(&self, value: V) -> Result<(), V> {
+        let tail = self.tail.load(Ordering::SeqCst);
+        if tail - self.head.load(Ordering::SeqCst) == self.values.len() {
+            return Err(value);
+        }
+        self.values[tail % self.values.len()].store(Some(value));
+        self.tail.store(tail + 1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn pop(&self) -> Option<V> {
+        let head = self.head.load(Ordering::SeqCst);
+        if head == self.tail.load(Ordering::SeqCst) {
+            return None;
+        }
+        let value = self.values[head % self.values.len()].take();
+        self.head.store(head + 1, Ordering::SeqCst);
+        value
+    }
+
+    fn is_empty(&self) -> bool {
+        self.head.load(Ordering::SeqCst) == self.tail.load(Ordering::SeqCst)
+    }
+}
+
+// The budget of `RingBufferBuilder::with_byte_capacity`. `bytes` is the weight of the values
+// in the ring: added before a value is written to a slot and taken off once it is out again,
+// so it never drops below what is really there.
+struct ByteLimit<V> {
+    max_bytes: usize,
+    weigh: Box<dyn Fn(&V) -> usize + Send + Sync>,
+    bytes: AtomicUsize,
+}
+
+// Keeps a flag raised until dropped.
+struct Raised<'a>(&'a AtomicBool);
+
+impl Drop for Raised<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
 }
 
 #[derive(Debug)]
@@ -32,10 +395,14 @@ impl<T> KeyCell<T> {
         }
     }
     pub fn set(&self, val: T) {
-        let old = mem::replace(unsafe { &mut *self.value.get() }, val);
+        let old = self.replace(val);
         drop(old);
     }
 
+    pub fn replace(&self, val: T) -> T {
+        mem::replace(unsafe { &mut *self.value.get() }, val)
+    }
+
     pub fn get(&self) -> &T {
         unsafe { &*self.value.get() }
     }
@@ -48,273 +415,3340 @@ enum KeyHolder<T> {
     NonCollapsible,
 }
 
-fn next_power_of_two(capacity: usize) -> usize {
-    let mut v = capacity;
-    v = v - 1;
-    v = v | (v >> 1);
-    v = v | (v >> 2);
-    v = v | (v >> 4);
-    v = v | (v >> 8);
-    v = v | (v >> 16);
-    v = v + 1;
-    return v;
+impl<T> KeyHolder<T> {
+    fn into_key(self) -> Option<T> {
+        match self {
+            KeyHolder::NonEmpty(key) => Some(key),
+            _ => None,
+        }
+    }
+}
+
+// Fails to compile when monomorphised for an `N` that is not a power of two.
+struct PowerOfTwo<const N: usize>;
+
+impl<const N: usize> PowerOfTwo<N> {
+    const CHECK: () = assert!(N.is_power_of_two(), "capacity must be a power of two");
+}
+
+// How often `size` retries for a consistent read before settling for a best effort one. The
+// consumer would have to release a poll between two loads every single time to exhaust it.
+const SIZE_RETRIES: usize = 1024;
+
+// Left in `seqs` by a poll that dropped the slot's value for having expired.
+const EXPIRED_SEQ: u64 = u64::MAX;
+
+// Rounds up for any width of `usize`; the shifts this used to do stopped at 32 bits.
+pub(super) fn next_power_of_two(capacity: usize) -> usize {
+    capacity.next_power_of_two()
 }
 
 #[allow(unused)]
 impl<K, V> CoalescingRingBuffer<K, V>
 where
     K: Eq + Send,
-    V: Send + Clone,
+    V: Send,
 {
+    /// Rounds `capacity` up to a power of two. A capacity of 1 is valid and holds a single
+    /// entry. Panics where `try_new` would fail.
     pub fn new(capacity: usize) -> CoalescingRingBuffer<K, V> {
-        let size = next_power_of_two(capacity);
-
-        let mut keys: Vec<KeyCell<KeyHolder<K>>> = Vec::with_capacity(size);
-        let mut values: Vec<AtomicCell<Option<V>>> = Vec::with_capacity(size);
+        match Self::try_new(capacity) {
+            Ok(buffer) => buffer,
+            Err(error) => panic!("{}", error),
+        }
+    }
 
-        for _ in 0..size {
-            keys.push(KeyCell::new(KeyHolder::Empty));
-            values.push(AtomicCell::new(None));
+    /// Same as `new` but fails with `InvalidCapacity` for a zero capacity, or one too large
+    /// to round up to a power of two.
+    pub fn try_new(capacity: usize) -> Result<CoalescingRingBuffer<K, V>, Error> {
+        match capacity.checked_next_power_of_two() {
+            Some(size) if capacity > 0 => Ok(Self::with_slots(capacity, size)),
+            _ => Err(Error::InvalidCapacity(capacity)),
         }
+    }
+
+    /// Same as `new` but allocates exactly `capacity` slots instead of rounding up to a power
+    /// of two. Slots are then found with `%` rather than a mask, which costs a little
+    /// throughput on every offer and poll.
+    pub fn with_exact_capacity(capacity: usize) -> CoalescingRingBuffer<K, V> {
+        assert!(capacity > 0, "capacity must be greater than zero");
+        Self::with_slots(capacity, capacity)
+    }
+}
+
+#[allow(unused)]
+impl<K, V, const N: usize> CoalescingRingBuffer<K, V, N>
+where
+    K: Eq + Send,
+    V: Send,
+{
+    /// Same as `new(N)`, with the capacity checked at compile time: `N` has to be a power of
+    /// two, so it is used as is. The slots are kept inline rather than allocated on their own,
+    /// and the buffer can not grow. They are built on the stack before moving to the heap
+    /// with the handles, so keep `N` small.
+    ///
+    /// ```
+    /// use coalescing_buffer::ring::CoalescingRingBuffer;
+    ///
+    /// let buffer = CoalescingRingBuffer::<i32, i32, 16>::new_fixed();
+    /// assert_eq!(16, buffer.capacity());
+    /// ```
+    pub fn new_fixed() -> CoalescingRingBuffer<K, V, N> {
+        #[allow(clippy::let_unit_value)]
+        let () = PowerOfTwo::<N>::CHECK;
+        Self::with_slots(N, N)
+    }
 
+    fn with_slots(capacity: usize, size: usize) -> CoalescingRingBuffer<K, V, N> {
         CoalescingRingBuffer {
-            next_write: AtomicUsize::new(1),
-            last_cleaned: AtomicUsize::new(0),
+            next_write: CachePadded::new(AtomicUsize::new(1)),
+            last_cleaned: CachePadded::new(AtomicUsize::new(0)),
             rejection_count: AtomicUsize::new(0),
-            first_write: AtomicUsize::new(1),
-            last_read: AtomicUsize::new(0),
-            capacity: size,
-            mask: size - 1,
-            keys,
-            values,
+            value_only_rejection_count: AtomicUsize::new(0),
+            overwritten_count: AtomicUsize::new(0),
+            coalesced_count: AtomicUsize::new(0),
+            high_water_mark: AtomicUsize::new(0),
+            expired_count: AtomicUsize::new(0),
+            rejections_by_reason: Default::default(),
+            last_rejection: AtomicU8::new(0),
+            coalesced_seen: AtomicUsize::new(0),
+            control_slots: AtomicUsize::new(0),
+            first_write: CachePadded::new(AtomicUsize::new(1)),
+            last_read: CachePadded::new(AtomicUsize::new(0)),
+            next_auto_key: AtomicU64::new(0),
+            next_seq: AtomicU64::new(0),
+            closed: AtomicBool::new(false),
+            sender_dropped: AtomicBool::new(false),
+            #[cfg(feature = "std")]
+            watchdog: None,
+            #[cfg(feature = "std")]
+            latency: None,
+            #[cfg(feature = "std")]
+            flush_window: None,
+            #[cfg(feature = "std")]
+            expiry: None,
+            key_index: None,
+            policy: CoalescePolicy::KeepLast,
+            holes: RefCell::new(Vec::new()),
+            priority: None,
+            byte_limit: None,
+            slots: UnsafeCell::new(Slots::new(size, false, false)),
+            capacity: AtomicUsize::new(size),
+            grow_limit: None,
+            reading: AtomicBool::new(false),
+            excluding: AtomicBool::new(false),
+            peeking: AtomicUsize::new(0),
+            writing: AtomicUsize::new(0),
+            #[cfg(feature = "std")]
+            consumer_waiting: AtomicBool::new(false),
+            #[cfg(feature = "std")]
+            data_lock: Mutex::new(None),
+            #[cfg(feature = "std")]
+            data_available: Condvar::new(),
+            #[cfg(feature = "std")]
+            producer_waiting: AtomicBool::new(false),
+            #[cfg(feature = "std")]
+            room_lock: Mutex::new(()),
+            #[cfg(feature = "std")]
+            room_available: Condvar::new(),
+            #[cfg(feature = "std")]
+            data_wait: None,
+            #[cfg(feature = "std")]
+            room_wait: None,
+            requested_capacity: capacity,
         }
     }
 
+    /// Hands the buffer over to a producer and a consumer handle.
+    ///
+    /// ```
+    /// use coalescing_buffer::ring::CoalescingRingBuffer;
+    ///
+    /// let buffer: CoalescingRingBuffer<i32, i32> = CoalescingRingBuffer::new(25);
+    /// assert_eq!(32, buffer.capacity());
+    ///
+    /// let (sender, receiver) = buffer.split();
+    /// sender.offer(1, 10);
+    /// assert_eq!(vec![10], receiver.poll_all());
+    /// ```
+    pub fn split(self) -> Handles<K, V, N> {
+        let buf = Arc::new(self);
+        let buf_clone = buf.clone();
+        (Sender::new(buf), Receiver::new(buf_clone))
+    }
+
+    /// Everything still pending, priority values first and the rest in offer order, for a
+    /// teardown that wants the values back rather than dropped with the buffer. The handles
+    /// have `Receiver::into_remaining` for the same.
+    pub fn into_remaining(self) -> Vec<V> {
+        self.poll_all()
+    }
+
+    /// The number of pending entries. Retries up to `SIZE_RETRIES` times for a consistent read
+    /// of both cursors, and then settles for one that may be slightly off.
     pub fn size(&self) -> usize {
+        self.try_size(SIZE_RETRIES).unwrap_or_else(|| {
+            // `next_write` is read second, so it is never behind the `last_read` it is paired
+            // with, but it can be ahead of a stale one by more than the capacity
+            let last_read = self.last_read.load(Ordering::SeqCst);
+            let next_write = self.next_write.load(Ordering::SeqCst);
+            cmp::min(next_write - last_read - 1, self.capacity())
+        })
+    }
+
+    /// Same as `size`, but gives up with `None` when `max_retries` attempts all saw the
+    /// consumer move `last_read` in the middle of the read.
+    pub fn try_size(&self, max_retries: usize) -> Option<usize> {
         // loop until you get a consistent read of both volatile indices
-        loop {
+        for _ in 0..max_retries {
             let last_read_before = self.last_read.load(Ordering::SeqCst);
             let current_next_write = self.next_write.load(Ordering::SeqCst);
             let last_read_after = self.last_read.load(Ordering::SeqCst);
 
             if last_read_before == last_read_after {
-                return (current_next_write - last_read_before) - 1;
+                return Some((current_next_write - last_read_before) - 1);
+            }
+        }
+        None
+    }
+
+    /// Returns `(last_read, first_write, next_write)` as one consistent view, using the same
+    /// retry loop as `size`.
+    pub(crate) fn cursors(&self) -> (usize, usize, usize) {
+        loop {
+            let last_read_before = self.last_read.load(Ordering::SeqCst);
+            let first_write = self.first_write.load(Ordering::SeqCst);
+            let next_write = self.next_write.load(Ordering::SeqCst);
+            let last_read_after = self.last_read.load(Ordering::SeqCst);
+            let first_write_after = self.first_write.load(Ordering::SeqCst);
+
+            if last_read_before == last_read_after && first_write == first_write_after {
+                return (last_read_before, first_write, next_write);
             }
         }
     }
 
     pub fn capacity(&self) -> usize {
-        return self.capacity;
+        self.capacity.load(Ordering::Relaxed)
+    }
+
+    /// Roughly how much memory the buffer takes: the struct and its slot arrays, including
+    /// the per-slot stamps and sequence numbers, and the priority lane. Values are stored in
+    /// their slots, so a `Box` or `Arc` counts as a pointer; what it points to is left out, as
+    /// is the key index of `RingBufferBuilder::with_hash_index`.
+    pub fn capacity_in_bytes(&self) -> usize {
+        let mut per_slot = mem::size_of::<KeyCell<KeyHolder<K>>>()
+            + mem::size_of::<AtomicCell<Option<V>>>()
+            + mem::size_of::<AtomicU64>();
+        #[cfg(feature = "std")]
+        {
+            let stamped = self.latency.is_some() || self.expiry.is_some();
+            let stamps = stamped as usize + self.flush_window.is_some() as usize;
+            per_slot += stamps * mem::size_of::<AtomicU64>();
+        }
+        let priority = self.priority.as_ref().map_or(0, |lane| {
+            lane.values.len() * mem::size_of::<AtomicCell<Option<(u64, V)>>>()
+        });
+        // inline slots are part of the struct already
+        let heap_slots = if N == 0 { self.capacity() } else { 0 };
+        mem::size_of::<Self>() + heap_slots * per_slot + priority
+    }
+
+    /// The capacity asked for at construction, before rounding up to a power of two.
+    pub fn requested_capacity(&self) -> usize {
+        self.requested_capacity
     }
 
     pub fn rejection_count(&self) -> usize {
-        return self.rejection_count.load(Ordering::SeqCst);
+        self.rejection_count.load(Ordering::Relaxed)
+    }
+
+    /// The part of `rejection_count` caused by keyless offers.
+    pub fn value_only_rejection_count(&self) -> usize {
+        self.value_only_rejection_count.load(Ordering::Relaxed)
+    }
+
+    /// Why the most recent offer was rejected, or `None` if none was since the last `clear`.
+    pub fn last_rejection(&self) -> Option<RejectReason> {
+        match self.last_rejection.load(Ordering::Relaxed) {
+            0 => None,
+            1 => Some(RejectReason::Full),
+            2 => Some(RejectReason::Closed),
+            _ => Some(RejectReason::ByteBudget),
+        }
     }
 
-    pub fn next_write(&self) -> usize {
-        return self.next_write.load(Ordering::SeqCst);
+    fn rejections(&self, reason: RejectReason) -> usize {
+        self.rejections_by_reason[reason as usize].load(Ordering::Relaxed)
     }
 
-    pub fn first_write(&self) -> usize {
-        return self.first_write.load(Ordering::SeqCst);
+    pub(crate) fn next_write(&self) -> usize {
+        self.next_write.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn first_write(&self) -> usize {
+        self.first_write.load(Ordering::SeqCst)
     }
 
     pub fn is_empty(&self) -> bool {
-        return self.first_write.load(Ordering::SeqCst) == self.next_write.load(Ordering::SeqCst);
+        self.first_write.load(Ordering::SeqCst) == self.next_write.load(Ordering::SeqCst)
+            && self.priority.as_ref().is_none_or(PriorityLane::is_empty)
     }
 
     pub fn is_full(&self) -> bool {
-        return self.size() == self.capacity;
+        self.pending() == self.capacity()
     }
 
-    pub fn offer(&self, key: K, value: V) -> bool {
-        use std::borrow::Cow;
+    // Whether a normal offer may take a slot, i.e. the buffer is not full once the slots held
+    // back for control messages are taken into account.
+    fn has_room(&self) -> bool {
+        self.pending() + self.control_slots.load(Ordering::Relaxed) < self.capacity()
+    }
 
-        let next_write = self.next_write.load(Ordering::SeqCst);
-        let key_type = KeyHolder::NonEmpty(key);
-        for update_pos in self.first_write.load(Ordering::SeqCst)..next_write {
-            let index = self.mask(update_pos);
-            if &key_type == self.keys[index].get() {
-                let old_ptr = self.values[index].swap(Some(value.clone()));
-                if update_pos >= self.first_write.load(Ordering::SeqCst) {
-                    return true;
-                } else {
-                    //self.values[index].compare_and_swap(old_ptr, val_ptr);
-                    break;
+    // `size` without its retry loop, for the producer's and the consumer's own checks. Each of
+    // them owns one of the two cursors, which cannot move while the other one is read, so a
+    // single load of each is consistent.
+    fn pending(&self) -> usize {
+        self.next_write.load(Ordering::SeqCst) - self.last_read.load(Ordering::SeqCst) - 1
+    }
+
+    /// Holds `n` slots back from every offer except `offer_control`, so that a control message
+    /// such as a shutdown sentinel can always be delivered.
+    pub(crate) fn reserve_control_slots(&self, n: usize) {
+        assert!(
+            n < self.capacity(),
+            "cannot reserve {} control slots in a buffer of capacity {}",
+            n,
+            self.capacity()
+        );
+        self.control_slots.store(n, Ordering::Relaxed);
+    }
+
+    pub fn control_slots(&self) -> usize {
+        self.control_slots.load(Ordering::Relaxed)
+    }
+
+    /// Marks the buffer as no longer read from. Every offer fails with `Closed` from then on.
+    pub(crate) fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        self.notify_producer();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn offer(&self, key: K, value: V) -> bool {
+        self.try_offer(key, value).is_ok()
+    }
+
+    /// Offers the items in order and returns how many were accepted, stopping at the first
+    /// rejection. Items coalesce with each other like separate offers do.
+    pub(crate) fn offer_all<I>(&self, items: I) -> usize
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let mut accepted = 0;
+        for (key, value) in items {
+            if !self.offer(key, value) {
+                break;
+            }
+            accepted += 1;
+        }
+        accepted
+    }
+
+    /// Offers all of `items`, or none of them if the free slots cannot take every item that
+    /// would be appended rather than coalesced. With `RingBufferBuilder::grow_when_full` the
+    /// buffer first grows, within its limit, to make room for the batch. The consumer is kept
+    /// out from the check until the last item is in, as a key it claimed in between would need
+    /// a slot of its own, so this waits for a poll in progress to finish. A byte budget is
+    /// checked as though nothing coalesced. A rejected batch counts once in `rejection_count`.
+    pub(crate) fn offer_batch_atomic(&self, items: &[(K, V)]) -> bool
+    where
+        K: Clone,
+        V: Clone,
+    {
+        if self.reject_closed() {
+            return false;
+        }
+        let mut grown = false;
+        loop {
+            // no slot is claimed, nor freed, until the batch is in
+            let excluded = self.exclude_consumer();
+            let appended = (0..items.len())
+                .filter(|&i| {
+                    let key = &items[i].0;
+                    // an earlier item for the same key is appended in its stead
+                    !self.contains_key(key) && !items[..i].iter().any(|(earlier, _)| earlier == key)
+                })
+                .count();
+            let reserved = self.pending() + self.control_slots.load(Ordering::Relaxed);
+            let room = self.capacity().saturating_sub(reserved);
+            if appended > room {
+                // `grow` keeps the consumer out itself, which does not nest
+                drop(excluded);
+                if self.grow_limit.is_some() && !grown {
+                    grown = true;
+                    // fails if a poll made the room in the meantime, which the check sees
+                    let _ = self.grow(reserved + appended);
+                    continue;
                 }
+                self.reject(RejectReason::Full, false);
+                return false;
+            }
+            let bytes_fit = self.byte_limit.as_ref().is_none_or(|limit| {
+                let batch: usize = items.iter().map(|(_, value)| (limit.weigh)(value)).sum();
+                limit.bytes.load(Ordering::Relaxed).saturating_add(batch) <= limit.max_bytes
+            });
+            if !bytes_fit {
+                self.reject(RejectReason::ByteBudget, false);
+                return false;
             }
+            for (key, value) in items {
+                let accepted = self.offer(key.clone(), value.clone());
+                debug_assert!(accepted, "the batch was checked to fit");
+            }
+            return true;
         }
-        return self.add(key_type, value);
     }
 
-    pub fn offer_value_only(&self, value: V) -> bool {
-        return self.add(KeyHolder::NonCollapsible, value);
+    pub(crate) fn try_offer(&self, key: K, value: V) -> Result<(), OfferError<K, V>> {
+        self.offer_at(key, value).map(|_| ())
     }
 
-    fn add(&self, key: KeyHolder<K>, value: V) -> bool {
-        if self.is_full() {
-            self.rejection_count.fetch_add(1, Ordering::SeqCst);
-            return false;
+    /// Like `try_offer`, but parks the producer while the buffer is full until the consumer
+    /// frees a slot. An offer that coalesces never waits. Fails only once the buffer is closed.
+    #[cfg(feature = "std")]
+    pub(crate) fn offer_blocking(&self, key: K, value: V) -> Result<(), OfferError<K, V>> {
+        let mut key_type = KeyHolder::NonEmpty(key);
+        let mut value = value;
+        loop {
+            if self.find_pending(&key_type).is_none() && !self.has_room() && !self.is_closed() {
+                self.wait_for_room();
+                continue;
+            }
+            // the pending entry may still be read before the offer gets to it, and leave no room
+            match self.try_offer(key_type.into_key().unwrap(), value) {
+                Err(OfferError::Full {
+                    key,
+                    value: rejected,
+                }) => {
+                    key_type = KeyHolder::NonEmpty(key);
+                    value = rejected;
+                }
+                result => return result,
+            }
         }
-        self.clean_up();
-        self.store(key, value);
-        return true;
     }
 
-    pub fn clean_up(&self) {
-        let last_read = self.last_read.load(Ordering::SeqCst);
-
-        let last_cln = self.last_cleaned.load(Ordering::Relaxed);
-        if last_read == last_cln {
+    // Parks the producer until the consumer releases a slot or goes away. Mirrors
+    // `wait_for_data`, with the producer and consumer swapped.
+    #[cfg(feature = "std")]
+    fn wait_for_room(&self) {
+        if let Some(strategy) = &self.room_wait {
+            if !self.has_room() && !self.is_closed() {
+                strategy.wait();
+            }
             return;
         }
-
-        for x in last_cln..last_read {
-            let index = self.mask(x + 1);
-            self.keys[index].set(KeyHolder::Empty);
-            let old_val = self.values[index].swap(None);
+        let lock = self.room_lock.lock().unwrap();
+        self.producer_waiting.store(true, Ordering::SeqCst);
+        if !self.has_room() && !self.is_closed() {
+            drop(self.room_available.wait(lock).unwrap());
         }
-        self.last_cleaned.store(last_read, Ordering::SeqCst);
+        self.producer_waiting.store(false, Ordering::SeqCst);
     }
 
-    fn store(&self, key: KeyHolder<K>, value: V) {
-        let next_write = self.next_write.load(Ordering::SeqCst);
-        let index = self.mask(next_write);
-        self.keys[index].set(key);
-        let old_ptr = self.values[index].swap(Some(value));
-        self.next_write.store(next_write + 1, Ordering::SeqCst);
+    /// Offers the value and then waits, for at most `timeout`, until the consumer has read the
+    /// slot it ended up in. Returns `true` only if that happened in time.
+    ///
+    /// A value can only be coalesced away by a later offer for the same key, and no such offer
+    /// can be made while this call is blocking the producer, so `true` means this exact value
+    /// was delivered. Returns `false` straight away if the buffer is full, or if the key was
+    /// pending and `CoalescePolicy::KeepFirst` dropped the value. With a `RingBufferBuilder::ttl`
+    /// a value that expires unread is dropped by the consumer, and that returns `false` too.
+    #[cfg(feature = "std")]
+    pub(crate) fn offer_and_wait_read(&self, key: K, value: V, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let position = match self.offer_replacing(key, value) {
+            // only `KeepFirst` hands back the value offered rather than the one it replaced
+            Ok((_, Some(_))) if self.policy == CoalescePolicy::KeepFirst => return false,
+            Ok((position, _)) => position,
+            Err(_) => return false,
+        };
+        let backoff = Backoff::new();
+        while self.last_read.load(Ordering::SeqCst) < position {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            backoff.snooze();
+        }
+        // the slot is not written again while the producer is stuck here
+        self.slots().seqs[self.mask(position)].load(Ordering::Relaxed) != EXPIRED_SEQ
     }
 
-    pub fn poll_all(&self) -> Vec<V> {
-        let total_to_poll = self.next_write.load(Ordering::SeqCst);
-        return self.fill(total_to_poll);
+    /// Like `offer`, but when the buffer is full and the key is not pending the oldest unread
+    /// entry is dropped to make room, so the freshest data always gets in. Every dropped entry
+    /// is counted by `overwritten_count`. Only fails once the buffer is closed.
+    pub(crate) fn offer_overwrite_oldest(&self, key: K, value: V) -> bool {
+        if self.reject_closed() {
+            return false;
+        }
+        let key_type = KeyHolder::NonEmpty(key);
+        if let Err(value) = self.coalesce(&key_type, value) {
+            self.overwrite_oldest(key_type, value);
+        }
+        true
     }
 
-    pub fn poll(&self, max_items: usize) -> Vec<V> {
-        let claim_up_to = cmp::min(
-            self.first_write.load(Ordering::SeqCst) + max_items,
-            self.next_write.load(Ordering::SeqCst),
-        );
-        return self.fill(claim_up_to);
+    /// Keyless counterpart of `offer_overwrite_oldest`: drops the oldest unread entry instead
+    /// of rejecting when there is no room.
+    pub(crate) fn offer_value_only_overwrite_oldest(&self, value: V) -> bool {
+        if self.reject_closed() {
+            return false;
+        }
+        self.overwrite_oldest(KeyHolder::NonCollapsible, value);
+        true
     }
 
-    fn fill(&self, claim_up_to: usize) -> Vec<V> {
-        self.first_write.store(claim_up_to, Ordering::SeqCst);
-        let last_read = self.last_read.load(Ordering::SeqCst);
-
-        let mut bucket: Vec<V> = Vec::new();
-        for read_index in last_read + 1..claim_up_to {
-            let index = self.mask(read_index);
-            let val = self.values[index].swap(None);
-            if val.is_none() {
-                //println!("{:?}", self);
-                //println!("claim_up_to:{:?}", claim_up_to);
-                panic!("Null pointer is not expected here!")
+    fn overwrite_oldest(&self, key: KeyHolder<K>, value: V) -> usize {
+        let backoff = Backoff::new();
+        while !self.has_room() {
+            if self.evict_oldest() {
+                self.overwritten_count.fetch_add(1, Ordering::Relaxed);
             } else {
-                bucket.push(val.unwrap());
+                // the consumer is reading and is about to free some space
+                backoff.snooze();
             }
         }
-        self.last_read.store(claim_up_to - 1, Ordering::SeqCst);
-        return bucket;
+        self.clean_up();
+        self.store(key, value)
     }
 
-    fn mask(&self, value: usize) -> usize {
-        return value & self.mask;
+    pub fn overwritten_count(&self) -> usize {
+        self.overwritten_count.load(Ordering::Relaxed)
     }
-}
-
-unsafe impl<K, V> Send for CoalescingRingBuffer<K, V> where V: Send + Clone {}
-unsafe impl<K, V> Sync for CoalescingRingBuffer<K, V> where V: Send + Clone {}
 
-pub struct Receiver<K, V>
-where
-    V: Send + Clone,
-{
-    buffer: Arc<CoalescingRingBuffer<K, V>>,
-    _phantom_data: PhantomData<*mut ()>, //This to make sure we have only one thread access this
-}
+    /// Offers that replaced or merged into a pending value instead of taking a slot.
+    pub fn coalesced_count(&self) -> usize {
+        self.coalesced_count.load(Ordering::Relaxed)
+    }
 
-unsafe impl<K: Send, V: Send + Clone> Send for Receiver<K, V> where V: Send {}
+    /// The statistics in one go. The figures are read one after another, so while offers
+    /// and polls go on they need not add up exactly.
+    pub fn metrics(&self) -> BufferMetrics {
+        BufferMetrics {
+            capacity: self.capacity(),
+            current_size: self.size(),
+            rejection_count: self.rejection_count(),
+            full_rejections: self.rejections(RejectReason::Full),
+            closed_rejections: self.rejections(RejectReason::Closed),
+            byte_budget_rejections: self.rejections(RejectReason::ByteBudget),
+            coalesced_count: self.coalesced_count(),
+            high_water_mark: self.high_water_mark.load(Ordering::Relaxed),
+        }
+    }
 
-impl<K: Send + Eq, V: Send + Clone> Receiver<K, V> {
-    fn new(buf: Arc<CoalescingRingBuffer<K, V>>) -> Self {
-        Receiver {
-            buffer: buf,
-            _phantom_data: PhantomData,
+    // Takes the oldest unread slot away from the consumer. This is only attempted while the
+    // consumer is idle (`first_write == last_read + 1`), so that once the slot is claimed
+    // `last_read` can be moved over it straight away and the capacity is really freed.
+    fn evict_oldest(&self) -> bool {
+        let last_read = self.last_read.load(Ordering::SeqCst);
+        let oldest = last_read + 1;
+        if oldest == self.next_write.load(Ordering::SeqCst) {
+            // the consumer has caught up in the meantime, nothing to evict
+            return false;
         }
+        if self
+            .first_write
+            .compare_exchange(oldest, oldest + 1, Ordering::SeqCst, Ordering::SeqCst)
+            .is_err()
+        {
+            return false;
+        }
+        let evicted = self.replace_value(oldest, None);
+        self.last_read.fetch_max(oldest, Ordering::SeqCst);
+        drop(evicted);
+        true
     }
 
-    pub fn poll_all(&self) -> Vec<V> {
-        return self.buffer.poll_all();
+    // Swaps the value of a pending slot, which the consumer may be peeking at. Pairs with the
+    // handshake in `peek`: each side announces the position it is about to touch and then
+    // backs off if the other one has announced the same position.
+    fn replace_value(&self, position: usize, value: Option<V>) -> Option<V> {
+        let backoff = Backoff::new();
+        loop {
+            self.writing.store(position, Ordering::SeqCst);
+            if self.peeking.load(Ordering::SeqCst) != position {
+                break;
+            }
+            self.writing.store(0, Ordering::SeqCst);
+            while self.peeking.load(Ordering::SeqCst) == position {
+                backoff.snooze();
+            }
+        }
+        let index = self.mask(position);
+        if let Some(value) = &value {
+            // within the handshake, so `poll_seq` gets the value and its number together
+            self.slots().seqs[index].store(self.take_seq(), Ordering::Relaxed);
+            self.weigh_in(value);
+        }
+        let old = self.slots().values[index].swap(value);
+        self.writing.store(0, Ordering::SeqCst);
+        if let Some(old) = &old {
+            self.weigh_out(old);
+        }
+        old
     }
 
-    pub fn poll(&self, max_items: usize) -> Vec<V> {
-        return self.buffer.poll(max_items);
+    fn weigh_in(&self, value: &V) {
+        if let Some(limit) = &self.byte_limit {
+            limit
+                .bytes
+                .fetch_add((limit.weigh)(value), Ordering::Relaxed);
+        }
     }
 
-    pub fn size(&self) -> usize {
-        self.buffer.size()
+    fn weigh_out(&self, value: &V) {
+        if let Some(limit) = &self.byte_limit {
+            limit
+                .bytes
+                .fetch_sub((limit.weigh)(value), Ordering::Relaxed);
+        }
     }
-}
 
-pub struct Sender<K, V>
-where
-    V: Send + Clone,
-{
-    buffer: Arc<CoalescingRingBuffer<K, V>>,
-    _phantom_data: PhantomData<*mut ()>, //This to make sure we have only one thread access this
-}
+    // Whether appending `value` keeps the byte total within the budget, if there is one.
+    fn fits(&self, value: &V) -> bool {
+        self.byte_limit.as_ref().is_none_or(|limit| {
+            let bytes = limit.bytes.load(Ordering::Relaxed);
+            bytes.saturating_add((limit.weigh)(value)) <= limit.max_bytes
+        })
+    }
 
-unsafe impl<K: Send, V: Send + Clone> Send for Sender<K, V> where V: Send {}
+    /// The total weight of the values in the ring, as given by the function passed to
+    /// `RingBufferBuilder::with_byte_capacity`. Always 0 without a byte budget.
+    pub fn byte_size(&self) -> usize {
+        self.byte_limit
+            .as_ref()
+            .map_or(0, |limit| limit.bytes.load(Ordering::Relaxed))
+    }
 
-impl<K: Send + Eq, V: Send + Clone> Sender<K, V>
-where
-    V: Send + Clone,
-{
-    fn new(buf: Arc<CoalescingRingBuffer<K, V>>) -> Self {
-        Sender {
-            buffer: buf,
-            _phantom_data: PhantomData,
+    // Takes the value out of a claimed slot, consumer side. A value past its time to live is
+    // dropped instead, leaving the slot to pass for a hole.
+    fn take_value(&self, index: usize) -> Option<V> {
+        let value = self.slots().values[index].swap(None)?;
+        self.weigh_out(&value);
+        if self.expired(index) {
+            self.expired_count.fetch_add(1, Ordering::Relaxed);
+            // for `offer_and_wait_read`, until the slot is written again
+            self.slots().seqs[index].store(EXPIRED_SEQ, Ordering::Relaxed);
+            return None;
         }
+        Some(value)
     }
 
-    pub fn offer(&self, key: K, value: V) -> bool {
-        return self.buffer.offer(key, value);
+    #[cfg(not(feature = "std"))]
+    fn expired(&self, _index: usize) -> bool {
+        false
     }
 
-    pub fn offer_value_only(&self, value: V) -> bool {
-        return self.buffer.offer_value_only(value);
+    #[cfg(feature = "std")]
+    fn expired(&self, index: usize) -> bool {
+        self.expiry.as_ref().is_some_and(|expiry| {
+            let now = expiry.epoch.elapsed().as_nanos() as u64;
+            let written = self.slots().stamps[index].load(Ordering::Relaxed);
+            Duration::from_nanos(now.saturating_sub(written)) >= expiry.ttl
+        })
     }
 
-    pub fn size(&self) -> usize {
-        self.buffer.size()
+    /// Values dropped by polls for having gone unwritten longer than `RingBufferBuilder::ttl`.
+    pub fn expired_count(&self) -> usize {
+        self.expired_count.load(Ordering::Relaxed)
     }
 
-    pub fn rejection_count(&self) -> usize {
-        self.buffer.rejection_count()
+    fn take_seq(&self) -> u64 {
+        self.next_seq.fetch_add(1, Ordering::Relaxed)
     }
-}
 
-/// Creates a ring buffer and provides a sender(to produce) and a receiver(consumer) to send/receive
-/// data of the buffer. Sender and Receiver can only be access by one thread
-///
-/// `let (sender, receiver) = new_ring_buffer(25);`
-///
-pub fn new_ring_buffer<K: Send + Eq, V: Send + Clone>(
-    capacity: usize,
-) -> (Sender<K, V>, Receiver<K, V>) {
-    let buf = Arc::new(CoalescingRingBuffer::new(capacity));
-    let buf_clone = buf.clone();
-    (Sender::new(buf), Receiver::new(buf_clone))
-}
+    // For a value taken back to be appended, whose write is then numbered again.
+    fn untake_seq(&self) {
+        self.next_seq.fetch_sub(1, Ordering::Relaxed);
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Same as `try_offer`, but hands back the unread value that a coalescing offer replaced.
+    /// `None` means the value was appended, including when the pending entry for the key was
+    /// read just as it was being replaced.
+    pub(crate) fn offer_replace(&self, key: K, value: V) -> Result<Option<V>, OfferError<K, V>> {
+        self.offer_replacing(key, value)
+            .map(|(_, replaced)| replaced)
+    }
 
-    static VOD_SNAPSHOT_1: MarketSnapshot = MarketSnapshot {
-        instrument_id: 1,
-        bid: 3,
+    // Returns the position the value was written at, whether by coalescing or appending.
+    fn offer_at(&self, key: K, value: V) -> Result<usize, OfferError<K, V>> {
+        self.offer_replacing(key, value)
+            .map(|(position, _)| position)
+    }
+
+    fn offer_replacing(&self, key: K, value: V) -> Result<(usize, Option<V>), OfferError<K, V>> {
+        if self.reject_closed() {
+            return Err(OfferError::Closed { key, value });
+        }
+        let key_type = KeyHolder::NonEmpty(key);
+        let value = match self.coalesce(&key_type, value) {
+            Ok(coalesced) => return Ok(coalesced),
+            Err(value) => value,
+        };
+        match self.admit(false, &value) {
+            Ok(()) => {
+                self.clean_up();
+                Ok((self.store(key_type, value), None))
+            }
+            Err(error) => Err(OfferError::new(error, key_type.into_key().unwrap(), value)),
+        }
+    }
+
+    // Replaces the value of a pending slot with the same key, returning its position and the
+    // value replaced, if it was still unread. Hands the value back if there is no such slot,
+    // so that it can be appended instead.
+    fn coalesce(&self, key_type: &KeyHolder<K>, value: V) -> Result<(usize, Option<V>), V> {
+        let update_pos = match self.find_pending(key_type) {
+            Some(update_pos) => update_pos,
+            None => return Err(value),
+        };
+        if self.policy == CoalescePolicy::KeepFirst && !self.fill_hole(update_pos) {
+            // the pending value may be read any moment now, but it was unread when found
+            self.coalesced_count.fetch_add(1, Ordering::Relaxed);
+            return Ok((update_pos, Some(value)));
+        }
+        self.stamp(self.mask(update_pos));
+        let replaced = self.replace_value(update_pos, Some(value));
+        // The consumer may have claimed the slot in the meantime. If the old value was still
+        // there it has not been read yet and the new one will be delivered in its place.
+        // Otherwise take the new one back, as it is about to be appended and must not be
+        // delivered twice.
+        if replaced.is_none() && update_pos < self.first_write.load(Ordering::SeqCst) {
+            if let Some(value) = self.replace_value(update_pos, None) {
+                self.untake_seq();
+                self.forget_key(key_type, update_pos);
+                return Err(value);
+            }
+        }
+        // filling a hole left by `remove` displaces nothing
+        if replaced.is_some() {
+            self.coalesced_count.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok((update_pos, replaced))
+    }
+
+    // Whether `position` was emptied by `remove`, in which case it is about to be filled again.
+    // Forgets holes the consumer has claimed along the way.
+    fn fill_hole(&self, position: usize) -> bool {
+        let mut holes = self.holes.borrow_mut();
+        let first_write = self.first_write.load(Ordering::SeqCst);
+        holes.retain(|&hole| hole >= first_write);
+        match holes.iter().position(|&hole| hole == position) {
+            Some(hole) => {
+                holes.swap_remove(hole);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // The position of the pending entry with the same key, if there is one.
+    fn find_pending(&self, key_type: &KeyHolder<K>) -> Option<usize> {
+        let first_write = self.first_write.load(Ordering::SeqCst);
+        if let (Some(key_index), KeyHolder::NonEmpty(key)) = (&self.key_index, key_type) {
+            let update_pos = key_index.borrow().get(key)?;
+            if update_pos >= first_write {
+                return Some(update_pos);
+            }
+            self.forget_key(key_type, update_pos);
+            return None;
+        }
+
+        let next_write = self.next_write.load(Ordering::SeqCst);
+        (first_write..next_write)
+            .find(|&update_pos| key_type == self.slots().keys[self.mask(update_pos)].get())
+    }
+
+    // The consumer has claimed the slot, so the key gets a new one.
+    fn forget_key(&self, key_type: &KeyHolder<K>, position: usize) {
+        if let (Some(key_index), KeyHolder::NonEmpty(key)) = (&self.key_index, key_type) {
+            key_index.borrow_mut().remove(key, position);
+        }
+    }
+
+    /// Takes the unread value for `key` back out of the buffer, so that it is never delivered.
+    /// Its slot stays behind as a hole that polls skip, and that a later offer for the key may
+    /// fill again. Returns `None` if the key is not pending or the consumer got there first.
+    pub(crate) fn remove(&self, key: &K) -> Option<V> {
+        let position = self.pending_position(key)?;
+        // The key is left alone, as the consumer may be reading it if it claims the slot in
+        // the meantime. Whoever swaps the value out first gets it, so it is delivered or
+        // returned here, never both.
+        let removed = self.replace_value(position, None)?;
+        if self.policy == CoalescePolicy::KeepFirst {
+            self.holes.borrow_mut().push(position);
+        }
+        Some(removed)
+    }
+
+    /// Removes and returns, oldest first, every unread value for which `pred` holds, given its
+    /// key, or `None` for a keyless value. The slots are left as holes, the same as `remove`
+    /// leaves, and the rest keep their order. Keeps the consumer out while it scans.
+    pub(crate) fn drain_filter<F>(&self, pred: F) -> Vec<V>
+    where
+        F: Fn(Option<&K>, &V) -> bool,
+    {
+        let _excluded = self.exclude_consumer();
+        let mut drained = Vec::new();
+        for position in self.first_write.load(Ordering::SeqCst)..self.next_write() {
+            let index = self.mask(position);
+            let key = match self.slots().keys[index].get() {
+                KeyHolder::NonEmpty(key) => Some(key),
+                _ => None,
+            };
+            // the consumer is kept out, so nothing else touches the value meanwhile
+            let matches = match unsafe { &*self.slots().values[index].as_ptr() } {
+                Some(value) => pred(key, value),
+                None => false,
+            };
+            if !matches {
+                continue;
+            }
+            let keyed = key.is_some();
+            drained.extend(self.replace_value(position, None));
+            // a keyless slot is never coalesced into, so it needs no filling
+            if keyed && self.policy == CoalescePolicy::KeepFirst {
+                self.holes.borrow_mut().push(position);
+            }
+        }
+        drained
+    }
+
+    /// Whether an offer for `key` would coalesce rather than take a slot of its own, that is
+    /// whether the key is pending, even as a hole left by `remove`. Only reads, and the answer
+    /// can be stale the moment it is returned, as the consumer may claim the entry meanwhile.
+    pub(crate) fn contains_key(&self, key: &K) -> bool {
+        self.pending_position(key).is_some()
+    }
+
+    // Read-only counterpart of `find_pending` that leaves the key index as it is.
+    fn pending_position(&self, key: &K) -> Option<usize> {
+        let first_write = self.first_write.load(Ordering::SeqCst);
+        if let Some(key_index) = &self.key_index {
+            return key_index
+                .borrow()
+                .get(key)
+                .filter(|&position| position >= first_write);
+        }
+        let next_write = self.next_write.load(Ordering::SeqCst);
+        (first_write..next_write).find(|&position| {
+            match self.slots().keys[self.mask(position)].get() {
+                KeyHolder::NonEmpty(pending) => pending == key,
+                _ => false,
+            }
+        })
+    }
+
+    /// Like `offer`, but a pending value for the key is merged with the new one by `merge`
+    /// instead of being replaced. Appends `value` as is when the consumer got there first.
+    pub(crate) fn offer_with<F>(&self, key: K, value: V, merge: F) -> bool
+    where
+        F: FnOnce(&V, V) -> V,
+    {
+        self.offer_with_owned(key, value, |old, value| merge(&old, value))
+    }
+
+    /// Entry-style counterpart of `offer_with`: applies `update` in place to the pending value
+    /// for the key, or offers `init` as a new entry if there is none. If the consumer reads the
+    /// pending value first, `init` is offered and `update` is never called.
+    pub(crate) fn offer_or_update<F>(&self, key: K, init: V, update: F) -> bool
+    where
+        F: FnOnce(&mut V),
+    {
+        self.offer_with_owned(key, init, |mut old, _| {
+            update(&mut old);
+            old
+        })
+    }
+
+    fn offer_with_owned<F>(&self, key: K, value: V, merge: F) -> bool
+    where
+        F: FnOnce(V, V) -> V,
+    {
+        if self.reject_closed() {
+            return false;
+        }
+        let key_type = KeyHolder::NonEmpty(key);
+        let value = match self.find_pending(&key_type) {
+            Some(position) => match self.merge_at(position, value, merge) {
+                Ok(()) => {
+                    self.coalesced_count.fetch_add(1, Ordering::Relaxed);
+                    return true;
+                }
+                Err(value) => value,
+            },
+            None => value,
+        };
+        self.add(key_type, value).is_ok()
+    }
+
+    // Merges `value` into the pending value at `position`. The old value is taken out for the
+    // duration of the merge, which leaves a hole that the consumer skips. Hands back the value
+    // to append if the consumer has read the slot in the meantime.
+    fn merge_at<F>(&self, position: usize, value: V, merge: F) -> Result<(), V>
+    where
+        F: FnOnce(V, V) -> V,
+    {
+        let old = match self.replace_value(position, None) {
+            Some(old) => old,
+            None => return Err(value),
+        };
+        let merged = merge(old, value);
+        self.stamp(self.mask(position));
+        self.replace_value(position, Some(merged));
+        if position >= self.first_write.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        // the consumer has claimed the slot, perhaps while it was a hole, so take the merged
+        // value back unless it has been read already
+        match self.replace_value(position, None) {
+            Some(merged) => {
+                self.untake_seq();
+                Err(merged)
+            }
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn offer_value_only(&self, value: V) -> bool {
+        self.try_offer_value_only(value).is_ok()
+    }
+
+    pub(crate) fn try_offer_value_only(&self, value: V) -> Result<(), Error> {
+        self.add(KeyHolder::NonCollapsible, value).map(|_| ())
+    }
+
+    /// Same as `offer_value_only` but returns the sequence number of the write, the one that
+    /// `poll_seq` hands out with the value, or `None` if the value was rejected.
+    pub(crate) fn offer_value_only_seq(&self, value: V) -> Option<u64> {
+        let position = self.add(KeyHolder::NonCollapsible, value).ok()?;
+        // only the producer writes the numbers, so the slot still holds this one
+        Some(self.slots().seqs[self.mask(position)].load(Ordering::Relaxed))
+    }
+
+    /// Offers every entry of the map, in key order, and returns how many were accepted and
+    /// how many were rejected because the buffer was full.
+    #[cfg(feature = "std")]
+    pub(crate) fn offer_map(&self, updates: &HashMap<K, V>) -> (usize, usize)
+    where
+        K: Hash + Ord + Clone,
+        V: Clone,
+    {
+        let mut entries: Vec<(&K, &V)> = updates.iter().collect();
+        entries.sort_by(|a, b| a.0.cmp(b.0));
+
+        let mut accepted = 0;
+        for (key, value) in entries.iter() {
+            if self.offer((*key).clone(), (*value).clone()) {
+                accepted += 1;
+            }
+        }
+        (accepted, entries.len() - accepted)
+    }
+
+    /// Offers a keyless control message. Unlike `offer_value_only` it may use the slots held
+    /// back by `reserve_control_slots`, so it only fails once the whole buffer is full.
+    pub(crate) fn offer_control(&self, value: V) -> bool {
+        if self.reject_closed() {
+            return false;
+        }
+        if self.is_full() {
+            self.reject(RejectReason::Full, true);
+            return false;
+        }
+        self.clean_up();
+        self.store(KeyHolder::NonCollapsible, value);
+        true
+    }
+
+    /// Offers a keyless value that the next poll returns ahead of everything in the ring,
+    /// after any priority value offered before it. Fails with `Full` when all the priority
+    /// slots are taken, and with `InvalidCapacity(0)` when none were set up on the builder,
+    /// which is a misuse rather than a rejection and so is not counted as one.
+    pub(crate) fn offer_priority(&self, value: V) -> Result<(), Error> {
+        let lane = self.priority.as_ref().ok_or(Error::InvalidCapacity(0))?;
+        if self.reject_closed() {
+            return Err(Error::Closed);
+        }
+        let seq = self.take_seq();
+        if lane.push((seq, value)).is_err() {
+            self.untake_seq();
+            self.reject(RejectReason::Full, true);
+            return Err(Error::Full);
+        }
+        self.notify_consumer();
+        Ok(())
+    }
+
+    // Hands up to `max_items` priority values to `f` and returns how many there were, for a
+    // poll to take ahead of its claim on the ring.
+    fn drain_priority<F>(&self, max_items: usize, mut f: F) -> usize
+    where
+        F: FnMut(V),
+    {
+        self.drain_priority_seq(max_items, |_, val| f(val))
+    }
+
+    fn drain_priority_seq<F>(&self, max_items: usize, mut f: F) -> usize
+    where
+        F: FnMut(u64, V),
+    {
+        let lane = match &self.priority {
+            Some(lane) => lane,
+            None => return 0,
+        };
+        let mut drained = 0;
+        while drained < max_items {
+            match lane.pop() {
+                Some((seq, val)) => f(seq, val),
+                None => break,
+            }
+            drained += 1;
+        }
+        drained
+    }
+
+    fn add(&self, key: KeyHolder<K>, value: V) -> Result<usize, Error> {
+        self.admit(key == KeyHolder::NonCollapsible, &value)?;
+        self.clean_up();
+        Ok(self.store(key, value))
+    }
+
+    // Checks that a new entry may be stored, counting the rejection if not.
+    fn admit(&self, keyless: bool, value: &V) -> Result<(), Error> {
+        if self.reject_closed() {
+            return Err(Error::Closed);
+        }
+        // growing the ring would not make room in the byte budget
+        if !self.fits(value) {
+            self.reject(RejectReason::ByteBudget, keyless);
+            return Err(Error::Full);
+        }
+        if !self.has_room() && !self.on_full() {
+            self.reject(RejectReason::Full, keyless);
+            return Err(Error::Full);
+        }
+        Ok(())
+    }
+
+    // Counts a rejected offer. Offers refused for the buffer being closed are not part of
+    // `rejection_count`, which only counts values the buffer had no room for.
+    fn reject(&self, reason: RejectReason, keyless: bool) {
+        if reason != RejectReason::Closed {
+            self.rejection_count.fetch_add(1, Ordering::Relaxed);
+            if keyless {
+                self.value_only_rejection_count
+                    .fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.rejections_by_reason[reason as usize].fetch_add(1, Ordering::Relaxed);
+        self.last_rejection
+            .store(reason as u8 + 1, Ordering::Relaxed);
+    }
+
+    // `is_closed` for the offers, which also records the rejection.
+    fn reject_closed(&self) -> bool {
+        let closed = self.is_closed();
+        if closed {
+            self.reject(RejectReason::Closed, false);
+        }
+        closed
+    }
+
+    pub(crate) fn clean_up(&self) {
+        let last_read = self.last_read.load(Ordering::SeqCst);
+
+        let last_cln = self.last_cleaned.load(Ordering::Relaxed);
+        if last_read == last_cln {
+            return;
+        }
+
+        for x in last_cln..last_read {
+            let index = self.mask(x + 1);
+            let old_key = self.slots().keys[index].replace(KeyHolder::Empty);
+            if let (Some(key_index), KeyHolder::NonEmpty(key)) = (&self.key_index, &old_key) {
+                key_index.borrow_mut().remove(key, x + 1);
+            }
+            let old_val = self.take_value(index);
+        }
+        self.last_cleaned.store(last_read, Ordering::Relaxed);
+    }
+
+    // Grows the buffer if it may, and otherwise runs the stall watchdog, if any, for an offer
+    // that found the buffer full. The callback fires once per stall. Returns whether room was
+    // made.
+    fn on_full(&self) -> bool {
+        if self.grow_limit.is_some() && self.try_grow().is_ok() {
+            return true;
+        }
+        self.run_watchdog()
+    }
+
+    #[cfg(not(feature = "std"))]
+    fn run_watchdog(&self) -> bool {
+        false
+    }
+
+    #[cfg(feature = "std")]
+    fn run_watchdog(&self) -> bool {
+        let watchdog = match &self.watchdog {
+            Some(watchdog) => watchdog,
+            None => return false,
+        };
+        let now = Instant::now();
+        let stalled_since = watchdog.stalled_since.get().unwrap_or(now);
+        watchdog.stalled_since.set(Some(stalled_since));
+
+        let stalled_for = now - stalled_since;
+        if watchdog.reported.get() || stalled_for < watchdog.timeout {
+            return false;
+        }
+        watchdog.reported.set(true);
+        match (watchdog.on_stall.borrow_mut())(stalled_for) {
+            StallAction::Wait => false,
+            StallAction::Clear => {
+                while self.evict_oldest() {
+                    self.overwritten_count.fetch_add(1, Ordering::Relaxed);
+                }
+                self.has_room()
+            }
+        }
+    }
+
+    fn store(&self, key: KeyHolder<K>, value: V) -> usize {
+        self.reset_watchdog();
+        let next_write = self.next_write.load(Ordering::SeqCst);
+        debug_assert!(
+            next_write < usize::MAX,
+            "the write cursor would wrap, see its field"
+        );
+        let index = self.mask(next_write);
+        if let (Some(key_index), KeyHolder::NonEmpty(key)) = (&self.key_index, &key) {
+            key_index.borrow_mut().insert(key, next_write);
+        }
+        self.slots().keys[index].set(key);
+        self.stamp(index);
+        self.stamp_stored(index);
+        self.slots().seqs[index].store(self.take_seq(), Ordering::Relaxed);
+        self.weigh_in(&value);
+        let old_ptr = self.slots().values[index].swap(Some(value));
+        self.next_write.store(next_write + 1, Ordering::SeqCst);
+        let size = next_write - self.last_read.load(Ordering::SeqCst);
+        self.high_water_mark.fetch_max(size, Ordering::Relaxed);
+        self.notify_consumer();
+        next_write
+    }
+
+    #[cfg(not(feature = "std"))]
+    fn reset_watchdog(&self) {}
+
+    #[cfg(feature = "std")]
+    fn reset_watchdog(&self) {
+        if let Some(watchdog) = &self.watchdog {
+            watchdog.stalled_since.set(None);
+            watchdog.reported.set(false);
+        }
+    }
+
+    #[cfg(not(feature = "std"))]
+    fn stamp_stored(&self, _index: usize) {}
+
+    #[cfg(feature = "std")]
+    fn stamp_stored(&self, index: usize) {
+        if let Some(flush) = &self.flush_window {
+            self.slots().stored_at[index].store(flush.tick(), Ordering::Relaxed);
+        }
+    }
+
+    // How many of the pending entries from `first_write` up to `claim_up_to` the flush window
+    // lets through. Entries are stamped in the order they are appended, so those are a prefix.
+    #[cfg(not(feature = "std"))]
+    fn ripe(&self, first_write: usize, claim_up_to: usize) -> usize {
+        claim_up_to - first_write
+    }
+
+    #[cfg(feature = "std")]
+    fn ripe(&self, first_write: usize, claim_up_to: usize) -> usize {
+        let flush = match &self.flush_window {
+            Some(flush) => flush,
+            None => return claim_up_to - first_write,
+        };
+        (first_write..claim_up_to)
+            .take_while(|&position| {
+                let stored_at = &self.slots().stored_at[self.mask(position)];
+                flush.remaining(stored_at.load(Ordering::Relaxed)).is_none()
+            })
+            .count()
+    }
+
+    // How long until the flush window lets the oldest pending entry through, if it is holding
+    // it back.
+    #[cfg(feature = "std")]
+    fn until_ripe(&self) -> Option<Duration> {
+        let flush = self.flush_window.as_ref()?;
+        // the producer may be growing the ring, and replacing the slots, meanwhile
+        let _reading = self.start_reading();
+        let first_write = self.first_write.load(Ordering::SeqCst);
+        if first_write == self.next_write.load(Ordering::SeqCst) {
+            return None;
+        }
+        let stored_at = &self.slots().stored_at[self.mask(first_write)];
+        flush.remaining(stored_at.load(Ordering::Relaxed))
+    }
+
+    // Whether a poll would return something now, with the flush window holding nothing back
+    // that it would take.
+    #[cfg(feature = "std")]
+    pub(crate) fn is_ripe(&self) -> bool {
+        let priority = self.priority.as_ref().is_some_and(|lane| !lane.is_empty());
+        priority || (!self.is_empty() && self.until_ripe().is_none())
+    }
+
+    // Parks the consumer like `wait_for_data`, or sleeps until the flush window lets the
+    // oldest entry through, as the producer will not wake it for that.
+    #[cfg(feature = "std")]
+    fn wait_for_poll(&self, timeout: Option<Duration>) {
+        match self.until_ripe() {
+            Some(ripe_in) => thread::sleep(timeout.map_or(ripe_in, |timeout| timeout.min(ripe_in))),
+            None => self.wait_for_data(timeout),
+        }
+    }
+
+    #[cfg(not(feature = "std"))]
+    fn stamp(&self, _index: usize) {}
+
+    #[cfg(feature = "std")]
+    fn stamp(&self, index: usize) {
+        let epoch = match (&self.latency, &self.expiry) {
+            (Some(latency), _) => latency.epoch,
+            (None, Some(expiry)) => expiry.epoch,
+            (None, None) => return,
+        };
+        let tick = epoch.elapsed().as_nanos() as u64;
+        self.slots().stamps[index].store(tick, Ordering::Relaxed);
+    }
+
+    /// Latencies, from offer to poll, of the values returned by the latest poll in offer
+    /// order. Always empty unless latency measurement was enabled on the builder.
+    #[cfg(feature = "std")]
+    pub(crate) fn last_poll_latencies(&self) -> Vec<Duration> {
+        match &self.latency {
+            Some(latency) => latency.last_poll.borrow().clone(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Everything pending, including what the flush window is still holding back.
+    pub(crate) fn poll_all(&self) -> Vec<V> {
+        let mut bucket: Vec<V> = Vec::new();
+        self.poll_window_into(&mut bucket, usize::MAX, false);
+        bucket
+    }
+
+    pub(crate) fn poll(&self, max_items: usize) -> Vec<V> {
+        let mut bucket: Vec<V> = Vec::new();
+        self.poll_into(&mut bucket, max_items);
+        bucket
+    }
+
+    /// Same as `poll`, along with how many offers were coalesced since the previous call. Those
+    /// are mostly merged into the polled slots, but may also be into ones left for later.
+    pub(crate) fn poll_stats(&self, max_items: usize) -> (Vec<V>, PollStats) {
+        let bucket = self.poll(max_items);
+        let coalesced_count = self.coalesced_count();
+        let seen = self.coalesced_seen.swap(coalesced_count, Ordering::Relaxed);
+        let stats = PollStats {
+            delivered: bucket.len(),
+            // the counters start over after a `clear`
+            coalesced: coalesced_count.checked_sub(seen).unwrap_or(coalesced_count),
+        };
+        (bucket, stats)
+    }
+
+    /// Same as `poll` but blocks until at least one value is available, or the sender is gone.
+    #[cfg(feature = "std")]
+    pub(crate) fn poll_blocking(&self, max_items: usize) -> Vec<V> {
+        loop {
+            let disconnected = self.is_disconnected();
+            let bucket = self.poll(max_items);
+            if !bucket.is_empty() || disconnected {
+                return bucket;
+            }
+            self.wait_for_poll(None);
+        }
+    }
+
+    pub fn is_disconnected(&self) -> bool {
+        self.sender_dropped.load(Ordering::SeqCst)
+    }
+
+    /// Same as `poll_all`, except that it fails once the sender is gone and everything it
+    /// offered has been read.
+    pub(crate) fn recv_all(&self) -> Result<Vec<V>, Disconnected> {
+        // read the flag first: whatever the sender stored before going away is then visible
+        let disconnected = self.is_disconnected();
+        let bucket = self.poll_all();
+        if bucket.is_empty() && disconnected {
+            return Err(Disconnected);
+        }
+        Ok(bucket)
+    }
+
+    /// Takes the oldest pending value, if there is one, telling an empty buffer apart from a
+    /// sender that is gone.
+    pub(crate) fn try_recv(&self) -> Result<V, TryRecvError> {
+        let disconnected = self.is_disconnected();
+        match self.take_one() {
+            Some(value) => Ok(value),
+            None if disconnected => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    // Claims one slot at a time until one of them holds a value, so that a hole left by
+    // `remove` does not pass for an empty buffer. Nothing is allocated.
+    fn take_one(&self) -> Option<V> {
+        let _reading = self.start_reading();
+        let mut taken = None;
+        if self.drain_priority(1, |val| taken = Some(val)) > 0 {
+            return taken;
+        }
+        loop {
+            let (first, claim_up_to) = self.claim(1);
+            if first == claim_up_to {
+                return None;
+            }
+            let mut taken = None;
+            self.drain(first, claim_up_to, |val| taken = Some(val));
+            if taken.is_some() {
+                return taken;
+            }
+        }
+    }
+
+    /// Blocks for the oldest pending value, failing only once the sender is gone and
+    /// everything it offered has been read.
+    #[cfg(feature = "std")]
+    pub(crate) fn recv(&self) -> Result<V, Disconnected> {
+        loop {
+            match self.try_recv() {
+                Ok(value) => return Ok(value),
+                Err(TryRecvError::Disconnected) => return Err(Disconnected),
+                Err(TryRecvError::Empty) => self.wait_for_poll(None),
+            }
+        }
+    }
+
+    /// Same as `poll_blocking` but gives up after `timeout`, returning whatever is available,
+    /// possibly nothing.
+    #[cfg(feature = "std")]
+    pub(crate) fn poll_timeout(&self, max_items: usize, timeout: Duration) -> Vec<V> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let bucket = self.poll(max_items);
+            let now = Instant::now();
+            if !bucket.is_empty() || now >= deadline {
+                return bucket;
+            }
+            // wakeups may be spurious, hence the loop back to the deadline check
+            self.wait_for_poll(Some(deadline - now));
+        }
+    }
+
+    // Parks the consumer until the producer stores something, or `timeout` elapses.
+    #[cfg(feature = "std")]
+    fn wait_for_data(&self, timeout: Option<Duration>) {
+        if let Some(strategy) = &self.data_wait {
+            if self.is_empty() && !self.is_disconnected() {
+                match timeout {
+                    Some(timeout) => strategy.wait_timeout(timeout),
+                    None => strategy.wait(),
+                }
+            }
+            return;
+        }
+        let lock = self.data_lock.lock().unwrap();
+        // announce the wait before checking again: either the producer sees the flag and
+        // notifies under the lock, or this check sees its store
+        self.consumer_waiting.store(true, Ordering::SeqCst);
+        if self.is_empty() && !self.is_disconnected() {
+            match timeout {
+                Some(timeout) => drop(self.data_available.wait_timeout(lock, timeout).unwrap()),
+                None => drop(self.data_available.wait(lock).unwrap()),
+            }
+        }
+        self.consumer_waiting.store(false, Ordering::SeqCst);
+    }
+
+    // Leaves `waker` for the next store to wake, unless there is something to poll already or
+    // the sender is gone, in which case it returns `false`. Same handshake as `wait_for_data`.
+    #[cfg(feature = "std")]
+    pub(crate) fn register_waker(&self, waker: &Waker) -> bool {
+        let mut registered = self.data_lock.lock().unwrap();
+        *registered = Some(waker.clone());
+        self.consumer_waiting.store(true, Ordering::SeqCst);
+        self.is_empty() && !self.is_disconnected()
+    }
+
+    /// Clears `bucket` and polls up to `max_items` values into it, reusing its allocation.
+    /// Returns the number of values polled.
+    pub(crate) fn poll_into(&self, bucket: &mut Vec<V>, max_items: usize) -> usize {
+        self.poll_window_into(bucket, max_items, true)
+    }
+
+    // `windowed` leaves what the flush window holds back, if there is one, for a later poll.
+    // Priority values are never held back.
+    fn poll_window_into(&self, bucket: &mut Vec<V>, max_items: usize, windowed: bool) -> usize {
+        bucket.clear();
+        self.fill(bucket, max_items, windowed)
+    }
+
+    // Puts up to `max_items` values into `bucket`, priority values first, and no more than it
+    // has room for.
+    fn fill<B: Bucket<V>>(&self, bucket: &mut B, max_items: usize, windowed: bool) -> usize {
+        let max_items = cmp::min(max_items, bucket.room());
+        let _reading = self.start_reading();
+        let priority = self.drain_priority(max_items, |val| bucket.put(val));
+        let (first, claim_up_to) = self.claim_window(max_items - priority, windowed);
+        priority + self.drain(first, claim_up_to, |val| bucket.put(val))
+    }
+
+    /// Polls up to `out.len()` values into the front of `out` and sets the rest of it to
+    /// `None`. Returns how many values were written.
+    pub(crate) fn poll_slice(&self, out: &mut [Option<V>]) -> usize {
+        let mut bucket = SliceBucket { out, written: 0 };
+        let written = self.fill(&mut bucket, usize::MAX, true);
+        for slot in &mut bucket.out[written..] {
+            *slot = None;
+        }
+        written
+    }
+
+    /// Same as `poll`, but batches of up to 16 values stay on the stack.
+    #[cfg(feature = "smallvec")]
+    pub(crate) fn poll_small(&self, max_items: usize) -> SmallVec<[V; 16]> {
+        let mut bucket = SmallVec::new();
+        self.fill(&mut bucket, max_items, true);
+        bucket
+    }
+
+    /// Claims up to `max_items` and returns an iterator that takes them out of the ring one at
+    /// a time. Whatever the iterator did not yield is discarded when it is dropped.
+    pub(crate) fn drain_iter(&self, max_items: usize) -> Drain<'_, K, V, N> {
+        let reading = self.start_reading();
+        // only the consumer pops, so what is in the priority lane now stays there for the drain
+        let priority = self.priority.as_ref().map_or(0, |lane| {
+            let pending = lane.tail.load(Ordering::SeqCst) - lane.head.load(Ordering::SeqCst);
+            cmp::min(pending, max_items)
+        });
+        let (first, claim_up_to) = self.claim(max_items - priority);
+        Drain {
+            buffer: self,
+            _reading: reading,
+            priority,
+            first,
+            next: first,
+            claim_up_to,
+        }
+    }
+
+    /// Same as `poll_all` but the most recently written entry comes first.
+    pub(crate) fn poll_all_newest_first(&self) -> Vec<V> {
+        let _reading = self.start_reading();
+        let mut bucket: Vec<V> = Vec::new();
+        self.drain_priority(usize::MAX, |val| bucket.push(val));
+        let (first, claim_up_to) = self.claim_window(usize::MAX, false);
+
+        bucket.reserve(claim_up_to - first);
+        for read_index in (first..claim_up_to).rev() {
+            let index = self.mask(read_index);
+            if let Some(val) = self.take_value(index) {
+                bucket.push(val);
+            }
+        }
+        self.release(first, claim_up_to);
+        bucket
+    }
+
+    /// Claims up to `max_items` and hands each value to `f` as it is taken out of the ring,
+    /// without collecting them first. Returns the number of values passed to `f`.
+    pub(crate) fn poll_for_each<F>(&self, max_items: usize, mut f: F) -> usize
+    where
+        F: FnMut(V),
+    {
+        let _reading = self.start_reading();
+        let priority = self.drain_priority(max_items, &mut f);
+        let (first, claim_up_to) = self.claim(max_items - priority);
+        priority + self.drain(first, claim_up_to, f)
+    }
+
+    /// Takes up to `max_items` values one at a time, handing each to `f` once its slot has been
+    /// given back, and returns how many it took. Should `f` panic, the value it was given is
+    /// gone, everything before it has been read and everything after it is still pending.
+    pub(crate) fn for_each_drained<F>(&self, max_items: usize, mut f: F) -> usize
+    where
+        F: FnMut(V),
+    {
+        let mut drained = 0;
+        while drained < max_items {
+            match self.take_one() {
+                Some(val) => f(val),
+                None => break,
+            }
+            drained += 1;
+        }
+        drained
+    }
+
+    /// Polls values until taking the next one would push the total reported by `size_of` past
+    /// `max_bytes`. At least one value is returned when the buffer is not empty, even if it is
+    /// larger than `max_bytes` on its own.
+    pub(crate) fn poll_bounded_bytes<F>(&self, max_bytes: usize, size_of: F) -> Vec<V>
+    where
+        F: Fn(&V) -> usize,
+    {
+        let _reading = self.start_reading();
+        let mut bucket: Vec<V> = Vec::new();
+        let mut read = None;
+        let mut total = 0;
+        loop {
+            let position = self.first_write.load(Ordering::SeqCst);
+            // anything the flush window holds back is left for a later poll
+            let next_write = self.next_write.load(Ordering::SeqCst);
+            if position == next_write || self.ripe(position, position + 1) == 0 {
+                break;
+            }
+            // Weigh the value while the producer is kept from replacing it, and claim the slot
+            // only if it fits, so that nothing claimed is ever handed back. A hole is claimed
+            // as it is. `None` from the pin, or a failed claim, means the producer evicted the
+            // slot first.
+            let claimed = self.pinned(position, |index| {
+                let fits = match unsafe { &*self.slots().values[index].as_ptr() } {
+                    Some(val) => bucket.is_empty() || total + size_of(val) <= max_bytes,
+                    None => true,
+                };
+                fits.then(|| {
+                    self.first_write
+                        .compare_exchange(
+                            position,
+                            position + 1,
+                            Ordering::SeqCst,
+                            Ordering::SeqCst,
+                        )
+                        .is_ok()
+                })
+            });
+            match claimed {
+                Some(None) => break,
+                Some(Some(true)) => {}
+                _ => continue,
+            }
+            read = Some((read.map_or(position, |(first, _)| first), position + 1));
+            if let Some(val) = self.take_value(self.mask(position)) {
+                total += size_of(&val);
+                bucket.push(val);
+            }
+        }
+        if let Some((first, read_up_to)) = read {
+            self.release(first, read_up_to);
+        }
+        bucket
+    }
+
+    /// Drains every pending entry together with its key (`None` for keyless entries), leaving
+    /// the buffer empty and ready for reuse. Meant for one-shot snapshots at a checkpoint, so
+    /// it takes what the flush window holds back too.
+    pub(crate) fn take_all(&self) -> Vec<(Option<K>, V)>
+    where
+        K: Clone,
+    {
+        let mut entries = Vec::new();
+        self.poll_keyed_window_into(&mut entries, usize::MAX, false);
+        entries
+    }
+
+    /// Same as `poll` but every value comes with its key, `None` for keyless entries.
+    pub(crate) fn poll_keyed(&self, max_items: usize) -> Vec<(Option<K>, V)>
+    where
+        K: Clone,
+    {
+        let mut entries = Vec::new();
+        self.poll_keyed_into(&mut entries, max_items);
+        entries
+    }
+
+    /// Same as `poll_keyed` but appends to `out`, which is not cleared first.
+    pub(crate) fn poll_keyed_into(&self, out: &mut Vec<(Option<K>, V)>, max_items: usize) -> usize
+    where
+        K: Clone,
+    {
+        self.poll_keyed_window_into(out, max_items, true)
+    }
+
+    fn poll_keyed_window_into(
+        &self,
+        out: &mut Vec<(Option<K>, V)>,
+        max_items: usize,
+        windowed: bool,
+    ) -> usize
+    where
+        K: Clone,
+    {
+        let _reading = self.start_reading();
+        let priority = self.drain_priority(max_items, |val| out.push((None, val)));
+        let (first, claim_up_to) = self.claim_window(max_items - priority, windowed);
+        priority
+            + self.drain_slots(first, claim_up_to, |index, val| {
+                out.push((self.key_at(index), val))
+            })
+    }
+
+    /// Same as `poll` but every value comes with the sequence number of the write that left it
+    /// in the buffer. Every value written, by an append or a coalescing offer, takes the next
+    /// number, so a gap between two numbers counts the updates coalesced away.
+    pub(crate) fn poll_seq(&self, max_items: usize) -> Vec<(u64, V)> {
+        let _reading = self.start_reading();
+        let mut entries = Vec::new();
+        let priority = self.drain_priority_seq(max_items, |seq, val| entries.push((seq, val)));
+        let (first, claim_up_to) = self.claim(max_items - priority);
+        for read_index in first..claim_up_to {
+            let index = self.mask(read_index);
+            // a coalescing offer may still be replacing the value, and its number with it
+            let entry = self.with_pin(read_index, || {
+                let seq = self.slots().seqs[index].load(Ordering::Relaxed);
+                self.take_value(index).map(|val| (seq, val))
+            });
+            entries.extend(entry);
+        }
+        self.release(first, claim_up_to);
+        entries
+    }
+
+    /// Clones the pending value for `key` without consuming it. Only ever reads: the producer
+    /// holds off replacing that one value while it is cloned.
+    pub(crate) fn peek(&self, key: &K) -> Option<V>
+    where
+        V: Clone,
+    {
+        let _reading = self.start_reading();
+        let first_write = self.first_write.load(Ordering::SeqCst);
+        let next_write = self.next_write.load(Ordering::SeqCst);
+        let position = (first_write..next_write).rev().find(|&position| {
+            match self.slots().keys[self.mask(position)].get() {
+                KeyHolder::NonEmpty(pending) => pending == key,
+                _ => false,
+            }
+        })?;
+        self.pinned(position, |index| self.clone_value(index))
+            .flatten()
+    }
+
+    /// Clones every pending entry, with its key, without consuming anything. Like `peek`, it
+    /// only ever reads, so the producer can keep offering meanwhile.
+    pub(crate) fn snapshot(&self) -> BufferSnapshot<K, V>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let _reading = self.start_reading();
+        let first_write = self.first_write.load(Ordering::SeqCst);
+        let next_write = self.next_write.load(Ordering::SeqCst);
+        let entries = (first_write..next_write)
+            .filter_map(|position| {
+                self.pinned(position, |index| {
+                    let value = self.clone_value(index);
+                    value.map(|value| (self.key_at(index), value))
+                })
+                .flatten()
+            })
+            .collect();
+        BufferSnapshot {
+            entries,
+            capacity: self.capacity(),
+            size: self.size(),
+            rejection_count: self.rejection_count(),
+        }
+    }
+
+    /// Calls `f` on every pending entry in offer order, by reference, with `None` for keyless
+    /// entries. Nothing is cloned or consumed and the cursors stay put. The producer cannot
+    /// replace a value while `f` is looking at it.
+    pub(crate) fn for_each_live<F>(&self, mut f: F)
+    where
+        F: FnMut(Option<&K>, &V),
+    {
+        let _reading = self.start_reading();
+        let first_write = self.first_write.load(Ordering::SeqCst);
+        let next_write = self.next_write.load(Ordering::SeqCst);
+        for position in first_write..next_write {
+            self.pinned(position, |index| {
+                // as sound as `clone_value`, the slot being pinned
+                if let Some(value) = unsafe { &*self.slots().values[index].as_ptr() } {
+                    let key = match self.slots().keys[index].get() {
+                        KeyHolder::NonEmpty(key) => Some(key),
+                        _ => None,
+                    };
+                    f(key, value);
+                }
+            });
+        }
+    }
+
+    // Only sound for a slot pinned with `pinned`.
+    fn clone_value(&self, index: usize) -> Option<V>
+    where
+        V: Clone,
+    {
+        unsafe { (*self.slots().values[index].as_ptr()).clone() }
+    }
+
+    // Runs `f` on the slot index of a pending position while the producer is kept from
+    // replacing its value. `None` if the position was no longer pending once pinned.
+    fn pinned<R, F>(&self, position: usize, f: F) -> Option<R>
+    where
+        F: FnOnce(usize) -> R,
+    {
+        self.with_pin(position, || {
+            // the slot may have been evicted before it was pinned, and then even reused
+            if position >= self.first_write.load(Ordering::SeqCst) {
+                Some(f(self.mask(position)))
+            } else {
+                None
+            }
+        })
+    }
+
+    // The consumer's half of the handshake in `replace_value`, held while `f` runs.
+    fn with_pin<R, F>(&self, position: usize, f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        let backoff = Backoff::new();
+        loop {
+            self.peeking.store(position, Ordering::SeqCst);
+            if self.writing.load(Ordering::SeqCst) != position {
+                break;
+            }
+            self.peeking.store(0, Ordering::SeqCst);
+            while self.writing.load(Ordering::SeqCst) == position {
+                backoff.snooze();
+            }
+        }
+        let result = f();
+        self.peeking.store(0, Ordering::SeqCst);
+        result
+    }
+
+    /// Polls up to `max_items` entries and keeps only the latest value per key, at the position
+    /// the key first showed up in. Keyless entries are returned separately, in offer order.
+    #[cfg(feature = "std")]
+    pub(crate) fn poll_compacted(&self, max_items: usize) -> (Vec<(K, V)>, Vec<V>)
+    where
+        K: Hash + Clone,
+    {
+        let _reading = self.start_reading();
+        let mut keyed: Vec<(K, V)> = Vec::new();
+        let mut keyless: Vec<V> = Vec::new();
+        let priority = self.drain_priority(max_items, |val| keyless.push(val));
+        let (first, claim_up_to) = self.claim(max_items - priority);
+        let mut positions: HashMap<K, usize> = HashMap::new();
+        self.drain_slots(first, claim_up_to, |index, val| match self.key_at(index) {
+            Some(key) => match positions.get(&key) {
+                Some(&position) => keyed[position].1 = val,
+                None => {
+                    positions.insert(key.clone(), keyed.len());
+                    keyed.push((key, val));
+                }
+            },
+            None => keyless.push(val),
+        });
+        (keyed, keyless)
+    }
+
+    // Claims the range `[first_write, claim_up_to)` for reading, short of anything the flush
+    // window holds back. `first_write` is advanced with a CAS because the producer may also
+    // move it when evicting the oldest entry.
+    fn claim(&self, max_items: usize) -> (usize, usize) {
+        self.claim_window(max_items, true)
+    }
+
+    // `windowed` is off for the reads that take everything pending, like `poll_all`.
+    fn claim_window(&self, max_items: usize, windowed: bool) -> (usize, usize) {
+        loop {
+            // read `first_write` first: it never passes `next_write`, but an older `next_write`
+            // could be behind a `first_write` the producer has just moved
+            let first_write = self.first_write.load(Ordering::SeqCst);
+            let next_write = self.next_write.load(Ordering::SeqCst);
+            let mut claim_up_to = cmp::min(first_write.saturating_add(max_items), next_write);
+            if windowed {
+                claim_up_to = first_write + self.ripe(first_write, claim_up_to);
+            }
+            if claim_up_to == first_write {
+                return (first_write, claim_up_to);
+            }
+            if self
+                .first_write
+                .compare_exchange(first_write, claim_up_to, Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok()
+            {
+                return (first_write, claim_up_to);
+            }
+        }
+    }
+
+    // Marks a claimed range as read, which frees its slots for the producer.
+    fn release(&self, first: usize, claim_up_to: usize) {
+        #[cfg(feature = "std")]
+        if let Some(latency) = &self.latency {
+            // the stamps stay put until the range is released
+            let now = latency.tick();
+            let mut last_poll = latency.last_poll.borrow_mut();
+            last_poll.clear();
+            for read_index in first..claim_up_to {
+                let stamp = self.slots().stamps[self.mask(read_index)].load(Ordering::Relaxed);
+                last_poll.push(Duration::from_nanos(now.saturating_sub(stamp)));
+            }
+        }
+        if claim_up_to > first {
+            self.last_read.store(claim_up_to - 1, Ordering::SeqCst);
+            self.notify_producer();
+        }
+    }
+
+    fn drain<F>(&self, first: usize, claim_up_to: usize, mut f: F) -> usize
+    where
+        F: FnMut(V),
+    {
+        self.drain_slots(first, claim_up_to, |_, val| f(val))
+    }
+
+    fn drain_slots<F>(&self, first: usize, claim_up_to: usize, mut f: F) -> usize
+    where
+        F: FnMut(usize, V),
+    {
+        let mut drained = 0;
+        for read_index in first..claim_up_to {
+            let index = self.mask(read_index);
+            // `store` fills a slot before it publishes `next_write`, both SeqCst, and the claim
+            // never goes past the `next_write` it read. Coalescing replaces a value with
+            // another, and neither eviction nor `clean_up` touch slots at or beyond
+            // `first_write`, which the claim has moved on. So a claimed slot is only ever empty
+            // when the producer took its value back, to append it instead, see `coalesce` and
+            // `merge_at`, or for good, see `remove`.
+            if let Some(val) = self.take_value(index) {
+                f(index, val);
+                drained += 1;
+            }
+        }
+        self.release(first, claim_up_to);
+        drained
+    }
+
+    fn mask(&self, value: usize) -> usize {
+        self.slots().index(value)
+    }
+
+    fn slots(&self) -> &Slots<K, V, N> {
+        unsafe { &*self.slots.get() }
+    }
+
+    // Keeps `try_grow` and `clear` from rewriting the slots while the consumer reads them. This
+    // is a Dekker style handshake: each side raises its own flag and then checks the other one's.
+    fn start_reading(&self) -> Raised<'_> {
+        let backoff = Backoff::new();
+        loop {
+            self.reading.store(true, Ordering::SeqCst);
+            if !self.excluding.load(Ordering::SeqCst) {
+                return Raised(&self.reading);
+            }
+            self.reading.store(false, Ordering::SeqCst);
+            while self.excluding.load(Ordering::SeqCst) {
+                backoff.snooze();
+            }
+        }
+    }
+
+    // The producer's side of the handshake in `start_reading`: waits for a poll in progress to
+    // finish and keeps the consumer out until the returned guard is dropped.
+    fn exclude_consumer(&self) -> Raised<'_> {
+        self.excluding.store(true, Ordering::SeqCst);
+        let backoff = Backoff::new();
+        while self.reading.load(Ordering::SeqCst) {
+            backoff.snooze();
+        }
+        Raised(&self.excluding)
+    }
+
+    /// Drops every pending entry and resets the cursors and counters, leaving the buffer as it
+    /// was when created without reallocating it. Producer side only, like `try_grow`.
+    pub(crate) fn clear(&self) {
+        let _excluded = self.exclude_consumer();
+        let slots = self.slots();
+        for position in self.last_cleaned.load(Ordering::Relaxed) + 1..self.next_write() {
+            let index = self.mask(position);
+            slots.keys[index].set(KeyHolder::Empty);
+            drop(slots.values[index].take());
+        }
+        if let Some(key_index) = &self.key_index {
+            key_index.borrow_mut().clear();
+        }
+        self.holes.borrow_mut().clear();
+        // the consumer is kept out, so the producer may empty the priority lane too
+        self.drain_priority(usize::MAX, drop);
+        self.reset_watchdog();
+        self.next_write.store(1, Ordering::SeqCst);
+        self.first_write.store(1, Ordering::SeqCst);
+        self.last_read.store(0, Ordering::SeqCst);
+        self.last_cleaned.store(0, Ordering::Relaxed);
+        self.rejection_count.store(0, Ordering::Relaxed);
+        self.value_only_rejection_count.store(0, Ordering::Relaxed);
+        self.overwritten_count.store(0, Ordering::Relaxed);
+        self.coalesced_count.store(0, Ordering::Relaxed);
+        self.high_water_mark.store(0, Ordering::Relaxed);
+        self.expired_count.store(0, Ordering::Relaxed);
+        for count in &self.rejections_by_reason {
+            count.store(0, Ordering::Relaxed);
+        }
+        self.last_rejection.store(0, Ordering::Relaxed);
+        self.coalesced_seen.store(0, Ordering::Relaxed);
+        self.next_seq.store(0, Ordering::Relaxed);
+        if let Some(limit) = &self.byte_limit {
+            limit.bytes.store(0, Ordering::Relaxed);
+        }
+    }
+
+    /// Doubles the capacity, keeping every pending entry in order. Producer side only: it waits
+    /// for a poll in progress to finish and keeps the consumer out until done. Fails with
+    /// `InvalidCapacity` when the doubled capacity would exceed the configured grow limit.
+    pub(crate) fn try_grow(&self) -> Result<usize, Error> {
+        self.grow(self.capacity().saturating_mul(2))
+    }
+
+    /// Like `try_grow`, but to `new_capacity`, rounded up to a power of two unless the buffer
+    /// was built with an exact capacity. Fails with `InvalidCapacity` unless that is more than
+    /// the current capacity and within the grow limit.
+    pub(crate) fn grow(&self, new_capacity: usize) -> Result<usize, Error> {
+        let capacity = self.capacity();
+        let grown_capacity = match self.slots().mask {
+            Some(_) => new_capacity.checked_next_power_of_two(),
+            None => Some(new_capacity),
+        }
+        .filter(|&grown| grown > capacity && grown <= self.grow_limit.unwrap_or(usize::MAX))
+        // the slots of a fixed capacity buffer are inline
+        .filter(|_| N == 0)
+        .ok_or(Error::InvalidCapacity(new_capacity))?;
+
+        let _excluded = self.exclude_consumer();
+        // read slots are not moved over, so let them forget their keys first
+        self.clean_up();
+
+        // the consumer is out, and positions are absolute, so only the mask changes for the
+        // entries that have not been read yet
+        let slots = unsafe { &mut *self.slots.get() };
+        let grown: Slots<K, V, N> = Slots::new(
+            grown_capacity,
+            !slots.stamps.is_empty(),
+            !slots.stored_at.is_empty(),
+        );
+        let last_read = self.last_read.load(Ordering::SeqCst);
+        for position in last_read + 1..self.next_write.load(Ordering::SeqCst) {
+            let from = slots.index(position);
+            let to = grown.index(position);
+            grown.keys[to].set(slots.keys[from].replace(KeyHolder::Empty));
+            grown.values[to].store(slots.values[from].take());
+            grown.seqs[to].store(slots.seqs[from].load(Ordering::Relaxed), Ordering::Relaxed);
+            if !grown.stamps.is_empty() {
+                grown.stamps[to].store(
+                    slots.stamps[from].load(Ordering::Relaxed),
+                    Ordering::Relaxed,
+                );
+            }
+            if !grown.stored_at.is_empty() {
+                let stored_at = slots.stored_at[from].load(Ordering::Relaxed);
+                grown.stored_at[to].store(stored_at, Ordering::Relaxed);
+            }
+        }
+        *slots = grown;
+        self.last_cleaned.store(last_read, Ordering::Relaxed);
+        self.capacity.store(grown_capacity, Ordering::Relaxed);
+        Ok(grown_capacity)
+    }
+
+    // Keys of claimed slots are safe to read from the consumer: the producer only rewrites a
+    // key in `store`, for a free slot, or in `clean_up`, once the slot has been read.
+    fn key_at(&self, index: usize) -> Option<K>
+    where
+        K: Clone,
+    {
+        match self.slots().keys[index].get() {
+            KeyHolder::NonEmpty(key) => Some(key.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl<K, V, const N: usize> CoalescingRingBuffer<K, V, N>
+where
+    V: Send,
+{
+    // Wakes a consumer blocked in `poll_blocking`, or the task of a stream. The lock is only
+    // taken when the consumer has announced it is about to wait, which keeps it off the fast
+    // path.
+    #[cfg(feature = "std")]
+    fn notify_consumer(&self) {
+        if let Some(strategy) = &self.data_wait {
+            strategy.signal();
+        }
+        // a stream still registers its waker here
+        if self.consumer_waiting.load(Ordering::SeqCst) {
+            let waker = {
+                let mut registered = self.data_lock.lock().unwrap();
+                self.data_available.notify_one();
+                let waker = registered.take();
+                if waker.is_some() {
+                    // a waker is good for one wake only, the stream registers again if need be
+                    self.consumer_waiting.store(false, Ordering::SeqCst);
+                }
+                waker
+            };
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        }
+    }
+
+    #[cfg(not(feature = "std"))]
+    fn notify_consumer(&self) {}
+
+    // Wakes a producer blocked in `offer_blocking`, the same way `notify_consumer` does.
+    #[cfg(feature = "std")]
+    fn notify_producer(&self) {
+        if let Some(strategy) = &self.room_wait {
+            strategy.signal();
+        }
+        if self.producer_waiting.load(Ordering::SeqCst) {
+            let _lock = self.room_lock.lock().unwrap();
+            self.room_available.notify_one();
+        }
+    }
+
+    #[cfg(not(feature = "std"))]
+    fn notify_producer(&self) {}
+}
+
+/// The pending entries of a buffer at one point in time, see `Receiver::snapshot`. Keyless
+/// entries have no key. Serializable with the `serde` feature.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct BufferSnapshot<K, V> {
+    pub entries: Vec<(Option<K>, V)>,
+    pub capacity: usize,
+    pub size: usize,
+    pub rejection_count: usize,
+}
+
+/// Why an offer was rejected, see `Sender::last_rejection`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum RejectReason {
+    /// No slot was free, and the buffer could not make room.
+    Full,
+    /// The receiver is gone, or the buffer was closed.
+    Closed,
+    /// The value did not fit in the byte budget of `RingBufferBuilder::with_byte_capacity`.
+    ByteBudget,
+}
+
+/// The statistics of a buffer, see `Sender::metrics`. `high_water_mark` is the largest size
+/// the buffer ever reached, up to a `clear`. `rejection_count` is `full_rejections` plus
+/// `byte_budget_rejections`; offers to a closed buffer are only in `closed_rejections`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct BufferMetrics {
+    pub capacity: usize,
+    pub current_size: usize,
+    pub rejection_count: usize,
+    pub full_rejections: usize,
+    pub closed_rejections: usize,
+    pub byte_budget_rejections: usize,
+    pub coalesced_count: usize,
+    pub high_water_mark: usize,
+}
+
+/// What a `Receiver::poll_stats` batch stands for: `delivered` values in place of
+/// `delivered + coalesced` offers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PollStats {
+    pub delivered: usize,
+    pub coalesced: usize,
+}
+
+// What a poll can put its values into. `room` bounds how many it claims.
+trait Bucket<V> {
+    fn room(&self) -> usize;
+
+    fn put(&mut self, value: V);
+}
+
+impl<V> Bucket<V> for Vec<V> {
+    fn room(&self) -> usize {
+        usize::MAX
+    }
+
+    fn put(&mut self, value: V) {
+        self.push(value);
+    }
+}
+
+#[cfg(feature = "smallvec")]
+impl<A: smallvec::Array> Bucket<A::Item> for SmallVec<A> {
+    fn room(&self) -> usize {
+        usize::MAX
+    }
+
+    fn put(&mut self, value: A::Item) {
+        self.push(value);
+    }
+}
+
+// Fills a slice from the front, see `Receiver::poll_slice`.
+struct SliceBucket<'a, V> {
+    out: &'a mut [Option<V>],
+    written: usize,
+}
+
+impl<V> Bucket<V> for SliceBucket<'_, V> {
+    fn room(&self) -> usize {
+        self.out.len() - self.written
+    }
+
+    fn put(&mut self, value: V) {
+        self.out[self.written] = Some(value);
+        self.written += 1;
+    }
+}
+
+/// Lazily drains a claimed range of the buffer, see `Receiver::drain`.
+pub struct Drain<'a, K, V, const N: usize>
+where
+    K: Eq + Send,
+    V: Send,
+{
+    buffer: &'a CoalescingRingBuffer<K, V, N>,
+    _reading: Raised<'a>,
+    // priority values still to be yielded ahead of the claimed slots
+    priority: usize,
+    first: usize,
+    next: usize,
+    claim_up_to: usize,
+}
+
+impl<K, V, const N: usize> Iterator for Drain<'_, K, V, N>
+where
+    K: Eq + Send,
+    V: Send,
+{
+    type Item = V;
+
+    fn next(&mut self) -> Option<V> {
+        if self.priority > 0 {
+            self.priority -= 1;
+            let lane = self.buffer.priority.as_ref();
+            return lane.and_then(PriorityLane::pop).map(|(_, val)| val);
+        }
+        while self.next < self.claim_up_to {
+            let read_index = self.next;
+            self.next += 1;
+            let val = self.buffer.take_value(self.buffer.mask(read_index));
+            // hand the slot back to the producer straight away
+            self.buffer.last_read.store(read_index, Ordering::SeqCst);
+            self.buffer.notify_producer();
+            if val.is_some() {
+                return val;
+            }
+        }
+        None
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (
+            self.priority,
+            Some(self.priority + self.claim_up_to - self.next),
+        )
+    }
+}
+
+impl<K, V, const N: usize> Drop for Drain<'_, K, V, N>
+where
+    K: Eq + Send,
+    V: Send,
+{
+    fn drop(&mut self) {
+        self.buffer.drain_priority(self.priority, drop);
+        for read_index in self.next..self.claim_up_to {
+            let index = self.buffer.mask(read_index);
+            drop(self.buffer.take_value(index));
+        }
+        self.buffer.release(self.first, self.claim_up_to);
+    }
+}
+
+impl<K, V, const N: usize> CoalescingRingBuffer<K, V, N>
+where
+    K: Eq + Send + From<u64>,
+    V: Send,
+{
+    /// Offers the value under a freshly generated key and returns that key, or `None` if the
+    /// buffer is full. Generated keys are unique, so these entries never coalesce.
+    pub(crate) fn offer_auto(&self, value: V) -> Option<u64> {
+        let key = self.next_auto_key.fetch_add(1, Ordering::Relaxed);
+        self.add(KeyHolder::NonEmpty(K::from(key)), value)
+            .ok()
+            .map(|_| key)
+    }
+}
+
+/// One line with the capacity, size, cursors and rejections, for logs.
+impl<K: Eq + Send, V: Send, const N: usize> fmt::Display for CoalescingRingBuffer<K, V, N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "capacity {}, size {}, first_write {}, next_write {}, last_read {}, rejections {}",
+            self.capacity(),
+            self.size(),
+            self.first_write.load(Ordering::SeqCst),
+            self.next_write.load(Ordering::SeqCst),
+            self.last_read.load(Ordering::SeqCst),
+            self.rejection_count()
+        )
+    }
+}
+
+/// The same figures as `Display`, leaving out the slots so that a large buffer does not flood
+/// the output. `Receiver::snapshot` has the entries.
+impl<K: Eq + Send, V: Send, const N: usize> fmt::Debug for CoalescingRingBuffer<K, V, N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CoalescingRingBuffer")
+            .field("capacity", &self.capacity())
+            .field("size", &self.size())
+            .field("first_write", &self.first_write.load(Ordering::SeqCst))
+            .field("next_write", &self.next_write.load(Ordering::SeqCst))
+            .field("last_read", &self.last_read.load(Ordering::SeqCst))
+            .field("rejection_count", &self.rejection_count())
+            .finish()
+    }
+}
+
+// Keys and values are moved between the producer and the consumer, so both have to be `Send`.
+// A pending key is also compared in place by both sides at once, the producer looking for the
+// entry to coalesce with while the consumer peeks, so sharing the buffer needs `K: Sync` too.
+// A value is only ever borrowed by one side while the other is kept out, by the handshakes
+// around pinning and excluding, so `V` does not have to be `Sync`.
+unsafe impl<K: Send, V: Send, const N: usize> Send for CoalescingRingBuffer<K, V, N> {}
+unsafe impl<K: Send + Sync, V: Send, const N: usize> Sync for CoalescingRingBuffer<K, V, N> {}
+
+pub struct Receiver<K, V, const N: usize = 0>
+where
+    V: Send,
+{
+    buffer: Arc<CoalescingRingBuffer<K, V, N>>,
+    _phantom_data: PhantomData<*mut ()>, //This to make sure we have only one thread access this
+}
+
+// shares the buffer with the sender, see the `Sync` impl above
+unsafe impl<K: Send + Sync, V: Send, const N: usize> Send for Receiver<K, V, N> {}
+
+/// A read-only view of a buffer's state, for a watchdog or a metrics thread. Unlike the
+/// handles it may be cloned and shared freely, as it can neither offer nor poll.
+pub struct StatsHandle<K, V, const N: usize = 0>
+where
+    V: Send,
+{
+    buffer: Arc<CoalescingRingBuffer<K, V, N>>,
+}
+
+impl<K, V: Send, const N: usize> Clone for StatsHandle<K, V, N> {
+    fn clone(&self) -> Self {
+        StatsHandle {
+            buffer: self.buffer.clone(),
+        }
+    }
+}
+
+impl<K: Send + Eq, V: Send, const N: usize> StatsHandle<K, V, N> {
+    pub fn size(&self) -> usize {
+        self.buffer.size()
+    }
+
+    /// See `CoalescingRingBuffer::capacity_in_bytes`.
+    pub fn capacity_in_bytes(&self) -> usize {
+        self.buffer.capacity_in_bytes()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buffer.capacity()
+    }
+
+    pub fn rejection_count(&self) -> usize {
+        self.buffer.rejection_count()
+    }
+
+    pub fn last_rejection(&self) -> Option<RejectReason> {
+        self.buffer.last_rejection()
+    }
+
+    pub fn coalesced_count(&self) -> usize {
+        self.buffer.coalesced_count()
+    }
+}
+
+impl<K: Send + Eq, V: Send, const N: usize> Receiver<K, V, N> {
+    fn new(buf: Arc<CoalescingRingBuffer<K, V, N>>) -> Self {
+        Receiver {
+            buffer: buf,
+            _phantom_data: PhantomData,
+        }
+    }
+
+    /// Everything pending, even what `RingBufferBuilder::flush_window` would hold back.
+    pub fn poll_all(&self) -> Vec<V> {
+        self.buffer.poll_all()
+    }
+
+    /// A handle that only reports the buffer's state, and may be shared across threads.
+    pub fn stats_handle(&self) -> StatsHandle<K, V, N> {
+        StatsHandle {
+            buffer: self.buffer.clone(),
+        }
+    }
+
+    pub fn poll(&self, max_items: usize) -> Vec<V> {
+        self.buffer.poll(max_items)
+    }
+
+    /// Like `poll`, but also reports how many offers were coalesced away since the previous
+    /// `poll_stats`, which gives the compression ratio of each batch.
+    pub fn poll_stats(&self, max_items: usize) -> (Vec<V>, PollStats) {
+        self.buffer.poll_stats(max_items)
+    }
+
+    /// Like `poll`, but parks the calling thread until there is at least one value to return
+    /// instead of returning an empty `Vec`. Only returns empty once the sender is gone.
+    #[cfg(feature = "std")]
+    pub fn poll_blocking(&self, max_items: usize) -> Vec<V> {
+        self.buffer.poll_blocking(max_items)
+    }
+
+    /// Like `poll_blocking`, but waits at most `timeout` and then returns whatever is there,
+    /// which may be nothing. Handy for a consumer that also has periodic housekeeping to do.
+    #[cfg(feature = "std")]
+    pub fn poll_timeout(&self, max_items: usize, timeout: Duration) -> Vec<V> {
+        self.buffer.poll_timeout(max_items, timeout)
+    }
+
+    /// Whether the sender has been dropped. Values it offered before that may still be pending.
+    pub fn is_disconnected(&self) -> bool {
+        self.buffer.is_disconnected()
+    }
+
+    /// Like `poll_all`, but returns `Err(Disconnected)` once the sender has been dropped and
+    /// everything it offered has been delivered, so a consumer loop needs no poison pill.
+    pub fn recv_all(&self) -> Result<Vec<V>, Disconnected> {
+        self.buffer.recv_all()
+    }
+
+    /// One value at a time, like `std::sync::mpsc::Receiver::recv`: blocks until a value is
+    /// pending and fails with `Disconnected` once the sender is gone and nothing is left.
+    #[cfg(feature = "std")]
+    pub fn recv(&self) -> Result<V, Disconnected> {
+        self.buffer.recv()
+    }
+
+    /// Non-blocking counterpart of `recv`, failing with `TryRecvError::Empty` when nothing is
+    /// pending and with `TryRecvError::Disconnected` once the sender is gone as well.
+    pub fn try_recv(&self) -> Result<V, TryRecvError> {
+        self.buffer.try_recv()
+    }
+
+    /// Takes values one at a time until nothing is pending, without blocking, like
+    /// `crossbeam_channel::Receiver::try_iter`. Later offers are picked up if they come in
+    /// before the iterator runs dry.
+    pub fn try_iter(&self) -> impl Iterator<Item = V> + '_ {
+        iter::from_fn(move || self.try_recv().ok())
+    }
+
+    /// Values dropped by polls for having expired, see `RingBufferBuilder::ttl`.
+    pub fn expired_count(&self) -> usize {
+        self.buffer.expired_count()
+    }
+
+    #[cfg(feature = "std")]
+    pub(crate) fn register_waker(&self, waker: &Waker) -> bool {
+        self.buffer.register_waker(waker)
+    }
+
+    // Whether a poll would return something, or the sender is gone.
+    #[cfg(feature = "std")]
+    pub(crate) fn is_ready(&self) -> bool {
+        self.buffer.is_ripe() || self.buffer.is_disconnected()
+    }
+
+    // How long until the flush window lets the oldest entry through, if it is holding it back.
+    #[cfg(feature = "std")]
+    pub(crate) fn ready_in(&self) -> Option<Duration> {
+        self.buffer.until_ripe()
+    }
+
+    /// Allocation free form of `poll`: clears `bucket`, fills it with up to `max_items` values
+    /// and returns how many there were. Reuse the same `Vec` across polls.
+    pub fn poll_into(&self, bucket: &mut Vec<V>, max_items: usize) -> usize {
+        self.buffer.poll_into(bucket, max_items)
+    }
+
+    /// Like `poll_into` but into a slice the caller owns, so nothing is ever allocated: up to
+    /// `out.len()` values go to the front of `out` as `Some`, the rest of it is set to `None`,
+    /// and the number of values written is returned.
+    ///
+    /// ```
+    /// # use coalescing_buffer::ring::new_ring_buffer;
+    /// let (sender, receiver) = new_ring_buffer::<i32, i32>(8);
+    /// sender.offer(1, 10);
+    /// sender.offer(2, 20);
+    /// let mut out = [None; 4];
+    /// assert_eq!(2, receiver.poll_slice(&mut out));
+    /// assert_eq!([Some(10), Some(20), None, None], out);
+    /// ```
+    pub fn poll_slice(&self, out: &mut [Option<V>]) -> usize {
+        self.buffer.poll_slice(out)
+    }
+
+    /// Like `poll`, but returns a `SmallVec` that keeps batches of up to 16 values off the
+    /// heap, for consumers that mostly see a handful of hot keys per poll. Needs the
+    /// `smallvec` feature.
+    #[cfg(feature = "smallvec")]
+    pub fn poll_small(&self, max_items: usize) -> SmallVec<[V; 16]> {
+        self.buffer.poll_small(max_items)
+    }
+
+    /// Iterator form of `poll`: up to `max_items` values are claimed up front and taken out one
+    /// by one as the iterator is advanced, so nothing is collected. Values that were claimed
+    /// but not yielded are dropped along with the iterator.
+    ///
+    /// ```
+    /// # use coalescing_buffer::ring::new_ring_buffer;
+    /// let (sender, receiver) = new_ring_buffer::<i32, i32>(8);
+    /// sender.offer(1, 1);
+    /// sender.offer_value_only(-1);
+    /// sender.offer(2, 2);
+    /// let before_pill: Vec<i32> = receiver.drain(8).take_while(|&v| v != -1).collect();
+    /// assert_eq!(vec![1], before_pill);
+    /// ```
+    pub fn drain(&self, max_items: usize) -> Drain<'_, K, V, N> {
+        self.buffer.drain_iter(max_items)
+    }
+
+    /// Drains everything like `poll_all`, newest entry first.
+    pub fn poll_all_newest_first(&self) -> Vec<V> {
+        self.buffer.poll_all_newest_first()
+    }
+
+    /// Push-style counterpart of `poll`: `f` is called with each claimed value in order and
+    /// no intermediate `Vec` is built. Returns the number of values handed to `f`.
+    pub fn poll_for_each<F>(&self, max_items: usize, f: F) -> usize
+    where
+        F: FnMut(V),
+    {
+        self.buffer.poll_for_each(max_items, f)
+    }
+
+    /// Like `poll_for_each`, but the cursors move on before every call to `f` rather than
+    /// after the batch, so a panic in `f` loses only the value it was given and leaves the
+    /// rest pending for the next poll. Costs a claim per value.
+    pub fn for_each_drained<F>(&self, max_items: usize, f: F) -> usize
+    where
+        F: FnMut(V),
+    {
+        self.buffer.for_each_drained(max_items, f)
+    }
+
+    /// Polls values until their combined weight, as reported by `size_of`, would exceed
+    /// `max_bytes`. A single oversized value is still returned on its own.
+    pub fn poll_bounded_bytes<F>(&self, max_bytes: usize, size_of: F) -> Vec<V>
+    where
+        F: Fn(&V) -> usize,
+    {
+        self.buffer.poll_bounded_bytes(max_bytes, size_of)
+    }
+
+    /// Drains everything still buffered along with the keys it was offered under. Keyless
+    /// entries come back with `None`. The buffer is empty and reusable afterwards.
+    pub fn take_all(&self) -> Vec<(Option<K>, V)>
+    where
+        K: Clone,
+    {
+        self.buffer.take_all()
+    }
+
+    /// Like `poll`, but pairs each value with the key it was offered under, or `None` if it
+    /// was offered with `offer_value_only`. Ordering is the same as for `poll`.
+    pub fn poll_keyed(&self, max_items: usize) -> Vec<(Option<K>, V)>
+    where
+        K: Clone,
+    {
+        self.buffer.poll_keyed(max_items)
+    }
+
+    /// Allocation-free form of `poll_keyed`: pairs are appended to `out`, which is not cleared
+    /// first, so one vector can be reused across polls. A slot that coalesced several offers
+    /// yields its key once, with the last value. Returns the number of pairs appended.
+    pub fn poll_keyed_into(&self, out: &mut Vec<(Option<K>, V)>, max_items: usize) -> usize
+    where
+        K: Clone,
+    {
+        self.buffer.poll_keyed_into(out, max_items)
+    }
+
+    /// Like `poll`, but pairs each value with the sequence number of the write that left it in
+    /// the buffer: the producer numbers every value it writes, appended or coalesced, from 0.
+    /// A gap in the numbers is the count of updates that were coalesced away in between.
+    ///
+    /// ```
+    /// # use coalescing_buffer::ring::new_ring_buffer;
+    /// let (sender, receiver) = new_ring_buffer::<&str, u32>(8);
+    /// sender.offer("VOD", 1);
+    /// sender.offer("BP", 2);
+    /// sender.offer("VOD", 3);
+    /// assert_eq!(vec![(2, 3), (1, 2)], receiver.poll_seq(8));
+    /// ```
+    pub fn poll_seq(&self, max_items: usize) -> Vec<(u64, V)> {
+        self.buffer.poll_seq(max_items)
+    }
+
+    pub fn size(&self) -> usize {
+        self.buffer.size()
+    }
+
+    /// `size`, or `None` if no consistent read was had within `max_retries` attempts.
+    pub fn try_size(&self, max_retries: usize) -> Option<usize> {
+        self.buffer.try_size(max_retries)
+    }
+
+    /// The weight of the unread values under `RingBufferBuilder::with_byte_capacity`, else 0.
+    pub fn byte_size(&self) -> usize {
+        self.buffer.byte_size()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buffer.capacity()
+    }
+
+    /// `(last_read, first_write, next_write)` read consistently, for diagnostics and custom
+    /// flow control.
+    pub fn cursors(&self) -> (usize, usize, usize) {
+        self.buffer.cursors()
+    }
+
+    pub fn requested_capacity(&self) -> usize {
+        self.buffer.requested_capacity()
+    }
+
+    /// The value pending for `key`, cloned, or `None` if there is none. Nothing is consumed
+    /// and the cursors are left alone, so this is safe to call while the producer coalesces.
+    /// A value that `offer_with` is merging at that very moment is missed.
+    pub fn peek(&self, key: &K) -> Option<V>
+    where
+        V: Clone,
+    {
+        self.buffer.peek(key)
+    }
+
+    /// Clones everything pending, in offer order, without consuming it. Meant for debugging
+    /// and state capture; the producer is not held up beyond the clone of each value.
+    pub fn snapshot(&self) -> BufferSnapshot<K, V>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        self.buffer.snapshot()
+    }
+
+    /// Hands every pending entry to `f` by reference, in offer order, without cloning or
+    /// consuming anything, e.g. to log the contents during a hang. Keyless entries get `None`.
+    pub fn for_each_live<F>(&self, f: F)
+    where
+        F: FnMut(Option<&K>, &V),
+    {
+        self.buffer.for_each_live(f)
+    }
+
+    /// The keys with a value pending, in offer order. Keyless entries and the holes left by
+    /// `Sender::remove` are skipped. Only reads, like `for_each_live`, which it is built on.
+    pub fn live_keys(&self) -> Vec<K>
+    where
+        K: Clone,
+    {
+        let mut keys = Vec::new();
+        self.buffer
+            .for_each_live(|key, _| keys.extend(key.cloned()));
+        keys
+    }
+
+    /// Like `poll`, but guarantees at most one value per key: should several entries for a key
+    /// be pending only the latest is returned. Keyless values come back in the second `Vec`.
+    #[cfg(feature = "std")]
+    pub fn poll_compacted(&self, max_items: usize) -> (Vec<(K, V)>, Vec<V>)
+    where
+        K: Hash + Clone,
+    {
+        self.buffer.poll_compacted(max_items)
+    }
+
+    /// How long each value returned by the latest poll spent between offer and poll, in offer
+    /// order. Needs `RingBufferBuilder::measure_latency`, otherwise it is always empty.
+    #[cfg(feature = "std")]
+    pub fn last_poll_latencies(&self) -> Vec<Duration> {
+        self.buffer.last_poll_latencies()
+    }
+
+    /// Closes the buffer and returns every value still in it, keyed and keyless, in offer
+    /// order. Meant for shutdown: nothing is left behind and the sender sees `Closed` from
+    /// then on. Stop the producer first, as a value offered while this runs may be lost.
+    pub fn into_remaining(self) -> Vec<V> {
+        self.buffer.close();
+        self.buffer.poll_all()
+    }
+}
+
+impl<K, V, const N: usize> Drop for Receiver<K, V, N>
+where
+    V: Send,
+{
+    fn drop(&mut self) {
+        self.buffer.closed.store(true, Ordering::SeqCst);
+        self.buffer.notify_producer();
+    }
+}
+
+impl<K, V, const N: usize> Drop for Sender<K, V, N>
+where
+    V: Send,
+{
+    fn drop(&mut self) {
+        self.buffer.sender_dropped.store(true, Ordering::SeqCst);
+        self.buffer.notify_consumer();
+    }
+}
+
+/// Best-effort bulk offer: every pair is offered in turn, and those rejected by a full buffer
+/// are dropped, while later pairs that coalesce still get in. Use `offer_all` to find out how
+/// many were accepted.
+impl<K: Send + Eq, V: Send, const N: usize> Extend<(K, V)> for Sender<K, V, N> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, items: I) {
+        for (key, value) in items {
+            self.offer(key, value);
+        }
+    }
+}
+
+pub struct Sender<K, V, const N: usize = 0>
+where
+    V: Send,
+{
+    buffer: Arc<CoalescingRingBuffer<K, V, N>>,
+    _phantom_data: PhantomData<*mut ()>, //This to make sure we have only one thread access this
+}
+
+unsafe impl<K: Send + Sync, V: Send, const N: usize> Send for Sender<K, V, N> {}
+
+impl<K: Send + Eq, V: Send, const N: usize> Sender<K, V, N>
+where
+    V: Send,
+{
+    fn new(buf: Arc<CoalescingRingBuffer<K, V, N>>) -> Self {
+        Sender {
+            buffer: buf,
+            _phantom_data: PhantomData,
+        }
+    }
+
+    pub fn offer(&self, key: K, value: V) -> bool {
+        self.buffer.offer(key, value)
+    }
+
+    /// Offers every item in turn and returns how many were accepted. Stops at the first
+    /// rejection: that item is dropped and the rest of `items` is left unconsumed. Within a
+    /// batch, a later item for a key still pending replaces the earlier one, exactly as with
+    /// separate offers.
+    pub fn offer_all<I>(&self, items: I) -> usize
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        self.buffer.offer_all(items)
+    }
+
+    /// All or nothing form of `offer_all`, e.g. for a set of order book levels that only make
+    /// sense together: `false` means none of `items` was offered, as the buffer had no room for
+    /// every item that does not coalesce with a pending key. Polls wait while the batch goes in.
+    pub fn offer_batch_atomic(&self, items: &[(K, V)]) -> bool
+    where
+        K: Clone,
+        V: Clone,
+    {
+        self.buffer.offer_batch_atomic(items)
+    }
+
+    /// Fallible form of `offer`. A rejected key and value are handed back in the error.
+    pub fn try_offer(&self, key: K, value: V) -> Result<(), OfferError<K, V>> {
+        self.buffer.try_offer(key, value)
+    }
+
+    /// Turns the sender into a function with the shape of `crossbeam_channel::Sender::send`
+    /// for `(key, value)` messages, so that the buffer can stand in for a channel as a
+    /// coalescing stage. It fails where `try_offer` does, rather than blocking when full.
+    ///
+    /// The messages carry their key because coalescing needs one, and the result is returned
+    /// like `send` returns it, where a plain `Fn(V)` would lose rejections without a trace.
+    #[cfg(feature = "crossbeam-channel")]
+    pub fn into_crossbeam_sink(self) -> impl Fn((K, V)) -> Result<(), OfferError<K, V>> {
+        move |(key, value)| self.try_offer(key, value)
+    }
+
+    /// Wraps the sender so that every value is offered under the key `key_of` derives from
+    /// it, which rules out passing a value with some other value's key.
+    ///
+    /// ```
+    /// # use coalescing_buffer::ring::new_ring_buffer;
+    /// let (sender, receiver) = new_ring_buffer::<u32, (u32, f64)>(8);
+    /// let sender = sender.with_key_fn(|&(instrument_id, _)| instrument_id);
+    /// sender.offer((7, 101.5));
+    /// sender.offer((7, 101.25));
+    /// assert_eq!(vec![(7, 101.25)], receiver.poll_all());
+    /// ```
+    pub fn with_key_fn<F>(self, key_of: F) -> KeyedSender<K, V, F, N>
+    where
+        F: Fn(&V) -> K,
+    {
+        KeyedSender {
+            sender: self,
+            key_of,
+        }
+    }
+
+    /// Ends the stream without an in-band sentinel: the receiver is woken, drains what is
+    /// pending and then sees the sender disconnect, e.g. `recv_all` fails with `Disconnected`.
+    /// Same as dropping the sender, but says so.
+    pub fn close(self) {
+        drop(self)
+    }
+
+    /// `try_offer` under the name `std::sync::mpsc::Sender` uses, to ease switching over.
+    pub fn send(&self, key: K, value: V) -> Result<(), OfferError<K, V>> {
+        self.try_offer(key, value)
+    }
+
+    /// Backpressure instead of rejection: waits for the consumer to free a slot while the
+    /// buffer is full, unless the key is pending and the offer coalesces. Only fails, with
+    /// `OfferError::Closed`, once the receiver is gone.
+    #[cfg(feature = "std")]
+    pub fn offer_blocking(&self, key: K, value: V) -> Result<(), OfferError<K, V>> {
+        self.buffer.offer_blocking(key, value)
+    }
+
+    /// Like `try_offer`, but returns the unread value the offer replaced when it coalesced,
+    /// e.g. to reuse or log a superseded snapshot. `Ok(None)` means it took a slot of its own.
+    pub fn offer_replace(&self, key: K, value: V) -> Result<Option<V>, OfferError<K, V>> {
+        self.buffer.offer_replace(key, value)
+    }
+
+    /// Whether an offer for `key` would coalesce with a pending entry instead of taking a slot.
+    /// Can be stale the instant it returns, as the consumer may poll the entry meanwhile.
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.buffer.contains_key(key)
+    }
+
+    /// Pulls the pending value for `key` back before it is read, e.g. for a halted instrument.
+    /// `None` if there is none, including when the consumer has just polled it.
+    pub fn remove(&self, key: &K) -> Option<V> {
+        self.buffer.remove(key)
+    }
+
+    /// Pulls back every pending value that `pred` picks, e.g. all instruments of a halted
+    /// sector. `pred` gets the key, `None` for a keyless value, and the value. Returns them
+    /// oldest first; the rest are still read in order. Blocks the consumer during the scan.
+    pub fn drain_filter<F>(&self, pred: F) -> Vec<V>
+    where
+        F: Fn(Option<&K>, &V) -> bool,
+    {
+        self.buffer.drain_filter(pred)
+    }
+
+    /// Offers the value and blocks until the consumer has read it or `timeout` elapses,
+    /// returning whether it was read. Lets a producer know an update was actually delivered.
+    #[cfg(feature = "std")]
+    pub fn offer_and_wait_read(&self, key: K, value: V, timeout: Duration) -> bool {
+        self.buffer.offer_and_wait_read(key, value, timeout)
+    }
+
+    /// Offers the value, dropping the oldest unread entry if the buffer is full and the key
+    /// is not already pending. Never rejects.
+    pub fn offer_overwrite_oldest(&self, key: K, value: V) -> bool {
+        self.buffer.offer_overwrite_oldest(key, value)
+    }
+
+    pub fn offer_value_only(&self, value: V) -> bool {
+        self.buffer.offer_value_only(value)
+    }
+
+    /// Fallible form of `offer_value_only`, for use with `?`.
+    pub fn try_offer_value_only(&self, value: V) -> Result<(), Error> {
+        self.buffer.try_offer_value_only(value)
+    }
+
+    /// `offer_value_only`, returning the sequence number that `Receiver::poll_seq` reports for
+    /// the value, or `None` if it was rejected.
+    pub fn offer_value_only_seq(&self, value: V) -> Option<u64> {
+        self.buffer.offer_value_only_seq(value)
+    }
+
+    /// Offers the value, merging it into a pending value for the same key with `merge` rather
+    /// than replacing it, e.g. to keep a running sum. If the consumer read the pending value
+    /// first, `value` is offered unmerged as a new entry.
+    pub fn offer_with<F>(&self, key: K, value: V, merge: F) -> bool
+    where
+        F: FnOnce(&V, V) -> V,
+    {
+        self.buffer.offer_with(key, value, merge)
+    }
+
+    /// Updates the pending value for the key in place, or offers `init` when there is none,
+    /// e.g. to count events per key. If the consumer read the pending value first, `init` is
+    /// offered as a new entry and `update` is not applied.
+    ///
+    /// ```
+    /// # use coalescing_buffer::ring::new_ring_buffer;
+    /// let (sender, receiver) = new_ring_buffer::<&str, u32>(8);
+    /// for _ in 0..3 {
+    ///     sender.offer_or_update("clicks", 1, |count| *count += 1);
+    /// }
+    /// assert_eq!(vec![3], receiver.poll_all());
+    /// ```
+    pub fn offer_or_update<F>(&self, key: K, init: V, update: F) -> bool
+    where
+        F: FnOnce(&mut V),
+    {
+        self.buffer.offer_or_update(key, init, update)
+    }
+
+    /// Offers a keyless value, dropping the oldest unread entry when there is no room. Keyless
+    /// values never coalesce, so this trades backlog for the latest data instead of rejecting.
+    pub fn offer_value_only_overwrite_oldest(&self, value: V) -> bool {
+        self.buffer.offer_value_only_overwrite_oldest(value)
+    }
+
+    /// Holds back `n` slots that only `offer_control` may use. Every other offer is rejected
+    /// `n` slots earlier, which guarantees that up to `n` control messages, such as a poison
+    /// pill at shutdown, can be delivered even while data saturates the buffer.
+    ///
+    /// Panics if `n` is not smaller than the capacity.
+    pub fn reserve_control_slots(&mut self, n: usize) {
+        self.buffer.reserve_control_slots(n)
+    }
+
+    /// Offers a keyless control message which may use the reserved control slots.
+    pub fn offer_control(&self, value: V) -> bool {
+        self.buffer.offer_control(value)
+    }
+
+    /// Offers a keyless value that jumps the queue: the next poll returns it before any entry
+    /// already in the buffer, keyed or not, and after earlier priority values. It never
+    /// coalesces, and only fits in the slots set aside with `RingBufferBuilder::priority_slots`.
+    /// Without any, it always fails with `Error::InvalidCapacity(0)`.
+    ///
+    /// ```
+    /// # use coalescing_buffer::ring::{Error, RingBufferBuilder};
+    /// let (sender, receiver) = RingBufferBuilder::<&str, &str>::new(4)
+    ///     .priority_slots(1)
+    ///     .build();
+    /// sender.offer("VOD", "quote");
+    /// assert_eq!(Ok(()), sender.offer_priority("halt"));
+    /// assert_eq!(Err(Error::Full), sender.offer_priority("resume"));
+    /// assert_eq!(vec!["halt", "quote"], receiver.poll_all());
+    /// ```
+    pub fn offer_priority(&self, value: V) -> Result<(), Error> {
+        self.buffer.offer_priority(value)
+    }
+
+    /// Applies a batch of latest values, coalescing each with anything still pending. Entries
+    /// are offered in key order; returns `(accepted, rejected)`.
+    #[cfg(feature = "std")]
+    pub fn offer_map(&self, updates: &HashMap<K, V>) -> (usize, usize)
+    where
+        K: Hash + Ord + Clone,
+        V: Clone,
+    {
+        self.buffer.offer_map(updates)
+    }
+
+    pub fn size(&self) -> usize {
+        self.buffer.size()
+    }
+
+    /// `size`, or `None` if no consistent read was had within `max_retries` attempts.
+    pub fn try_size(&self, max_retries: usize) -> Option<usize> {
+        self.buffer.try_size(max_retries)
+    }
+
+    /// The weight of the unread values under `RingBufferBuilder::with_byte_capacity`, else 0.
+    pub fn byte_size(&self) -> usize {
+        self.buffer.byte_size()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buffer.capacity()
+    }
+
+    /// `(last_read, first_write, next_write)` read consistently, for diagnostics and custom
+    /// flow control.
+    pub fn cursors(&self) -> (usize, usize, usize) {
+        self.buffer.cursors()
+    }
+
+    pub fn requested_capacity(&self) -> usize {
+        self.buffer.requested_capacity()
+    }
+
+    pub fn rejection_count(&self) -> usize {
+        self.buffer.rejection_count()
+    }
+
+    pub fn overwritten_count(&self) -> usize {
+        self.buffer.overwritten_count()
+    }
+
+    /// How many offers were coalesced into a pending entry rather than taking a slot of their
+    /// own. Keyless offers never are.
+    pub fn coalesced_count(&self) -> usize {
+        self.buffer.coalesced_count()
+    }
+
+    /// Capacity, size, rejections, coalesced offers and the high-water mark of the size, for
+    /// dashboards.
+    pub fn metrics(&self) -> BufferMetrics {
+        self.buffer.metrics()
+    }
+
+    /// A handle that only reports the buffer's state, e.g. for a watchdog thread that must not
+    /// be able to offer.
+    ///
+    /// ```
+    /// # use coalescing_buffer::ring::new_ring_buffer;
+    /// let (sender, _receiver) = new_ring_buffer::<u32, u32>(4);
+    /// let stats = sender.stats_handle();
+    /// sender.offer(1, 10);
+    /// let watchdog = std::thread::spawn(move || stats.size());
+    /// assert_eq!(1, watchdog.join().unwrap());
+    /// ```
+    pub fn stats_handle(&self) -> StatsHandle<K, V, N> {
+        StatsHandle {
+            buffer: self.buffer.clone(),
+        }
+    }
+
+    /// Whether the receiver is gone, after which nothing offered will ever be read. Every
+    /// offer then fails straight away, `try_offer` with `OfferError::Closed`.
+    pub fn is_closed(&self) -> bool {
+        self.buffer.is_closed()
+    }
+
+    /// Discards everything pending and resets the counters, so the buffer can be reused as if
+    /// it were new. Waits for a poll in progress to finish, like `try_grow`.
+    pub fn clear(&mut self) {
+        self.buffer.clear()
+    }
+
+    /// Doubles the capacity, keeping everything pending in order, and returns the new capacity.
+    /// Waits for a poll in progress to finish and blocks polls while it runs.
+    pub fn try_grow(&mut self) -> Result<usize, Error> {
+        self.buffer.try_grow()
+    }
+
+    /// Grows the buffer to at least `new_capacity` in one go, rounded up to a power of two
+    /// unless it was built with an exact capacity, and returns the new capacity.
+    pub fn grow(&mut self, new_capacity: usize) -> Result<usize, Error> {
+        self.buffer.grow(new_capacity)
+    }
+
+    /// Rejections of keyless offers only. These always need a slot of their own since they
+    /// never coalesce.
+    pub fn value_only_rejection_count(&self) -> usize {
+        self.buffer.value_only_rejection_count()
+    }
+
+    /// Why the most recent offer was rejected, which tells a full buffer from a closed one or
+    /// a value over the byte budget. The counts per reason are in `metrics`.
+    pub fn last_rejection(&self) -> Option<RejectReason> {
+        self.buffer.last_rejection()
+    }
+}
+
+impl<K: Send + Eq + From<u64>, V: Send, const N: usize> Sender<K, V, N> {
+    /// Offers the value under a generated key which is returned on success. Since every
+    /// generated key is unique these entries never coalesce, but unlike `offer_value_only`
+    /// they can still be addressed by key later on.
+    pub fn offer_auto(&self, value: V) -> Option<u64> {
+        self.buffer.offer_auto(value)
+    }
+}
+
+/// A `Sender` that works out the key of each value itself, see `Sender::with_key_fn`.
+pub struct KeyedSender<K, V, F, const N: usize = 0>
+where
+    V: Send,
+{
+    sender: Sender<K, V, N>,
+    key_of: F,
+}
+
+impl<K: Send + Eq, V: Send, F: Fn(&V) -> K, const N: usize> KeyedSender<K, V, F, N> {
+    pub fn offer(&self, value: V) -> bool {
+        self.sender.offer((self.key_of)(&value), value)
+    }
+
+    /// Fallible form of `offer`, handing back the derived key along with the value.
+    pub fn try_offer(&self, value: V) -> Result<(), OfferError<K, V>> {
+        self.sender.try_offer((self.key_of)(&value), value)
+    }
+
+    /// The sender underneath, for everything but plain offers.
+    pub fn sender(&self) -> &Sender<K, V, N> {
+        &self.sender
+    }
+
+    pub fn into_sender(self) -> Sender<K, V, N> {
+        self.sender
+    }
+}
+
+type Handles<K, V, const N: usize = 0> = (Sender<K, V, N>, Receiver<K, V, N>);
+
+/// Builds a ring buffer with optional behaviour that `new_ring_buffer` leaves off.
+///
+/// ```
+/// use coalescing_buffer::ring::{RingBufferBuilder, StallAction};
+/// use std::time::Duration;
+///
+/// let (sender, receiver) = RingBufferBuilder::<i32, i32>::new(8)
+///     .stall_watchdog(Duration::from_secs(5), |stalled_for| {
+///         eprintln!("consumer stuck for {:?}", stalled_for);
+///         StallAction::Clear
+///     })
+///     .build();
+/// ```
+pub struct RingBufferBuilder<K, V> {
+    capacity: usize,
+    #[cfg(feature = "std")]
+    watchdog: Option<Watchdog>,
+    #[cfg(feature = "std")]
+    measure_latency: bool,
+    #[cfg(feature = "std")]
+    flush_window: Option<Duration>,
+    #[cfg(feature = "std")]
+    ttl: Option<Duration>,
+    // one for each side, see `CoalescingRingBuffer::data_wait`
+    #[cfg(feature = "std")]
+    wait_strategy: Option<(Box<dyn WaitStrategy>, Box<dyn WaitStrategy>)>,
+    grow_limit: Option<usize>,
+    exact_capacity: bool,
+    key_index: Option<Box<dyn KeyIndex<K>>>,
+    policy: CoalescePolicy,
+    priority_slots: usize,
+    byte_limit: Option<ByteLimit<V>>,
+    _phantom_data: PhantomData<(K, V)>,
+}
+
+impl<K: Send + Eq, V: Send> RingBufferBuilder<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        RingBufferBuilder {
+            capacity,
+            #[cfg(feature = "std")]
+            watchdog: None,
+            #[cfg(feature = "std")]
+            measure_latency: false,
+            #[cfg(feature = "std")]
+            flush_window: None,
+            #[cfg(feature = "std")]
+            ttl: None,
+            #[cfg(feature = "std")]
+            wait_strategy: None,
+            grow_limit: None,
+            exact_capacity: false,
+            key_index: None,
+            policy: CoalescePolicy::default(),
+            priority_slots: 0,
+            byte_limit: None,
+            _phantom_data: PhantomData,
+        }
+    }
+
+    /// Calls `on_stall` on the producer thread once offers have been rejected as full for
+    /// longer than `timeout`, with the time spent stalled so far. It fires once per stall, and
+    /// returning `StallAction::Clear` drops the unread backlog, e.g. for a crashed consumer.
+    #[cfg(feature = "std")]
+    pub fn stall_watchdog<F>(mut self, timeout: Duration, on_stall: F) -> Self
+    where
+        F: FnMut(Duration) -> StallAction + Send + 'static,
+    {
+        self.watchdog = Some(Watchdog {
+            timeout,
+            on_stall: RefCell::new(Box::new(on_stall)),
+            stalled_since: Cell::new(None),
+            reported: Cell::new(false),
+        });
+        self
+    }
+
+    /// Stamps every offer so that `Receiver::last_poll_latencies` can report how long each
+    /// polled value waited in the buffer. Off by default as it costs a clock read per offer.
+    #[cfg(feature = "std")]
+    pub fn measure_latency(mut self) -> Self {
+        self.measure_latency = true;
+        self
+    }
+
+    /// Holds every entry back from the polls until it has been in the buffer for `window`, so
+    /// that updates to a key coming in faster than that reach the consumer at most once per
+    /// window. Coalescing does not restart the wait. Every way to read looks at the window,
+    /// from `poll` to `try_recv`, `drain` and `poll_bounded_bytes`, and the blocking ones sleep
+    /// until it lets the oldest entry through. The exceptions return everything pending:
+    /// `poll_all`, with `recv_all`, `poll_all_newest_first` and the `ReceiverStream` built on
+    /// it, `take_all` and `into_remaining`. Priority values are never held back.
+    #[cfg(feature = "std")]
+    pub fn flush_window(mut self, window: Duration) -> Self {
+        self.flush_window = Some(window);
+        self
+    }
+
+    /// Drops, rather than returns, entries that have not been written to for `ttl` by the
+    /// time a poll gets to them. Coalescing renews an entry. Expiry is lazy: an expired entry
+    /// keeps its slot, and counts towards `size`, until the consumer passes it, and is then
+    /// counted by `expired_count`. Priority values never expire.
+    #[cfg(feature = "std")]
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Picks how the blocking calls wait for the other side, e.g. `BusySpin` for the lowest
+    /// latency. By default they park on a condition variable, like `Blocking`, which is only
+    /// signalled when someone is waiting; a strategy is signalled on every store and release.
+    /// The consumer waits on `strategy` and the producer on a clone of it.
+    #[cfg(feature = "std")]
+    pub fn wait_strategy<W>(mut self, strategy: W) -> Self
+    where
+        W: WaitStrategy + Clone + 'static,
+    {
+        let room_wait = Box::new(strategy.clone());
+        self.wait_strategy = Some((Box::new(strategy), room_wait));
+        self
+    }
+
+    /// Doubles the capacity instead of rejecting an offer when the buffer is full, until it
+    /// would exceed `max_capacity`. Each grow briefly blocks the consumer.
+    pub fn grow_when_full(mut self, max_capacity: usize) -> Self {
+        self.grow_limit = Some(next_power_of_two(cmp::max(max_capacity, 1)));
+        self
+    }
+
+    /// Replaces the capacity given to `new`, e.g. for a builder configured elsewhere.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Whether to use exactly the requested capacity rather than rounding it up to a power of
+    /// two, see `CoalescingRingBuffer::with_exact_capacity`. Off by default.
+    pub fn exact_capacity(mut self, exact: bool) -> Self {
+        self.exact_capacity = exact;
+        self
+    }
+
+    /// Picks which value wins when an offer coalesces, `CoalescePolicy::KeepLast` by default.
+    /// Explicit merges with `offer_with` are not affected.
+    pub fn coalesce_policy(mut self, policy: CoalescePolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Sets aside `n` slots, on top of the capacity, for values offered with `offer_priority`.
+    /// None by default, in which case every priority offer fails.
+    pub fn priority_slots(mut self, n: usize) -> Self {
+        self.priority_slots = n;
+        self
+    }
+
+    /// Caps the buffer by the total weight of its values as well as by their number, e.g. the
+    /// bytes of payloads that vary wildly in size. An offer that would append past `max_bytes`
+    /// is rejected, while a coalescing offer only moves the total by the difference between
+    /// the new weight and the old. `weigh` must give the same weight for a value every time.
+    /// Priority values are not counted.
+    pub fn with_byte_capacity<F>(mut self, max_bytes: usize, weigh: F) -> Self
+    where
+        F: Fn(&V) -> usize + Send + Sync + 'static,
+    {
+        self.byte_limit = Some(ByteLimit {
+            max_bytes,
+            weigh: Box::new(weigh),
+            bytes: AtomicUsize::new(0),
+        });
+        self
+    }
+
+    pub fn build(self) -> Handles<K, V> {
+        let mut buffer = if self.exact_capacity {
+            CoalescingRingBuffer::with_exact_capacity(self.capacity)
+        } else {
+            CoalescingRingBuffer::new(self.capacity)
+        };
+        buffer.grow_limit = self.grow_limit;
+        buffer.policy = self.policy;
+        if self.priority_slots > 0 {
+            buffer.priority = Some(PriorityLane::new(self.priority_slots));
+        }
+        buffer.key_index = self.key_index.map(RefCell::new);
+        buffer.byte_limit = self.byte_limit;
+        #[cfg(feature = "std")]
+        {
+            buffer.watchdog = self.watchdog;
+            if let Some((data_wait, room_wait)) = self.wait_strategy {
+                buffer.data_wait = Some(data_wait);
+                buffer.room_wait = Some(room_wait);
+            }
+            if self.measure_latency {
+                buffer.latency = Some(LatencyTracker::new());
+            }
+            buffer.flush_window = self.flush_window.map(|window| FlushWindow {
+                window,
+                epoch: Instant::now(),
+            });
+            let epoch = buffer
+                .latency
+                .as_ref()
+                .map_or_else(Instant::now, |latency| latency.epoch);
+            buffer.expiry = self.ttl.map(|ttl| Expiry { ttl, epoch });
+            let stamped = self.measure_latency || self.ttl.is_some();
+            if stamped || self.flush_window.is_some() {
+                let timed = self.flush_window.is_some();
+                *buffer.slots.get_mut() = Slots::new(buffer.capacity(), stamped, timed);
+            }
+        }
+        buffer.split()
+    }
+
+    /// Same as `build` but returns `Error::InvalidCapacity` for a zero capacity, or
+    /// one too large to round up to a power of two.
+    pub fn try_build(self) -> Result<Handles<K, V>, Error> {
+        let rounds_up = self.exact_capacity || self.capacity.checked_next_power_of_two().is_some();
+        if self.capacity == 0 || !rounds_up {
+            return Err(Error::InvalidCapacity(self.capacity));
+        }
+        Ok(self.build())
+    }
+}
+
+#[cfg(feature = "std")]
+impl<K: Send + Hash + Eq + Clone + 'static, V: Send> RingBufferBuilder<K, V> {
+    /// Keeps a map from pending keys to their slots, hashed with `hash_builder`, so that an
+    /// offer finds the entry to coalesce with in O(1) instead of scanning every pending slot.
+    /// Worth it for large buffers, at the cost of a key clone and a couple of map updates per
+    /// stored entry. `RandomState` is the standard choice, and a faster hasher than SipHash
+    /// pays off for small integer keys.
+    pub fn with_hash_index<S>(mut self, hash_builder: S) -> Self
+    where
+        S: BuildHasher + Send + 'static,
+    {
+        self.key_index = Some(Box::new(HashKeyIndex {
+            positions: HashMap::with_hasher(hash_builder),
+        }));
+        self
+    }
+
+    /// Same as `with_hash_index` with the standard hasher.
+    #[deprecated(note = "use `with_hash_index(RandomState::new())` instead")]
+    pub fn index_keys(self) -> Self {
+        self.with_hash_index(RandomState::new())
+    }
+}
+
+/// Creates a ring buffer and provides a sender(to produce) and a receiver(consumer) to send/receive
+/// data of the buffer. Sender and Receiver can only be access by one thread
+///
+/// `let (sender, receiver) = new_ring_buffer(25);`
+///
+/// Values are moved into the buffer and never cloned, so `V` need not be `Clone`. For large
+/// payloads use `Box<V>` or `Arc<V>` as the value type: `Option` of either is pointer sized,
+/// which keeps the slots lock-free.
+///
+pub fn new_ring_buffer<K: Send + Eq, V: Send>(capacity: usize) -> (Sender<K, V>, Receiver<K, V>) {
+    RingBufferBuilder::new(capacity).build()
+}
+
+/// Same as `new_ring_buffer(N)` for a capacity that is known up front, which has to be a power
+/// of two: anything else fails to compile. The slots are kept inline in the buffer, see
+/// `CoalescingRingBuffer::new_fixed`.
+///
+/// `let (sender, receiver) = new_fixed_ring_buffer::<i32, i32, 64>();`
+///
+/// ```compile_fail
+/// let (sender, receiver) = coalescing_buffer::ring::new_fixed_ring_buffer::<i32, i32, 12>();
+/// ```
+pub fn new_fixed_ring_buffer<K: Send + Eq, V: Send, const N: usize>() -> Handles<K, V, N> {
+    CoalescingRingBuffer::new_fixed().split()
+}
+
+/// Same as `new_ring_buffer` but returns `Error::InvalidCapacity` instead of building
+/// a buffer that cannot hold anything.
+pub fn try_new_ring_buffer<K: Send + Eq, V: Send>(capacity: usize) -> Result<Handles<K, V>, Error> {
+    CoalescingRingBuffer::try_new(capacity).map(CoalescingRingBuffer::split)
+}
+
+/// Same as `new_ring_buffer` for payloads shared behind an `Arc`. Slots then hold a pointer
+/// sized handle, so they stay lock-free however large `V` is, and offering or polling a
+/// value never copies it.
+///
+/// ```
+/// use coalescing_buffer::ring::new_shared_ring_buffer;
+/// use std::sync::Arc;
+///
+/// let (sender, receiver) = new_shared_ring_buffer::<u32, [u64; 512]>(8);
+/// let book = Arc::new([7; 512]);
+/// sender.offer(1, Arc::clone(&book));
+/// assert!(Arc::ptr_eq(&book, &receiver.poll_all()[0]));
+/// ```
+pub fn new_shared_ring_buffer<K: Send + Eq, V: Send + Sync>(capacity: usize) -> Handles<K, Arc<V>> {
+    new_ring_buffer(capacity)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static VOD_SNAPSHOT_1: MarketSnapshot = MarketSnapshot {
+        instrument_id: 1,
+        bid: 3,
         ask: 4,
     };
     static VOD_SNAPSHOT_2: MarketSnapshot = MarketSnapshot {
@@ -328,211 +3762,1389 @@ mod tests {
         ask: 8,
     };
 
-    #[derive(Debug, Clone, PartialEq, Eq, Copy)]
-    struct MarketSnapshot {
-        pub instrument_id: usize,
-        pub bid: isize,
-        pub ask: isize,
+    #[derive(Debug, Clone, PartialEq, Eq, Copy)]
+    struct MarketSnapshot {
+        pub instrument_id: usize,
+        pub bid: isize,
+        pub ask: isize,
+    }
+
+    impl MarketSnapshot {
+        pub fn new(instrument_id: usize, best_bid: isize, best_ask: isize) -> Self {
+            MarketSnapshot {
+                instrument_id,
+                bid: best_bid,
+                ask: best_ask,
+            }
+        }
+    }
+
+    fn create_buffer(capacity: usize) -> CoalescingRingBuffer<usize, MarketSnapshot> {
+        CoalescingRingBuffer::new(capacity)
+    }
+
+    #[test]
+    fn should_correctly_increase_the_capacity_to_the_next_higher_power_of_two() {
+        check_capacity(1024, &create_buffer(1023));
+        check_capacity(1024, &create_buffer(1024));
+        check_capacity(2048, &create_buffer(1025));
+    }
+
+    #[test]
+    fn should_remember_the_requested_capacity() {
+        let buffer = create_buffer(1000);
+        assert_eq!(1000, buffer.requested_capacity());
+        assert_eq!(1024, buffer.capacity());
+
+        let buffer = create_buffer(1024);
+        assert_eq!(1024, buffer.requested_capacity());
+        assert_eq!(1024, buffer.capacity());
+    }
+
+    fn check_capacity(capacity: usize, buffer: &CoalescingRingBuffer<usize, MarketSnapshot>) {
+        assert_eq!(capacity, buffer.capacity());
+        for i in 0..capacity {
+            let success = buffer.offer(0, MarketSnapshot::new(i, i as isize, i as isize));
+            assert!(success);
+        }
+    }
+
+    #[test]
+    fn should_correctly_report_size() {
+        let buffer = create_buffer(2);
+        assert_eq!(0, buffer.size());
+        assert!(buffer.is_empty());
+        assert!(!buffer.is_full());
+
+        buffer.offer_value_only(BP_SNAPSHOT);
+        assert_eq!(1, buffer.size());
+        assert!(!buffer.is_empty());
+        assert!(!buffer.is_full());
+
+        buffer.offer(VOD_SNAPSHOT_1.instrument_id, VOD_SNAPSHOT_1);
+        assert_eq!(2, buffer.size());
+        assert!(!buffer.is_empty());
+        assert!(buffer.is_full());
+
+        let _ = buffer.poll(1);
+        assert_eq!(1, buffer.size());
+        assert!(!buffer.is_empty());
+        assert!(!buffer.is_full());
+
+        let _ = buffer.poll(1);
+        assert_eq!(0, buffer.size());
+        assert!(buffer.is_empty());
+        assert!(!buffer.is_full());
+    }
+
+    #[test]
+    fn should_reject_new_keys_when_full() {
+        let buffer = create_buffer(2);
+        buffer.offer(1, BP_SNAPSHOT);
+        buffer.offer(2, VOD_SNAPSHOT_1);
+
+        assert!(!buffer.offer(4, VOD_SNAPSHOT_2));
+        assert_eq!(2, buffer.size());
+    }
+
+    #[test]
+    fn should_detect_full_exactly_while_wrapping_around() {
+        let buffer: CoalescingRingBuffer<usize, usize> = CoalescingRingBuffer::new(4);
+        buffer.reserve_control_slots(1);
+        assert!(buffer.offer_value_only(0));
+        for round in 0..10 {
+            assert_eq!(1, buffer.size());
+            for value in 0..2 {
+                assert!(buffer.offer_value_only(value));
+            }
+            assert!(!buffer.offer_value_only(round));
+            assert!(!buffer.is_full());
+            assert!(buffer.offer_control(round));
+            assert!(buffer.is_full());
+            assert!(!buffer.offer_control(round));
+
+            assert_eq!(3, buffer.poll(3).len());
+            assert!(!buffer.is_full());
+        }
+    }
+
+    #[test]
+    fn should_accept_existing_keys_when_full() {
+        let buffer = create_buffer(2);
+        buffer.offer(1, BP_SNAPSHOT);
+        buffer.offer(2, VOD_SNAPSHOT_1);
+
+        assert!(buffer.offer(2, VOD_SNAPSHOT_2));
+        assert_eq!(2, buffer.size());
+    }
+
+    #[test]
+    fn should_return_single_value() {
+        let buffer = create_buffer(2);
+
+        add_key_value(&buffer, BP_SNAPSHOT);
+        assert_contains(&buffer, vec![BP_SNAPSHOT]);
+    }
+
+    #[test]
+    fn should_return_two_values_with_different_keys() {
+        let buffer = create_buffer(2);
+        add_key_value(&buffer, BP_SNAPSHOT);
+        add_key_value(&buffer, VOD_SNAPSHOT_1);
+
+        assert_contains(&buffer, vec![BP_SNAPSHOT, VOD_SNAPSHOT_1]);
+    }
+
+    #[test]
+    fn should_update_values_with_equal_keys() {
+        let buffer = create_buffer(2);
+        add_key_value(&buffer, VOD_SNAPSHOT_1);
+        add_key_value(&buffer, VOD_SNAPSHOT_2);
+        assert_contains(&buffer, vec![VOD_SNAPSHOT_2]);
+    }
+
+    #[test]
+    fn should_not_update_values_without_keys() {
+        let buffer = create_buffer(2);
+        add_value(&buffer, VOD_SNAPSHOT_1);
+        add_value(&buffer, VOD_SNAPSHOT_2);
+        assert_contains(&buffer, vec![VOD_SNAPSHOT_1, VOD_SNAPSHOT_2]);
+    }
+
+    #[test]
+    fn should_update_values_with_equal_keys_and_preserve_ordering() {
+        let buffer = create_buffer(2);
+        add_key_value(&buffer, VOD_SNAPSHOT_1);
+        add_key_value(&buffer, BP_SNAPSHOT);
+        add_key_value(&buffer, VOD_SNAPSHOT_2);
+
+        assert_contains(&buffer, vec![VOD_SNAPSHOT_2, BP_SNAPSHOT]);
+    }
+
+    #[test]
+    fn should_not_update_values_if_read_occurs_between_values() {
+        let buffer = create_buffer(2);
+
+        add_key_value(&buffer, VOD_SNAPSHOT_1);
+        assert_contains(&buffer, vec![VOD_SNAPSHOT_1]);
+
+        add_key_value(&buffer, VOD_SNAPSHOT_2);
+        assert_contains(&buffer, vec![VOD_SNAPSHOT_2]);
+    }
+
+    #[test]
+    fn should_return_only_the_maximum_number_of_requested_items() {
+        let buffer = create_buffer(10);
+        add_value(&buffer, BP_SNAPSHOT);
+        add_value(&buffer, VOD_SNAPSHOT_1);
+        add_value(&buffer, VOD_SNAPSHOT_2);
+
+        let snapshots = buffer.poll(2);
+        assert_eq!(2, snapshots.len());
+        assert_eq!(&BP_SNAPSHOT, snapshots.first().unwrap());
+        assert_eq!(&VOD_SNAPSHOT_1, snapshots.get(1).unwrap());
+
+        let snapshots = buffer.poll(1);
+        assert_eq!(1, snapshots.len());
+        assert_eq!(&VOD_SNAPSHOT_2, snapshots.first().unwrap());
+
+        assert_is_empty(&buffer);
+    }
+
+    #[test]
+    fn should_return_all_items_without_request_limit() {
+        let buffer = create_buffer(10);
+        add_value(&buffer, BP_SNAPSHOT);
+        add_key_value(&buffer, VOD_SNAPSHOT_1);
+        add_key_value(&buffer, VOD_SNAPSHOT_2);
+
+        let snapshots = buffer.poll_all();
+        assert_eq!(2, snapshots.len());
+
+        assert_eq!(&BP_SNAPSHOT, snapshots.first().unwrap());
+        assert_eq!(&VOD_SNAPSHOT_2, snapshots.get(1).unwrap());
+
+        assert_is_empty(&buffer);
+    }
+
+    #[test]
+    fn should_count_rejections() {
+        let buffer = create_buffer(2);
+        assert_eq!(0, buffer.rejection_count());
+
+        buffer.offer_value_only(BP_SNAPSHOT);
+        assert_eq!(0, buffer.rejection_count());
+
+        buffer.offer(1, VOD_SNAPSHOT_1);
+        assert_eq!(0, buffer.rejection_count());
+
+        buffer.offer(1, VOD_SNAPSHOT_2);
+        assert_eq!(0, buffer.rejection_count());
+
+        buffer.offer_value_only(BP_SNAPSHOT);
+        assert_eq!(1, buffer.rejection_count());
+
+        buffer.offer(2, BP_SNAPSHOT);
+        assert_eq!(2, buffer.rejection_count());
+    }
+
+    #[test]
+    fn should_use_object_equality_to_compare_keys() {
+        let buffer: CoalescingRingBuffer<String, MarketSnapshot> = CoalescingRingBuffer::new(2);
+
+        buffer.offer(String::from("boo"), BP_SNAPSHOT);
+        buffer.offer(String::from("boo"), BP_SNAPSHOT);
+
+        assert_eq!(1, buffer.size());
+    }
+
+    #[test]
+    fn should_generate_unique_keys_for_auto_offers() {
+        let buffer: CoalescingRingBuffer<u64, MarketSnapshot> = CoalescingRingBuffer::new(2);
+
+        assert_eq!(Some(0), buffer.offer_auto(VOD_SNAPSHOT_1));
+        assert_eq!(Some(1), buffer.offer_auto(VOD_SNAPSHOT_2));
+        assert_eq!(None, buffer.offer_auto(BP_SNAPSHOT));
+        assert_eq!(1, buffer.rejection_count());
+
+        assert_eq!(vec![VOD_SNAPSHOT_1, VOD_SNAPSHOT_2], buffer.poll_all());
+    }
+
+    #[test]
+    fn should_pass_each_polled_value_to_the_closure() {
+        let buffer = create_buffer(10);
+        add_value(&buffer, BP_SNAPSHOT);
+        add_key_value(&buffer, VOD_SNAPSHOT_1);
+        add_key_value(&buffer, VOD_SNAPSHOT_2);
+        add_value(&buffer, BP_SNAPSHOT);
+
+        let mut seen = Vec::new();
+        assert_eq!(2, buffer.poll_for_each(2, |snapshot| seen.push(snapshot)));
+        assert_eq!(vec![BP_SNAPSHOT, VOD_SNAPSHOT_2], seen);
+        assert_eq!(1, buffer.size());
+
+        assert_eq!(1, buffer.poll_for_each(10, |snapshot| seen.push(snapshot)));
+        assert_eq!(BP_SNAPSHOT, seen[2]);
+        assert_eq!(0, buffer.poll_for_each(10, |snapshot| seen.push(snapshot)));
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn should_offer_map_in_key_order_and_count_rejections() {
+        let buffer = create_buffer(2);
+        add_key_value(&buffer, VOD_SNAPSHOT_1);
+
+        let mut updates = HashMap::new();
+        updates.insert(3, BP_SNAPSHOT);
+        updates.insert(1, VOD_SNAPSHOT_2);
+        updates.insert(2, BP_SNAPSHOT);
+
+        assert_eq!((2, 1), buffer.offer_map(&updates));
+        assert_eq!(1, buffer.rejection_count());
+        assert_eq!(vec![VOD_SNAPSHOT_2, BP_SNAPSHOT], buffer.poll_all());
+    }
+
+    #[test]
+    fn should_stop_polling_before_exceeding_the_byte_budget() {
+        let buffer: CoalescingRingBuffer<usize, String> = CoalescingRingBuffer::new(8);
+        buffer.offer(1, String::from("aaaa"));
+        buffer.offer(2, String::from("bbb"));
+        buffer.offer(3, String::from("cc"));
+        buffer.offer(4, String::from("dddddddddd"));
+
+        let first = buffer.poll_bounded_bytes(9, |value| value.len());
+        assert_eq!(vec!["aaaa", "bbb", "cc"], first);
+        assert_eq!(1, buffer.size());
+
+        // the oversized value comes out on its own rather than blocking the buffer
+        let second = buffer.poll_bounded_bytes(5, |value| value.len());
+        assert_eq!(vec!["dddddddddd"], second);
+        assert!(buffer.is_empty());
+        assert!(buffer.poll_bounded_bytes(5, |value| value.len()).is_empty());
+    }
+
+    #[test]
+    fn should_coalesce_into_a_value_handed_back_by_a_bounded_poll() {
+        let buffer: CoalescingRingBuffer<usize, String> = CoalescingRingBuffer::new(8);
+        buffer.offer(1, String::from("aaaa"));
+        buffer.offer(2, String::from("bbbb"));
+
+        assert_eq!(
+            vec!["aaaa"],
+            buffer.poll_bounded_bytes(6, |value| value.len())
+        );
+        assert!(buffer.offer(2, String::from("cc")));
+        assert_eq!(1, buffer.size());
+        assert_eq!(vec!["cc"], buffer.poll_all());
+    }
+
+    #[test]
+    fn should_take_all_entries_with_their_keys() {
+        let buffer = create_buffer(4);
+        add_key_value(&buffer, VOD_SNAPSHOT_1);
+        add_value(&buffer, BP_SNAPSHOT);
+        add_key_value(&buffer, VOD_SNAPSHOT_2);
+        add_key_value(&buffer, BP_SNAPSHOT);
+
+        assert_eq!(
+            vec![
+                (Some(1), VOD_SNAPSHOT_2),
+                (None, BP_SNAPSHOT),
+                (Some(2), BP_SNAPSHOT)
+            ],
+            buffer.take_all()
+        );
+        assert!(buffer.is_empty());
+        assert!(buffer.take_all().is_empty());
+
+        add_key_value(&buffer, VOD_SNAPSHOT_1);
+        assert_eq!(vec![(Some(1), VOD_SNAPSHOT_1)], buffer.take_all());
+    }
+
+    #[test]
+    fn should_return_newest_first_when_asked() {
+        let buffer = create_buffer(10);
+        add_value(&buffer, BP_SNAPSHOT);
+        add_key_value(&buffer, VOD_SNAPSHOT_1);
+        add_value(&buffer, VOD_SNAPSHOT_2);
+        add_key_value(&buffer, VOD_SNAPSHOT_2);
+
+        assert_eq!(
+            vec![VOD_SNAPSHOT_2, VOD_SNAPSHOT_2, BP_SNAPSHOT],
+            buffer.poll_all_newest_first()
+        );
+        assert!(buffer.is_empty());
+        assert!(buffer.poll_all_newest_first().is_empty());
+    }
+
+    #[test]
+    fn should_overwrite_the_oldest_entry_when_full() {
+        let buffer = create_buffer(2);
+        add_key_value(&buffer, VOD_SNAPSHOT_1);
+        add_value(&buffer, BP_SNAPSHOT);
+
+        assert!(buffer.offer_overwrite_oldest(3, VOD_SNAPSHOT_2));
+        assert_eq!(2, buffer.size());
+        assert_eq!(1, buffer.overwritten_count());
+        assert_eq!(0, buffer.rejection_count());
+
+        assert!(buffer.offer_overwrite_oldest(4, BP_SNAPSHOT));
+        assert_eq!(2, buffer.size());
+        assert_eq!(2, buffer.overwritten_count());
+
+        assert_eq!(vec![VOD_SNAPSHOT_2, BP_SNAPSHOT], buffer.poll_all());
+    }
+
+    #[test]
+    fn should_coalesce_rather_than_overwrite_a_pending_key() {
+        let buffer = create_buffer(2);
+        add_key_value(&buffer, VOD_SNAPSHOT_1);
+        add_key_value(&buffer, BP_SNAPSHOT);
+
+        assert!(buffer.offer_overwrite_oldest(1, VOD_SNAPSHOT_2));
+        assert_eq!(0, buffer.overwritten_count());
+        assert_eq!(vec![VOD_SNAPSHOT_2, BP_SNAPSHOT], buffer.poll_all());
+
+        // once everything has been read the buffer fills up normally again
+        add_key_value(&buffer, VOD_SNAPSHOT_1);
+        assert_eq!(1, buffer.size());
+        assert_eq!(vec![VOD_SNAPSHOT_1], buffer.poll_all());
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct LargeSnapshot {
+        levels: Vec<isize>,
+    }
+
+    static LARGE_SNAPSHOT_CLONES: AtomicUsize = AtomicUsize::new(0);
+
+    impl Clone for LargeSnapshot {
+        fn clone(&self) -> Self {
+            LARGE_SNAPSHOT_CLONES.fetch_add(1, Ordering::SeqCst);
+            LargeSnapshot {
+                levels: self.levels.clone(),
+            }
+        }
+    }
+
+    #[test]
+    fn should_coalesce_shared_values_without_cloning_the_payload() {
+        assert!(AtomicCell::<Option<Arc<LargeSnapshot>>>::is_lock_free());
+
+        let buffer: CoalescingRingBuffer<usize, Arc<LargeSnapshot>> = CoalescingRingBuffer::new(2);
+        for bid in 0..100 {
+            let snapshot = Arc::new(LargeSnapshot {
+                levels: vec![bid; 1000],
+            });
+            assert!(buffer.offer(1, snapshot));
+        }
+        assert_eq!(1, buffer.size());
+
+        let polled = buffer.poll_all();
+        assert_eq!(vec![99; 1000], polled[0].levels);
+        assert_eq!(1, Arc::strong_count(&polled[0]));
+        assert_eq!(0, LARGE_SNAPSHOT_CLONES.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn should_report_consistent_cursors() {
+        let buffer = create_buffer(4);
+        assert_eq!((0, 1, 1), buffer.cursors());
+
+        add_key_value(&buffer, VOD_SNAPSHOT_1);
+        add_key_value(&buffer, BP_SNAPSHOT);
+        add_key_value(&buffer, VOD_SNAPSHOT_2);
+        assert_eq!((0, 1, 3), buffer.cursors());
+
+        let _ = buffer.poll(1);
+        assert_eq!((1, 2, 3), buffer.cursors());
+        let (last_read, _, next_write) = buffer.cursors();
+        assert_eq!(buffer.size(), next_write - last_read - 1);
+    }
+
+    #[test]
+    fn should_count_keyless_rejections_separately() {
+        let buffer = create_buffer(2);
+        add_value(&buffer, BP_SNAPSHOT);
+        add_key_value(&buffer, VOD_SNAPSHOT_1);
+
+        assert!(!buffer.offer(2, BP_SNAPSHOT));
+        assert!(!buffer.offer_value_only(BP_SNAPSHOT));
+        assert!(!buffer.offer_value_only(BP_SNAPSHOT));
+        assert_eq!(3, buffer.rejection_count());
+        assert_eq!(2, buffer.value_only_rejection_count());
+    }
+
+    #[test]
+    fn should_drop_the_oldest_entry_for_keyless_overwrites() {
+        let buffer = create_buffer(2);
+        add_key_value(&buffer, VOD_SNAPSHOT_1);
+        add_value(&buffer, BP_SNAPSHOT);
+
+        assert!(buffer.offer_value_only_overwrite_oldest(VOD_SNAPSHOT_2));
+        assert_eq!(1, buffer.overwritten_count());
+        assert_eq!(0, buffer.value_only_rejection_count());
+        assert_eq!(vec![BP_SNAPSHOT, VOD_SNAPSHOT_2], buffer.poll_all());
+    }
+
+    #[test]
+    fn should_keep_reserved_slots_for_control_messages() {
+        let buffer = create_buffer(4);
+        buffer.reserve_control_slots(1);
+        add_key_value(&buffer, VOD_SNAPSHOT_1);
+        add_key_value(&buffer, BP_SNAPSHOT);
+        add_value(&buffer, BP_SNAPSHOT);
+
+        assert!(!buffer.offer_value_only(BP_SNAPSHOT));
+        assert!(buffer.offer(1, VOD_SNAPSHOT_2));
+        assert!(buffer.offer_control(VOD_SNAPSHOT_1));
+        assert!(buffer.is_full());
+        assert!(!buffer.offer_control(VOD_SNAPSHOT_1));
+
+        assert_eq!(
+            vec![VOD_SNAPSHOT_2, BP_SNAPSHOT, BP_SNAPSHOT, VOD_SNAPSHOT_1],
+            buffer.poll_all()
+        );
+    }
+
+    #[test]
+    fn should_not_hold_on_to_values_after_into_remaining() {
+        let value = Arc::new(String::from("in flight"));
+        let (sender, receiver) = new_ring_buffer::<usize, Arc<String>>(4);
+        assert!(sender.offer(1, value.clone()));
+        assert!(sender.offer_value_only(value.clone()));
+
+        let remaining = receiver.into_remaining();
+        assert_eq!(2, remaining.len());
+        drop(remaining);
+        assert_eq!(1, Arc::strong_count(&value));
     }
 
-    impl MarketSnapshot {
-        pub fn new(instrument_id: usize, best_bid: isize, best_ask: isize) -> Self {
-            MarketSnapshot {
-                instrument_id,
-                bid: best_bid,
-                ask: best_ask,
-            }
+    #[test]
+    fn should_report_a_stalled_consumer_once() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let (sender, receiver) = RingBufferBuilder::<usize, MarketSnapshot>::new(1)
+            .stall_watchdog(Duration::from_millis(0), move |_| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                StallAction::Wait
+            })
+            .build();
+        assert!(sender.offer(1, VOD_SNAPSHOT_1));
+
+        assert!(!sender.offer(2, BP_SNAPSHOT));
+        assert!(!sender.offer(2, BP_SNAPSHOT));
+        assert_eq!(1, calls.load(Ordering::SeqCst));
+
+        receiver.poll_all();
+        assert!(sender.offer(2, BP_SNAPSHOT));
+        assert!(!sender.offer(1, VOD_SNAPSHOT_1));
+        assert_eq!(2, calls.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn should_clear_the_backlog_when_the_watchdog_asks_for_it() {
+        let (sender, receiver) = RingBufferBuilder::<usize, MarketSnapshot>::new(2)
+            .stall_watchdog(Duration::from_millis(0), |_| StallAction::Clear)
+            .build();
+        assert!(sender.offer(1, VOD_SNAPSHOT_1));
+        assert!(sender.offer_value_only(VOD_SNAPSHOT_2));
+
+        assert!(sender.offer(2, BP_SNAPSHOT));
+        assert_eq!(2, sender.overwritten_count());
+        assert_eq!(0, sender.rejection_count());
+        assert_eq!(vec![BP_SNAPSHOT], receiver.poll_all());
+    }
+
+    #[test]
+    fn should_report_the_latency_of_the_last_poll() {
+        let (sender, receiver) = RingBufferBuilder::<usize, MarketSnapshot>::new(4)
+            .measure_latency()
+            .build();
+        assert!(sender.offer(1, VOD_SNAPSHOT_1));
+        assert!(sender.offer_value_only(BP_SNAPSHOT));
+        std::thread::sleep(Duration::from_millis(5));
+
+        assert_eq!(2, receiver.poll_all().len());
+        let latencies = receiver.last_poll_latencies();
+        assert_eq!(2, latencies.len());
+        assert!(latencies.iter().all(|l| *l >= Duration::from_millis(5)));
+
+        assert!(receiver.poll_all().is_empty());
+        assert!(receiver.last_poll_latencies().is_empty());
+    }
+
+    #[test]
+    fn should_not_measure_latency_by_default() {
+        let (sender, receiver) = new_ring_buffer::<usize, MarketSnapshot>(4);
+        assert!(sender.offer(1, VOD_SNAPSHOT_1));
+        assert_eq!(1, receiver.poll_all().len());
+        assert!(receiver.last_poll_latencies().is_empty());
+    }
+
+    #[test]
+    fn should_return_one_value_per_key_when_compacting() {
+        let buffer = create_buffer(8);
+        add_key_value(&buffer, VOD_SNAPSHOT_1);
+        add_value(&buffer, BP_SNAPSHOT);
+        // slip a second entry for the same key in, as a non coalescing producer would
+        assert!(buffer.add(KeyHolder::NonEmpty(2), BP_SNAPSHOT).is_ok());
+        assert!(buffer.add(KeyHolder::NonEmpty(1), VOD_SNAPSHOT_2).is_ok());
+
+        let (keyed, keyless) = buffer.poll_compacted(10);
+        assert_eq!(vec![(1, VOD_SNAPSHOT_2), (2, BP_SNAPSHOT)], keyed);
+        assert_eq!(vec![BP_SNAPSHOT], keyless);
+        assert_is_empty(&buffer);
+    }
+
+    #[test]
+    fn should_keep_pending_entries_in_order_when_growing() {
+        let buffer: CoalescingRingBuffer<usize, usize> = CoalescingRingBuffer::new(4);
+        for key in 0..3 {
+            assert!(buffer.offer(key, key));
+        }
+        assert_eq!(vec![0, 1], buffer.poll(2));
+        // wrap around the end of the ring before growing
+        for key in 3..6 {
+            assert!(buffer.offer(key, key));
         }
+        assert!(buffer.is_full());
+
+        assert_eq!(Ok(8), buffer.try_grow());
+        assert_eq!(8, buffer.capacity());
+        assert!(buffer.offer(4, 40));
+        for key in 6..10 {
+            assert!(buffer.offer(key, key));
+        }
+        assert!(!buffer.offer(10, 10));
+        assert_eq!(vec![2, 3, 40, 5, 6, 7, 8, 9], buffer.poll_all());
     }
 
-    fn create_buffer(capacity: usize) -> CoalescingRingBuffer<usize, MarketSnapshot> {
-        CoalescingRingBuffer::new(capacity)
+    #[test]
+    fn should_grow_to_a_given_capacity() {
+        let (mut sender, receiver) = new_ring_buffer::<usize, usize>(4);
+        for key in 0..4 {
+            assert!(sender.offer(key, key));
+        }
+        assert_eq!(vec![0], receiver.poll(1));
+        assert!(sender.offer(4, 4));
+        assert!(!sender.offer(5, 5));
+
+        assert_eq!(Err(Error::InvalidCapacity(3)), sender.grow(3));
+        assert_eq!(Ok(16), sender.grow(9));
+        assert!(sender.offer(2, 20));
+        for key in 5..17 {
+            assert!(sender.offer(key, key));
+        }
+        assert!(!sender.offer(17, 17));
+        assert_eq!(
+            vec![1, 20, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16],
+            receiver.poll_all()
+        );
+
+        let (mut sender, _receiver) = RingBufferBuilder::<usize, usize>::new(3)
+            .exact_capacity(true)
+            .build();
+        assert_eq!(Ok(5), sender.grow(5));
+        assert_eq!(5, sender.capacity());
     }
 
     #[test]
-    fn should_correctly_increase_the_capacity_to_the_next_higher_power_of_two() {
-        check_capacity(1024, &create_buffer(1023));
-        check_capacity(1024, &create_buffer(1024));
-        check_capacity(2048, &create_buffer(1025));
+    fn should_grow_when_full_up_to_the_limit() {
+        let (sender, receiver) = RingBufferBuilder::<usize, usize>::new(2)
+            .grow_when_full(8)
+            .build();
+        for key in 0..8 {
+            assert!(sender.offer(key, key));
+        }
+        assert_eq!(8, sender.capacity());
+        assert!(!sender.offer(8, 8));
+        assert_eq!(1, sender.rejection_count());
+        assert_eq!((0..8).collect::<Vec<usize>>(), receiver.poll_all());
     }
 
-    fn check_capacity(capacity: usize, buffer: &CoalescingRingBuffer<usize, MarketSnapshot>) {
-        assert_eq!(capacity, buffer.capacity());
-        for i in 0..capacity {
-            let success = buffer.offer(0, MarketSnapshot::new(i, i as isize, i as isize));
-            assert!(success);
+    #[test]
+    fn should_poll_into_a_reused_bucket() {
+        let buffer = create_buffer(8);
+        add_key_value(&buffer, VOD_SNAPSHOT_1);
+        add_key_value(&buffer, BP_SNAPSHOT);
+        add_value(&buffer, VOD_SNAPSHOT_2);
+
+        let mut bucket = vec![BP_SNAPSHOT; 4];
+        assert_eq!(2, buffer.poll_into(&mut bucket, 2));
+        assert_eq!(vec![VOD_SNAPSHOT_1, BP_SNAPSHOT], bucket);
+        assert_eq!(2, buffer.last_read.load(Ordering::SeqCst));
+
+        assert_eq!(1, buffer.poll_into(&mut bucket, 2));
+        assert_eq!(vec![VOD_SNAPSHOT_2], bucket);
+
+        let capacity = bucket.capacity();
+        assert_eq!(0, buffer.poll_into(&mut bucket, 2));
+        assert!(bucket.is_empty());
+        assert_eq!(capacity, bucket.capacity());
+    }
+
+    #[test]
+    fn should_free_slots_as_the_drain_is_advanced() {
+        let buffer = create_buffer(4);
+        add_key_value(&buffer, VOD_SNAPSHOT_1);
+        add_key_value(&buffer, BP_SNAPSHOT);
+        add_value(&buffer, VOD_SNAPSHOT_2);
+
+        let mut drain = buffer.drain_iter(10);
+        assert_eq!((0, Some(3)), drain.size_hint());
+        assert_eq!(Some(VOD_SNAPSHOT_1), drain.next());
+        assert_eq!(1, buffer.last_read.load(Ordering::SeqCst));
+        assert_eq!(Some(BP_SNAPSHOT), drain.next());
+        assert_eq!(2, buffer.last_read.load(Ordering::SeqCst));
+        drop(drain);
+
+        // the value that was claimed but not yielded is gone
+        assert_eq!(3, buffer.last_read.load(Ordering::SeqCst));
+        assert_is_empty(&buffer);
+        assert!(buffer
+            .slots()
+            .values
+            .iter()
+            .all(|value| value.take().is_none()));
+    }
+
+    #[test]
+    fn should_poll_values_with_their_keys() {
+        let buffer = create_buffer(8);
+        add_key_value(&buffer, VOD_SNAPSHOT_1);
+        add_value(&buffer, BP_SNAPSHOT);
+        add_key_value(&buffer, BP_SNAPSHOT);
+        add_key_value(&buffer, VOD_SNAPSHOT_2);
+
+        assert_eq!(
+            vec![(Some(1), VOD_SNAPSHOT_2), (None, BP_SNAPSHOT)],
+            buffer.poll_keyed(2)
+        );
+        assert_eq!(vec![(Some(2), BP_SNAPSHOT)], buffer.poll_keyed(2));
+        assert!(buffer.poll_keyed(2).is_empty());
+    }
+
+    #[test]
+    fn should_append_keyed_values_to_a_reused_vec() {
+        let buffer = create_buffer(8);
+        let mut out = Vec::with_capacity(4);
+        add_key_value(&buffer, VOD_SNAPSHOT_1);
+        add_value(&buffer, BP_SNAPSHOT);
+        add_key_value(&buffer, VOD_SNAPSHOT_2);
+        assert_eq!(2, buffer.poll_keyed_into(&mut out, 8));
+
+        add_key_value(&buffer, BP_SNAPSHOT);
+        assert_eq!(1, buffer.poll_keyed_into(&mut out, 8));
+        assert_eq!(
+            vec![
+                (Some(1), VOD_SNAPSHOT_2),
+                (None, BP_SNAPSHOT),
+                (Some(2), BP_SNAPSHOT)
+            ],
+            out
+        );
+        assert_eq!(4, out.capacity());
+        assert_eq!(0, buffer.poll_keyed_into(&mut out, 8));
+    }
+
+    #[test]
+    fn should_coalesce_through_the_key_index() {
+        let (sender, receiver) = RingBufferBuilder::<usize, MarketSnapshot>::new(4)
+            .with_hash_index(RandomState::new())
+            .build();
+        assert!(sender.offer(1, VOD_SNAPSHOT_1));
+        assert!(sender.offer(2, BP_SNAPSHOT));
+        assert!(sender.offer(1, VOD_SNAPSHOT_2));
+        assert_eq!(2, sender.size());
+        assert_eq!(vec![VOD_SNAPSHOT_2], receiver.poll(1));
+
+        // the slot of key 1 has been read, so it is stored again
+        assert!(sender.offer(1, VOD_SNAPSHOT_1));
+        assert!(sender.offer(2, BP_SNAPSHOT));
+        assert_eq!(2, sender.size());
+        assert_eq!(vec![BP_SNAPSHOT, VOD_SNAPSHOT_1], receiver.poll_all());
+    }
+
+    #[test]
+    fn should_forget_read_keys_in_the_key_index() {
+        let (sender, receiver) = RingBufferBuilder::<usize, usize>::new(2)
+            .with_hash_index(RandomState::new())
+            .build();
+        for key in 0..100 {
+            assert!(sender.offer(key, key));
+            assert!(sender.offer(key, key + 1));
+            assert_eq!(vec![key + 1], receiver.poll_all());
         }
+        let key_index = sender.buffer.key_index.as_ref().unwrap().borrow();
+        assert_eq!(Some(100), key_index.get(&99));
+        assert_eq!(None, key_index.get(&97));
     }
 
     #[test]
-    fn should_correctly_report_size() {
+    fn should_free_unread_entries_when_dropped() {
+        let key = Arc::new(String::from("VOD"));
+        let first = Arc::new(String::from("first"));
+        let second = Arc::new(String::from("second"));
+        let keyless = Arc::new(String::from("keyless"));
+        let (sender, receiver) = new_ring_buffer::<Arc<String>, Arc<String>>(4);
+        assert!(sender.offer(key.clone(), first.clone()));
+        assert!(sender.offer(key.clone(), second.clone()));
+        assert!(sender.offer_value_only(keyless.clone()));
+        // the coalesced value is released straight away
+        assert_eq!(1, Arc::strong_count(&first));
+
+        drop(sender);
+        drop(receiver);
+        for value in [&key, &second, &keyless].iter() {
+            assert_eq!(1, Arc::strong_count(value));
+        }
+    }
+
+    #[test]
+    fn should_drop_every_value_exactly_once() {
+        struct Counted(Arc<AtomicUsize>);
+        impl Drop for Counted {
+            fn drop(&mut self) {
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+        let drops = Arc::new(AtomicUsize::new(0));
+        let counted = || Counted(drops.clone());
+
+        let buffer: CoalescingRingBuffer<usize, Counted> = CoalescingRingBuffer::new(4);
+        assert!(buffer.offer(1, counted()));
+        assert!(buffer.offer(2, counted()));
+        assert!(buffer.offer(1, counted()));
+        assert!(buffer.offer_value_only(counted()));
+        assert!(buffer.offer_value_only(counted()));
+        assert!(!buffer.offer(3, counted()));
+        assert_eq!(2, drops.load(Ordering::SeqCst));
+
+        assert_eq!(1, buffer.poll(1).len());
+        assert!(buffer.remove(&2).is_some());
+        assert_eq!(4, drops.load(Ordering::SeqCst));
+        assert_eq!(Ok(8), buffer.grow(8));
+        // wrap around the grown ring, leaving some values unread each time
+        for key in 0..20 {
+            assert!(buffer.offer(key, counted()));
+            if key % 2 == 0 {
+                buffer.poll(2);
+            }
+        }
+        assert!(buffer.size() > 0);
+        drop(buffer);
+        assert_eq!(26, drops.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn should_behave_like_a_new_buffer_after_clear() {
         let buffer = create_buffer(2);
+        add_key_value(&buffer, VOD_SNAPSHOT_1);
+        assert_eq!(vec![VOD_SNAPSHOT_1], buffer.poll_all());
+        add_key_value(&buffer, VOD_SNAPSHOT_2);
+        add_value(&buffer, BP_SNAPSHOT);
+        assert!(!buffer.offer(2, BP_SNAPSHOT));
+
+        buffer.clear();
         assert_eq!(0, buffer.size());
         assert!(buffer.is_empty());
-        assert!(!buffer.is_full());
-
-        buffer.offer_value_only(BP_SNAPSHOT.clone());
-        assert_eq!(1, buffer.size());
-        assert!(!buffer.is_empty());
-        assert!(!buffer.is_full());
+        assert_eq!(0, buffer.rejection_count());
+        assert_eq!((0, 1, 1), buffer.cursors());
+        assert!(buffer
+            .slots()
+            .keys
+            .iter()
+            .all(|key| *key.get() == KeyHolder::Empty));
+        assert!(buffer
+            .slots()
+            .values
+            .iter()
+            .all(|value| value.take().is_none()));
 
-        buffer.offer(VOD_SNAPSHOT_1.instrument_id, VOD_SNAPSHOT_1.clone());
-        assert_eq!(2, buffer.size());
-        assert!(!buffer.is_empty());
+        add_key_value(&buffer, BP_SNAPSHOT);
+        add_key_value(&buffer, VOD_SNAPSHOT_1);
+        add_key_value(&buffer, VOD_SNAPSHOT_2);
         assert!(buffer.is_full());
+        assert_eq!(vec![BP_SNAPSHOT, VOD_SNAPSHOT_2], buffer.poll_all());
+    }
 
-        let _ = buffer.poll(1);
-        assert_eq!(1, buffer.size());
-        assert!(!buffer.is_empty());
-        assert!(!buffer.is_full());
+    #[test]
+    fn should_peek_without_consuming() {
+        let buffer = create_buffer(4);
+        assert_eq!(None, buffer.peek(&1));
+        add_key_value(&buffer, VOD_SNAPSHOT_1);
+        add_value(&buffer, BP_SNAPSHOT);
+        add_key_value(&buffer, VOD_SNAPSHOT_2);
 
-        let _ = buffer.poll(1);
-        assert_eq!(0, buffer.size());
-        assert!(buffer.is_empty());
-        assert!(!buffer.is_full());
+        let cursors = buffer.cursors();
+        assert_eq!(Some(VOD_SNAPSHOT_2), buffer.peek(&1));
+        assert_eq!(None, buffer.peek(&2));
+        assert_eq!(cursors, buffer.cursors());
+
+        assert_eq!(vec![VOD_SNAPSHOT_2, BP_SNAPSHOT], buffer.poll_all());
+        assert_eq!(None, buffer.peek(&1));
     }
 
     #[test]
-    fn should_reject_new_keys_when_full() {
-        let buffer = create_buffer(2);
-        buffer.offer(1, BP_SNAPSHOT.clone());
-        buffer.offer(2, VOD_SNAPSHOT_1.clone());
+    fn should_merge_pending_values() {
+        let buffer: CoalescingRingBuffer<usize, u64> = CoalescingRingBuffer::new(4);
+        assert!(buffer.offer_with(1, 10, |old, new| old + new));
+        assert!(buffer.offer_with(2, 1, |old, new| old + new));
+        assert!(buffer.offer_with(1, 5, |old, new| old + new));
+        assert!(buffer.offer_with(1, 7, |&old, new| cmp::max(old, new)));
+        assert_eq!(vec![15, 1], buffer.poll_all());
 
-        assert!(!buffer.offer(4, VOD_SNAPSHOT_2.clone()));
-        assert_eq!(2, buffer.size());
+        // nothing pending any more, so it is appended as is
+        assert!(buffer.offer_with(1, 3, |old, new| old + new));
+        assert_eq!(vec![3], buffer.poll_all());
     }
 
     #[test]
-    fn should_accept_existing_keys_when_full() {
-        let buffer = create_buffer(2);
-        buffer.offer(1, BP_SNAPSHOT.clone());
-        buffer.offer(2, VOD_SNAPSHOT_1.clone());
+    fn should_update_pending_values_in_place() {
+        let buffer: CoalescingRingBuffer<usize, Vec<u64>> = CoalescingRingBuffer::new(4);
+        assert!(buffer.offer_or_update(1, vec![10], |trades| trades.push(10)));
+        assert!(buffer.offer_or_update(2, vec![1], |trades| trades.push(1)));
+        assert!(buffer.offer_or_update(1, vec![5], |trades| trades.push(5)));
+        assert_eq!(vec![vec![10, 5], vec![1]], buffer.poll_all());
+        assert_eq!(1, buffer.coalesced_count());
 
-        assert!(buffer.offer(2, VOD_SNAPSHOT_2.clone()));
-        assert_eq!(2, buffer.size());
+        // read already, so the update is not applied to anything
+        assert!(buffer.offer_or_update(1, vec![3], |_| unreachable!()));
+        assert_eq!(vec![vec![3]], buffer.poll_all());
     }
 
     #[test]
-    fn should_return_single_value() {
-        let buffer = create_buffer(2);
+    fn should_skip_a_slot_emptied_by_a_merge() {
+        let buffer = create_buffer(4);
+        add_key_value(&buffer, VOD_SNAPSHOT_1);
+        add_key_value(&buffer, BP_SNAPSHOT);
+        assert_eq!(Some(VOD_SNAPSHOT_1), buffer.replace_value(1, None));
 
-        add_key_value(&buffer, BP_SNAPSHOT.clone());
-        assert_contains(&buffer, vec![BP_SNAPSHOT.clone()]);
+        assert_eq!(vec![BP_SNAPSHOT], buffer.poll_all());
+        assert_eq!(2, buffer.last_read.load(Ordering::SeqCst));
     }
 
     #[test]
-    fn should_return_two_values_with_different_keys() {
-        let buffer = create_buffer(2);
-        add_key_value(&buffer, BP_SNAPSHOT.clone());
-        add_key_value(&buffer, VOD_SNAPSHOT_1.clone());
+    fn should_honor_an_exact_capacity() {
+        let buffer: CoalescingRingBuffer<usize, usize> =
+            CoalescingRingBuffer::with_exact_capacity(3);
+        assert_eq!(3, buffer.capacity());
 
-        assert_contains(&buffer, vec![BP_SNAPSHOT.clone(), VOD_SNAPSHOT_1.clone()]);
+        // wrap around the three slots many times over
+        for round in 0..10 {
+            for key in 0..3 {
+                assert!(buffer.offer(key, round * 3 + key));
+            }
+            assert!(buffer.is_full());
+            assert!(!buffer.offer(3, 0));
+            assert!(buffer.offer(1, 100 + round));
+            assert_eq!(
+                vec![round * 3, 100 + round, round * 3 + 2],
+                buffer.poll_all()
+            );
+        }
+        assert!(buffer.is_empty());
     }
 
     #[test]
-    fn should_update_values_with_equal_keys() {
-        let buffer = create_buffer(2);
-        add_key_value(&buffer, VOD_SNAPSHOT_1.clone());
-        add_key_value(&buffer, VOD_SNAPSHOT_2.clone());
-        assert_contains(&buffer, vec![VOD_SNAPSHOT_2.clone()]);
+    fn should_count_coalesced_offers() {
+        let buffer = create_buffer(4);
+        add_key_value(&buffer, VOD_SNAPSHOT_1);
+        add_value(&buffer, VOD_SNAPSHOT_1);
+        add_value(&buffer, VOD_SNAPSHOT_2);
+        add_key_value(&buffer, BP_SNAPSHOT);
+        assert_eq!(0, buffer.coalesced_count());
+
+        add_key_value(&buffer, VOD_SNAPSHOT_2);
+        assert!(buffer.offer_with(BP_SNAPSHOT.instrument_id, BP_SNAPSHOT, |_, new| new));
+        assert_eq!(2, buffer.coalesced_count());
+
+        buffer.poll_all();
+        add_key_value(&buffer, VOD_SNAPSHOT_1);
+        assert_eq!(2, buffer.coalesced_count());
+        buffer.clear();
+        assert_eq!(0, buffer.coalesced_count());
     }
 
     #[test]
-    fn should_not_update_values_without_keys() {
+    fn should_hand_back_the_value_replaced_by_an_offer() {
         let buffer = create_buffer(2);
-        add_value(&buffer, VOD_SNAPSHOT_1.clone());
-        add_value(&buffer, VOD_SNAPSHOT_2.clone());
-        assert_contains(
-            &buffer,
-            vec![VOD_SNAPSHOT_1.clone(), VOD_SNAPSHOT_2.clone()],
+        assert_eq!(Ok(None), buffer.offer_replace(1, VOD_SNAPSHOT_1));
+        assert_eq!(
+            Ok(Some(VOD_SNAPSHOT_1)),
+            buffer.offer_replace(1, VOD_SNAPSHOT_2)
+        );
+        assert_eq!(Ok(None), buffer.offer_replace(2, BP_SNAPSHOT));
+        assert_eq!(
+            Err(OfferError::Full {
+                key: 3,
+                value: BP_SNAPSHOT
+            }),
+            buffer.offer_replace(3, BP_SNAPSHOT)
         );
+
+        // once read, the old value is no longer pending and nothing is handed back
+        assert_eq!(vec![VOD_SNAPSHOT_2, BP_SNAPSHOT], buffer.poll_all());
+        assert_eq!(Ok(None), buffer.offer_replace(1, VOD_SNAPSHOT_1));
     }
 
     #[test]
-    fn should_update_values_with_equal_keys_and_preserve_ordering() {
+    fn should_not_hand_back_a_value_the_consumer_has_claimed() {
+        let buffer = create_buffer(4);
+        add_key_value(&buffer, VOD_SNAPSHOT_1);
+        let (first, claim_up_to) = buffer.claim(usize::MAX);
+        let read: Vec<_> = (first..claim_up_to)
+            .filter_map(|position| buffer.take_value(buffer.mask(position)))
+            .collect();
+
+        // the consumer is past the slot but has not released it yet, so the offer appends
+        assert_eq!(Ok(None), buffer.offer_replace(1, VOD_SNAPSHOT_2));
+        buffer.release(first, claim_up_to);
+        assert_eq!(vec![VOD_SNAPSHOT_1], read);
+        assert_eq!(vec![VOD_SNAPSHOT_2], buffer.poll_all());
+    }
+
+    #[test]
+    fn should_coalesce_without_blocking_when_full() {
         let buffer = create_buffer(2);
-        add_key_value(&buffer, VOD_SNAPSHOT_1.clone());
-        add_key_value(&buffer, BP_SNAPSHOT.clone());
-        add_key_value(&buffer, VOD_SNAPSHOT_2.clone());
+        add_key_value(&buffer, VOD_SNAPSHOT_1);
+        add_key_value(&buffer, BP_SNAPSHOT);
 
-        assert_contains(&buffer, vec![VOD_SNAPSHOT_2.clone(), BP_SNAPSHOT.clone()]);
+        assert_eq!(Ok(()), buffer.offer_blocking(1, VOD_SNAPSHOT_2));
+        assert_eq!(vec![VOD_SNAPSHOT_2, BP_SNAPSHOT], buffer.poll_all());
     }
 
     #[test]
-    fn should_not_update_values_if_read_occurs_between_values() {
+    fn should_keep_the_cursors_moving_forward_across_polls() {
+        let buffer = create_buffer(4);
+        for round in 1..=3 {
+            add_key_value(&buffer, VOD_SNAPSHOT_1);
+            add_key_value(&buffer, BP_SNAPSHOT);
+            assert_eq!(2, buffer.poll_all().len());
+            // `last_read` is the last position claimed, never a step back from the previous one
+            assert_eq!(round * 2, buffer.last_read.load(Ordering::SeqCst));
+            assert_eq!(0, buffer.size());
+
+            buffer.clean_up();
+            assert_eq!(round * 2, buffer.last_cleaned.load(Ordering::SeqCst));
+        }
+    }
+
+    #[test]
+    fn should_offer_a_batch_until_the_first_rejection() {
         let buffer = create_buffer(2);
+        let batch = vec![
+            (1, VOD_SNAPSHOT_1),
+            (1, VOD_SNAPSHOT_2),
+            (2, BP_SNAPSHOT),
+            (3, BP_SNAPSHOT),
+            (1, VOD_SNAPSHOT_1),
+        ];
+        assert_eq!(3, buffer.offer_all(batch));
+        assert_eq!(1, buffer.rejection_count());
+        assert_eq!(vec![VOD_SNAPSHOT_2, BP_SNAPSHOT], buffer.poll_all());
+    }
 
-        add_key_value(&buffer, VOD_SNAPSHOT_1.clone());
-        assert_contains(&buffer, vec![VOD_SNAPSHOT_1.clone()]);
+    #[test]
+    fn should_buffer_zero_sized_values() {
+        let buffer: CoalescingRingBuffer<u32, ()> = CoalescingRingBuffer::new(4);
+        for key in 0..3 {
+            assert!(buffer.offer(key, ()));
+        }
+        assert!(buffer.offer(1, ()));
+        assert!(buffer.offer_value_only(()));
+        assert!(!buffer.offer(3, ()));
+        assert_eq!(4, buffer.size());
+        assert_eq!(1, buffer.coalesced_count());
+        assert_eq!(1, buffer.rejection_count());
 
-        add_key_value(&buffer, VOD_SNAPSHOT_2.clone());
-        assert_contains(&buffer, vec![VOD_SNAPSHOT_2.clone()]);
+        assert_eq!(vec![(); 4], buffer.poll_all());
+        assert!(buffer.is_empty());
+        assert!(buffer.offer(3, ()));
+        assert_eq!(vec![()], buffer.poll_all());
     }
 
     #[test]
-    fn should_return_only_the_maximum_number_of_requested_items() {
-        let buffer = create_buffer(10);
-        add_value(&buffer, BP_SNAPSHOT.clone());
-        add_value(&buffer, VOD_SNAPSHOT_1.clone());
-        add_value(&buffer, VOD_SNAPSHOT_2.clone());
+    fn should_skip_the_hole_left_by_a_removed_key() {
+        let buffer = create_buffer(4);
+        assert!(buffer.offer(1, VOD_SNAPSHOT_1));
+        assert!(buffer.offer(2, BP_SNAPSHOT));
+        assert!(buffer.offer(3, VOD_SNAPSHOT_2));
 
-        let snapshots = buffer.poll(2);
-        assert_eq!(2, snapshots.len());
-        assert_eq!(&BP_SNAPSHOT, snapshots.get(0).unwrap());
-        assert_eq!(&VOD_SNAPSHOT_1, snapshots.get(1).unwrap());
+        assert_eq!(Some(BP_SNAPSHOT), buffer.remove(&2));
+        assert_eq!(None, buffer.remove(&2));
+        assert_eq!(None, buffer.remove(&4));
+        assert_eq!(vec![VOD_SNAPSHOT_1, VOD_SNAPSHOT_2], buffer.poll_all());
+        assert_eq!(None, buffer.remove(&1));
+    }
 
-        let snapshots = buffer.poll(1);
-        assert_eq!(1, snapshots.len());
-        assert_eq!(&VOD_SNAPSHOT_2, snapshots.get(0).unwrap());
+    #[test]
+    fn should_fill_the_hole_with_a_later_offer_for_the_key() {
+        let buffer = create_buffer(4);
+        assert!(buffer.offer(1, VOD_SNAPSHOT_1));
+        assert!(buffer.offer(2, BP_SNAPSHOT));
+        assert_eq!(Some(VOD_SNAPSHOT_1), buffer.remove(&1));
 
-        assert_is_empty(&buffer);
+        assert!(buffer.offer(1, VOD_SNAPSHOT_2));
+        assert_eq!(0, buffer.coalesced_count());
+        assert_eq!(vec![VOD_SNAPSHOT_2, BP_SNAPSHOT], buffer.poll_all());
     }
 
     #[test]
-    fn should_return_all_items_without_request_limit() {
-        let buffer = create_buffer(10);
-        add_value(&buffer, BP_SNAPSHOT.clone());
-        add_key_value(&buffer, VOD_SNAPSHOT_1.clone());
-        add_key_value(&buffer, VOD_SNAPSHOT_2.clone());
+    fn should_remove_an_indexed_key() {
+        let (sender, receiver) = RingBufferBuilder::<usize, MarketSnapshot>::new(4)
+            .with_hash_index(RandomState::new())
+            .build();
+        assert!(sender.offer(1, VOD_SNAPSHOT_1));
+        assert!(sender.offer(2, BP_SNAPSHOT));
+        assert_eq!(Some(VOD_SNAPSHOT_1), sender.remove(&1));
 
-        let snapshots = buffer.poll_all();
-        assert_eq!(2, snapshots.len());
+        assert!(sender.offer(1, VOD_SNAPSHOT_2));
+        assert_eq!(vec![VOD_SNAPSHOT_2, BP_SNAPSHOT], receiver.poll_all());
+    }
 
-        assert_eq!(&BP_SNAPSHOT, snapshots.get(0).unwrap());
-        assert_eq!(&VOD_SNAPSHOT_2, snapshots.get(1).unwrap());
+    #[test]
+    fn should_tell_whether_an_offer_would_coalesce() {
+        for indexed in [false, true].iter() {
+            let builder = RingBufferBuilder::<usize, MarketSnapshot>::new(4);
+            let (sender, receiver) = if *indexed {
+                builder.with_hash_index(RandomState::new()).build()
+            } else {
+                builder.build()
+            };
+            assert!(!sender.contains_key(&1));
+            assert!(sender.offer(1, VOD_SNAPSHOT_1));
+            assert!(sender.offer_value_only(BP_SNAPSHOT));
+            assert!(sender.contains_key(&1));
+            assert!(!sender.contains_key(&2));
 
-        assert_is_empty(&buffer);
+            assert!(sender.remove(&1).is_some());
+            assert!(sender.contains_key(&1));
+            assert_eq!(vec![BP_SNAPSHOT], receiver.poll_all());
+            assert!(!sender.contains_key(&1));
+        }
     }
 
     #[test]
-    fn should_count_rejections() {
-        let buffer = create_buffer(2);
-        assert_eq!(0, buffer.rejection_count());
+    fn should_report_metrics_with_the_high_water_mark() {
+        let buffer = create_buffer(4);
+        assert!(buffer.offer(1, VOD_SNAPSHOT_1));
+        assert!(buffer.offer(2, BP_SNAPSHOT));
+        assert!(buffer.offer(1, VOD_SNAPSHOT_2));
+        assert!(buffer.offer(3, BP_SNAPSHOT));
+        assert_eq!(3, buffer.poll_all().len());
+        assert!(buffer.offer(4, BP_SNAPSHOT));
 
-        buffer.offer_value_only(BP_SNAPSHOT.clone());
-        assert_eq!(0, buffer.rejection_count());
+        assert_eq!(
+            BufferMetrics {
+                capacity: 4,
+                current_size: 1,
+                rejection_count: 0,
+                full_rejections: 0,
+                closed_rejections: 0,
+                byte_budget_rejections: 0,
+                coalesced_count: 1,
+                high_water_mark: 3,
+            },
+            buffer.metrics()
+        );
+        buffer.clear();
+        assert_eq!(0, buffer.metrics().high_water_mark);
+    }
 
-        buffer.offer(1, VOD_SNAPSHOT_1.clone());
-        assert_eq!(0, buffer.rejection_count());
+    #[test]
+    fn should_index_keys_with_a_custom_hasher() {
+        #[derive(Default)]
+        struct IdentityHasher(u64);
 
-        buffer.offer(1, VOD_SNAPSHOT_2.clone());
-        assert_eq!(0, buffer.rejection_count());
+        impl std::hash::Hasher for IdentityHasher {
+            fn finish(&self) -> u64 {
+                self.0
+            }
 
-        buffer.offer_value_only(BP_SNAPSHOT.clone());
-        assert_eq!(1, buffer.rejection_count());
+            fn write(&mut self, bytes: &[u8]) {
+                for &byte in bytes {
+                    self.0 = self.0 << 8 | u64::from(byte);
+                }
+            }
 
-        buffer.offer(2, BP_SNAPSHOT.clone());
-        assert_eq!(2, buffer.rejection_count());
+            fn write_usize(&mut self, value: usize) {
+                self.0 = value as u64;
+            }
+        }
+
+        let (sender, receiver) = RingBufferBuilder::<usize, MarketSnapshot>::new(4)
+            .with_hash_index(std::hash::BuildHasherDefault::<IdentityHasher>::default())
+            .build();
+        assert!(sender.offer(1, VOD_SNAPSHOT_1));
+        assert!(sender.offer(2, BP_SNAPSHOT));
+        assert!(sender.offer(1, VOD_SNAPSHOT_2));
+        assert_eq!(1, sender.coalesced_count());
+        assert_eq!(vec![VOD_SNAPSHOT_2, BP_SNAPSHOT], receiver.poll_all());
     }
 
     #[test]
-    fn should_use_object_equality_to_compare_keys() {
-        let buffer: CoalescingRingBuffer<String, MarketSnapshot> = CoalescingRingBuffer::new(2);
+    fn should_keep_the_first_value_under_keep_first() {
+        let mut buffer = create_buffer(4);
+        buffer.policy = CoalescePolicy::KeepFirst;
+        add_key_value(&buffer, VOD_SNAPSHOT_1);
+        add_key_value(&buffer, BP_SNAPSHOT);
+        add_key_value(&buffer, VOD_SNAPSHOT_2);
+        assert_eq!(
+            Ok(Some(VOD_SNAPSHOT_2)),
+            buffer.offer_replace(1, VOD_SNAPSHOT_2)
+        );
+        assert_eq!(2, buffer.coalesced_count());
+        assert_eq!(vec![VOD_SNAPSHOT_1, BP_SNAPSHOT], buffer.poll_all());
+
+        // once read, the key takes a slot again
+        add_key_value(&buffer, VOD_SNAPSHOT_2);
+        assert_eq!(vec![VOD_SNAPSHOT_2], buffer.poll_all());
+    }
+
+    #[test]
+    fn should_fill_a_removed_key_under_keep_first() {
+        let mut buffer = create_buffer(4);
+        buffer.policy = CoalescePolicy::KeepFirst;
+        add_key_value(&buffer, VOD_SNAPSHOT_1);
+        assert_eq!(Some(VOD_SNAPSHOT_1), buffer.remove(&1));
+        add_key_value(&buffer, VOD_SNAPSHOT_2);
+        add_key_value(&buffer, VOD_SNAPSHOT_1);
+        assert_eq!(vec![VOD_SNAPSHOT_2], buffer.poll_all());
+        assert!(buffer.holes.borrow().is_empty());
+    }
+
+    #[test]
+    fn should_skip_an_unexpectedly_empty_slot_without_panicking() {
+        let buffer = create_buffer(4);
+        add_key_value(&buffer, VOD_SNAPSHOT_1);
+        add_key_value(&buffer, BP_SNAPSHOT);
+        add_value(&buffer, VOD_SNAPSHOT_2);
+        let index = buffer.mask(2);
+        assert_eq!(Some(BP_SNAPSHOT), buffer.slots().values[index].take());
+
+        assert_eq!(vec![VOD_SNAPSHOT_1, VOD_SNAPSHOT_2], buffer.poll_all());
+        assert_eq!(3, buffer.last_read.load(Ordering::SeqCst));
+        assert!(buffer.is_empty());
+    }
+
+    fn fill_fixed<const N: usize>() {
+        let (sender, receiver) = new_fixed_ring_buffer::<usize, usize, N>();
+        assert_eq!(N, sender.capacity());
+        for key in 0..N {
+            assert!(sender.offer(key, key));
+        }
+        assert!(sender.offer(0, N));
+        assert!(!sender.offer(N, N));
 
-        buffer.offer(String::from("boo"), BP_SNAPSHOT.clone());
-        buffer.offer(String::from("boo"), BP_SNAPSHOT.clone());
+        let mut expected: Vec<usize> = (0..N).collect();
+        expected[0] = N;
+        assert_eq!(expected, receiver.poll_all());
+    }
+
+    #[test]
+    fn should_build_fixed_capacities() {
+        fill_fixed::<1>();
+        fill_fixed::<2>();
+        fill_fixed::<64>();
+    }
+
+    #[test]
+    fn should_keep_fixed_slots_inline() {
+        let buffer = CoalescingRingBuffer::<usize, usize, 64>::new_fixed();
+        let slots = buffer.slots();
+        assert_eq!(0, slots.keys.heap.capacity());
+        assert_eq!(0, slots.values.heap.capacity());
+        assert_eq!(0, slots.seqs.heap.capacity());
+        assert!(mem::size_of_val(&buffer) > 64 * mem::size_of::<AtomicCell<Option<usize>>>());
+        assert_eq!(mem::size_of_val(&buffer), buffer.capacity_in_bytes());
+        // there is nowhere for them to grow into
+        assert_eq!(Err(Error::InvalidCapacity(128)), buffer.try_grow());
+        assert_eq!(64, buffer.capacity());
+    }
+
+    #[test]
+    fn should_reject_capacities_that_cannot_hold_anything() {
+        let zero = CoalescingRingBuffer::<usize, MarketSnapshot>::try_new(0);
+        assert_eq!(Some(Error::InvalidCapacity(0)), zero.err());
+        let too_large = CoalescingRingBuffer::<usize, MarketSnapshot>::try_new(usize::MAX);
+        assert_eq!(Some(Error::InvalidCapacity(usize::MAX)), too_large.err());
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid buffer capacity 0")]
+    fn should_panic_on_a_zero_capacity() {
+        CoalescingRingBuffer::<usize, MarketSnapshot>::new(0);
+    }
+
+    #[test]
+    fn should_hold_a_single_entry_with_a_capacity_of_one() {
+        let buffer: CoalescingRingBuffer<usize, MarketSnapshot> =
+            CoalescingRingBuffer::try_new(1).unwrap();
+        assert_eq!(1, buffer.capacity());
+        assert!(buffer.offer(1, VOD_SNAPSHOT_1));
+        assert!(buffer.offer(1, VOD_SNAPSHOT_2));
+        assert!(!buffer.offer(2, BP_SNAPSHOT));
+        assert!(buffer.is_full());
+        assert_eq!(vec![VOD_SNAPSHOT_2], buffer.poll_all());
+        assert!(buffer.offer(2, BP_SNAPSHOT));
+        assert_eq!(vec![BP_SNAPSHOT], buffer.poll_all());
+    }
 
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "the write cursor would wrap")]
+    fn should_refuse_to_wrap_the_write_cursor() {
+        let buffer: CoalescingRingBuffer<usize, MarketSnapshot> = CoalescingRingBuffer::new(4);
+        buffer.next_write.store(usize::MAX - 1, Ordering::SeqCst);
+        buffer.first_write.store(usize::MAX - 1, Ordering::SeqCst);
+        buffer.last_read.store(usize::MAX - 2, Ordering::SeqCst);
+        buffer.last_cleaned.store(usize::MAX - 2, Ordering::Relaxed);
+        assert!(buffer.offer(1, VOD_SNAPSHOT_1));
         assert_eq!(1, buffer.size());
+        assert_eq!(vec![VOD_SNAPSHOT_1], buffer.poll_all());
+        buffer.offer(2, BP_SNAPSHOT);
+    }
+
+    #[test]
+    fn should_round_large_capacities_up_to_a_power_of_two() {
+        assert_eq!(1 << 20, next_power_of_two(1 << 20));
+        assert_eq!(1 << 21, next_power_of_two((1 << 20) + 1));
+        assert_eq!(1 << 30, next_power_of_two((1 << 29) + 1));
+        #[cfg(target_pointer_width = "64")]
+        assert_eq!(1 << 33, next_power_of_two((1 << 32) + 1));
+
+        let buffer: CoalescingRingBuffer<u8, u8> = CoalescingRingBuffer::new((1 << 20) + 1);
+        assert_eq!(1 << 21, buffer.capacity());
+    }
+
+    #[test]
+    fn should_keep_shared_values_lock_free_and_drop_coalesced_ones() {
+        assert!(AtomicCell::<Option<Arc<[u64; 512]>>>::is_lock_free());
+        let (sender, receiver) = new_shared_ring_buffer::<usize, MarketSnapshot>(4);
+        let first = Arc::new(VOD_SNAPSHOT_1);
+        let second = Arc::new(VOD_SNAPSHOT_2);
+        assert!(sender.offer(1, Arc::clone(&first)));
+        assert!(sender.offer(1, Arc::clone(&second)));
+        assert_eq!(1, Arc::strong_count(&first));
+
+        let polled = receiver.poll_all();
+        assert_eq!(1, polled.len());
+        assert!(Arc::ptr_eq(&second, &polled[0]));
+    }
+
+    #[test]
+    fn should_number_values_by_the_write_that_left_them() {
+        let buffer: CoalescingRingBuffer<usize, u64> = CoalescingRingBuffer::new(4);
+        assert!(buffer.offer(1, 10));
+        assert!(buffer.offer_value_only(20));
+        assert!(buffer.offer(1, 11));
+        assert!(buffer.offer_with(2, 30, |old, new| old + new));
+        assert!(buffer.offer_with(2, 1, |old, new| old + new));
+        assert_eq!(vec![(2, 11), (1, 20), (4, 31)], buffer.poll_seq(8));
+
+        assert!(buffer.offer(1, 12));
+        assert_eq!(Some(12), buffer.remove(&1));
+        assert!(buffer.offer(1, 13));
+        assert_eq!(vec![(6, 13)], buffer.poll_seq(8));
+    }
+
+    #[test]
+    fn should_give_up_on_size_without_retries() {
+        let buffer = create_buffer(4);
+        add_key_value(&buffer, VOD_SNAPSHOT_1);
+        add_key_value(&buffer, BP_SNAPSHOT);
+        assert_eq!(None, buffer.try_size(0));
+        assert_eq!(Some(2), buffer.try_size(1));
+        assert_eq!(2, buffer.size());
+    }
+
+    #[test]
+    fn should_summarise_the_buffer_in_one_line() {
+        let buffer = create_buffer(1 << 20);
+        add_key_value(&buffer, VOD_SNAPSHOT_1);
+        add_value(&buffer, BP_SNAPSHOT);
+        buffer.poll(1);
+        assert_eq!(
+            "capacity 1048576, size 1, first_write 2, next_write 3, last_read 1, rejections 0",
+            buffer.to_string()
+        );
+        assert_eq!(
+            "CoalescingRingBuffer { capacity: 1048576, size: 1, first_write: 2, next_write: 3, \
+             last_read: 1, rejection_count: 0 }",
+            format!("{:?}", buffer)
+        );
+    }
+
+    #[test]
+    fn should_report_a_footprint_that_scales_with_capacity() {
+        let footprint = |capacity| create_buffer(capacity).capacity_in_bytes();
+        let per_slot = (footprint(1 << 11) - footprint(1 << 10)) >> 10;
+        assert_eq!(per_slot << 9, footprint(1 << 10) - footprint(1 << 9));
+        assert!(per_slot >= mem::size_of::<MarketSnapshot>());
+        assert_eq!(
+            mem::size_of::<CoalescingRingBuffer<usize, MarketSnapshot>>() + (per_slot << 10),
+            footprint(1 << 10)
+        );
+
+        let (sender, _receiver) = RingBufferBuilder::<usize, MarketSnapshot>::new(1 << 10)
+            .measure_latency()
+            .build();
+        let stamps = (1 << 10) * mem::size_of::<AtomicU64>();
+        assert_eq!(
+            footprint(1 << 10) + stamps,
+            sender.stats_handle().capacity_in_bytes()
+        );
+    }
+
+    #[test]
+    fn should_hand_back_the_remaining_values() {
+        let buffer = create_buffer(4);
+        add_key_value(&buffer, VOD_SNAPSHOT_1);
+        add_value(&buffer, BP_SNAPSHOT);
+        add_key_value(&buffer, VOD_SNAPSHOT_2);
+        assert_eq!(vec![VOD_SNAPSHOT_2, BP_SNAPSHOT], buffer.into_remaining());
     }
 
     fn assert_is_empty(buffer: &CoalescingRingBuffer<usize, MarketSnapshot>) {
@@ -553,7 +5165,7 @@ mod tests {
         let actual = buffer.poll_all();
         let ret = (actual.len() == expected.len()) && // zip stops at the shortest
             actual.iter().zip(expected).all(|(a, b)| a == &b);
-        return ret;
+        ret
     }
 
     fn add_value(buffer: &CoalescingRingBuffer<usize, MarketSnapshot>, snapshot: MarketSnapshot) {
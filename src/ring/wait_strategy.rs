@@ -0,0 +1,102 @@
+use core::hint;
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// spins of `Yielding` before it gives the CPU away
+const YIELD_AFTER_SPINS: usize = 64;
+
+/// How the blocking calls, such as `Receiver::poll_blocking` and `Sender::offer_blocking`,
+/// wait for the other side, see `RingBufferBuilder::wait_strategy`. The caller checks its
+/// condition again every time `wait` returns, so returning early is fine, but a `signal` that
+/// comes before the `wait` it is meant for must not be lost.
+pub trait WaitStrategy: Send + Sync {
+    fn wait(&self);
+
+    /// Same as `wait` but for at most `timeout`, which a strategy that sleeps must honour.
+    fn wait_timeout(&self, timeout: Duration);
+
+    /// Called by the other side after every change that a waiter may be waiting for.
+    fn signal(&self);
+}
+
+/// Spins on the CPU and never sleeps: the lowest latency, at the cost of a busy core.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BusySpin;
+
+impl WaitStrategy for BusySpin {
+    fn wait(&self) {
+        hint::spin_loop();
+    }
+
+    // a single spin is over long before any timeout
+    fn wait_timeout(&self, _timeout: Duration) {
+        self.wait()
+    }
+
+    fn signal(&self) {}
+}
+
+/// Spins for a little while and then yields the thread, which leaves the core to others
+/// while still waking up quickly.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Yielding;
+
+impl WaitStrategy for Yielding {
+    fn wait(&self) {
+        for _ in 0..YIELD_AFTER_SPINS {
+            hint::spin_loop();
+        }
+        thread::yield_now();
+    }
+
+    fn wait_timeout(&self, _timeout: Duration) {
+        self.wait()
+    }
+
+    fn signal(&self) {}
+}
+
+/// Parks the thread on a condition variable until it is signalled: the least CPU, but every
+/// signal takes a lock. The buffer waits the same way when no strategy is set. A clone starts
+/// out unsignalled, with a condition variable of its own.
+#[derive(Debug, Default)]
+pub struct Blocking {
+    // a signal not waited for yet
+    signalled: Mutex<bool>,
+    condvar: Condvar,
+}
+
+impl Blocking {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Clone for Blocking {
+    fn clone(&self) -> Self {
+        Self::new()
+    }
+}
+
+impl WaitStrategy for Blocking {
+    fn wait(&self) {
+        let signalled = self.signalled.lock().unwrap();
+        let mut signalled = self.condvar.wait_while(signalled, |s| !*s).unwrap();
+        *signalled = false;
+    }
+
+    fn wait_timeout(&self, timeout: Duration) {
+        let signalled = self.signalled.lock().unwrap();
+        let (mut signalled, _) = self
+            .condvar
+            .wait_timeout_while(signalled, timeout, |s| !*s)
+            .unwrap();
+        *signalled = false;
+    }
+
+    fn signal(&self) {
+        *self.signalled.lock().unwrap() = true;
+        self.condvar.notify_all();
+    }
+}
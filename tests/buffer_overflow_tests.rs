@@ -7,7 +7,8 @@ mod tests {
 
     #[test]
     fn should_be_able_to_reuse_capacity() {
-        let (sender, receiver) = new_ring_buffer(32);
+        let (mut sender, receiver) = new_ring_buffer(32);
+        sender.reserve_control_slots(1);
         let producer = thread::spawn(move || producer_task(sender));
         let _consumer = thread::spawn(move || consumer_task(receiver));
 
@@ -15,18 +16,394 @@ mod tests {
         assert!(!producer_overflow, "ring simple has overflowed");
     }
 
+    #[test]
+    fn should_keep_delivering_in_order_while_overwriting_oldest() {
+        let (sender, receiver) = new_ring_buffer(8);
+        let producer = thread::spawn(move || {
+            for value in 0..100000 {
+                assert!(sender.offer_overwrite_oldest(value, value));
+            }
+            sender.offer_overwrite_oldest(POISON_PILL, POISON_PILL);
+            sender.overwritten_count()
+        });
+
+        let mut last_seen = POISON_PILL;
+        let mut received = 0;
+        loop {
+            let values = receiver.poll(3);
+            for &value in values.iter().filter(|&&value| value != POISON_PILL) {
+                assert!(value > last_seen, "{} delivered after {}", value, last_seen);
+                last_seen = value;
+                received += 1;
+            }
+            if values.contains(&POISON_PILL) {
+                break;
+            }
+        }
+        let overwritten = producer.join().unwrap();
+        assert_eq!(100000, received + overwritten);
+    }
+
+    #[test]
+    fn should_deliver_everything_in_order_while_growing() {
+        let (mut sender, receiver) = RingBufferBuilder::new(2).grow_when_full(1 << 16).build();
+        let producer = thread::spawn(move || {
+            for value in 0..50000 {
+                assert!(sender.offer(value, value));
+                if value == 25000 {
+                    sender.try_grow().unwrap();
+                }
+            }
+            assert!(sender.offer_value_only(POISON_PILL));
+        });
+
+        let mut expected = 0;
+        loop {
+            let values = receiver.poll(7);
+            for &value in values.iter().filter(|&&value| value != POISON_PILL) {
+                assert_eq!(expected, value);
+                expected += 1;
+            }
+            if values.contains(&POISON_PILL) {
+                break;
+            }
+        }
+        producer.join().unwrap();
+        assert_eq!(50000, expected);
+    }
+
+    #[test]
+    fn should_deliver_a_monotonic_stream_per_key_under_load() {
+        const KEYS: i32 = 16;
+        const MESSAGES: i32 = 4_000_000;
+        let (mut sender, receiver) = new_ring_buffer(64);
+        sender.reserve_control_slots(1);
+        let producer = thread::spawn(move || {
+            for message in 0..MESSAGES {
+                while !sender.offer(message % KEYS, message) {
+                    thread::yield_now();
+                }
+            }
+            assert!(sender.offer_control(POISON_PILL));
+        });
+
+        let mut last_seen = vec![POISON_PILL; KEYS as usize];
+        let mut bucket = Vec::new();
+        loop {
+            receiver.poll_into(&mut bucket, 5);
+            for &message in bucket.iter().filter(|&&message| message != POISON_PILL) {
+                let key = (message % KEYS) as usize;
+                assert!(
+                    message > last_seen[key],
+                    "{} after {}",
+                    message,
+                    last_seen[key]
+                );
+                last_seen[key] = message;
+            }
+            if bucket.contains(&POISON_PILL) {
+                break;
+            }
+        }
+        producer.join().unwrap();
+        // the last update of every key always gets through
+        for (key, &message) in last_seen.iter().enumerate() {
+            assert_eq!(MESSAGES - KEYS + key as i32, message);
+        }
+    }
+
+    #[test]
+    fn should_deliver_a_monotonic_stream_per_key_to_every_broadcast_receiver() {
+        const KEYS: i32 = 16;
+        const MESSAGES: i32 = 200_000;
+        let (sender, receivers) = new_broadcast_ring_buffer(64, 3);
+        let producer = thread::spawn(move || {
+            for message in 0..MESSAGES {
+                while !sender.offer(message % KEYS, message) {
+                    thread::yield_now();
+                }
+            }
+        });
+
+        let consumers: Vec<_> = receivers
+            .into_iter()
+            .map(|receiver| {
+                thread::spawn(move || {
+                    let mut last_seen = vec![POISON_PILL; KEYS as usize];
+                    loop {
+                        // read once more after the sender is gone, for its last offers
+                        let disconnected = receiver.is_disconnected();
+                        for message in receiver.poll(5) {
+                            let key = (message % KEYS) as usize;
+                            assert!(
+                                message > last_seen[key],
+                                "{} after {}",
+                                message,
+                                last_seen[key]
+                            );
+                            last_seen[key] = message;
+                        }
+                        if disconnected && receiver.size() == 0 {
+                            break;
+                        }
+                    }
+                    last_seen
+                })
+            })
+            .collect();
+        producer.join().unwrap();
+        for consumer in consumers {
+            // the last update of every key always gets through
+            for (key, &message) in consumer.join().unwrap().iter().enumerate() {
+                assert_eq!(MESSAGES - KEYS + key as i32, message);
+            }
+        }
+    }
+
+    #[test]
+    fn should_neither_lose_nor_duplicate_merged_updates() {
+        const KEYS: usize = 4;
+        const UPDATES: usize = 400_000;
+        let (sender, receiver) = new_ring_buffer::<usize, usize>(8);
+        let producer = thread::spawn(move || {
+            for update in 0..UPDATES {
+                while !sender.offer_with(update % KEYS, 1, |old, new| old + new) {
+                    thread::yield_now();
+                }
+            }
+        });
+
+        let mut totals = [0; KEYS];
+        loop {
+            let disconnected = receiver.is_disconnected();
+            let entries = receiver.poll_keyed(3);
+            if entries.is_empty() && disconnected {
+                break;
+            }
+            for (key, count) in entries {
+                totals[key.unwrap()] += count;
+            }
+        }
+        producer.join().unwrap();
+        assert_eq!([UPDATES / KEYS; KEYS], totals);
+    }
+
+    #[test]
+    fn should_coalesce_offers_from_two_producers() {
+        const UPDATES: i32 = 200_000;
+        let (sender, receiver) = new_mpsc_ring_buffer::<i32, i32>(16);
+        let producers: Vec<_> = (0..2)
+            .map(|producer| {
+                let sender = sender.clone();
+                // every producer owns four keys and counts up on each of them
+                thread::spawn(move || {
+                    for update in 0..UPDATES {
+                        let key = producer * 4 + update % 4;
+                        while !sender.offer(key, update) {
+                            thread::yield_now();
+                        }
+                    }
+                })
+            })
+            .collect();
+        drop(sender);
+
+        let mut last_seen = [-1; 8];
+        loop {
+            let disconnected = receiver.is_disconnected();
+            let entries = receiver.poll_keyed(5);
+            if entries.is_empty() && disconnected {
+                break;
+            }
+            for (key, update) in entries {
+                let key = key.unwrap() as usize;
+                assert!(
+                    update > last_seen[key],
+                    "{} after {}",
+                    update,
+                    last_seen[key]
+                );
+                last_seen[key] = update;
+            }
+        }
+        for producer in producers {
+            producer.join().unwrap();
+        }
+        for (key, &update) in last_seen.iter().enumerate() {
+            assert_eq!(UPDATES - 4 + (key % 4) as i32, update);
+        }
+    }
+
+    #[test]
+    fn should_account_for_every_offer_under_contention() {
+        const OFFERS: usize = 1_000_000;
+        let (sender, receiver) = new_ring_buffer::<usize, usize>(4);
+        let producer = thread::spawn(move || {
+            let accepted = (0..OFFERS)
+                .filter(|&offer| sender.offer(offer % 8, offer))
+                .count();
+            (accepted, sender.coalesced_count(), sender.rejection_count())
+        });
+
+        let mut received = 0;
+        loop {
+            let disconnected = receiver.is_disconnected();
+            let values = receiver.poll(3);
+            if values.is_empty() && disconnected {
+                break;
+            }
+            received += values.len();
+        }
+        // each accepted offer was either delivered or replaced while pending
+        let (accepted, coalesced, rejected) = producer.join().unwrap();
+        assert_eq!(OFFERS, accepted + rejected);
+        assert_eq!(accepted, received + coalesced);
+    }
+
+    #[test]
+    fn should_deliver_the_newest_value_when_polling_by_bytes() {
+        const KEYS: i32 = 4;
+        const UPDATES: i32 = 200_000;
+        let (mut sender, receiver) = new_ring_buffer::<i32, i32>(8);
+        sender.reserve_control_slots(1);
+        let producer = thread::spawn(move || {
+            for update in 0..UPDATES {
+                while !sender.offer(update % KEYS, update) {
+                    thread::yield_now();
+                }
+            }
+            assert!(sender.offer_control(POISON_PILL));
+        });
+
+        // a budget of two values leaves the third one pending while the producer coalesces
+        let mut last_seen = [POISON_PILL; KEYS as usize];
+        loop {
+            let values = receiver.poll_bounded_bytes(2, |_| 1);
+            for &update in values.iter().filter(|&&update| update != POISON_PILL) {
+                let key = (update % KEYS) as usize;
+                assert!(
+                    update > last_seen[key],
+                    "{} after {}",
+                    update,
+                    last_seen[key]
+                );
+                last_seen[key] = update;
+            }
+            if values.contains(&POISON_PILL) {
+                break;
+            }
+        }
+        producer.join().unwrap();
+        for (key, &update) in last_seen.iter().enumerate() {
+            assert_eq!(UPDATES - KEYS + key as i32, update);
+        }
+    }
+
+    #[test]
+    fn should_offer_whole_batches_while_the_consumer_polls() {
+        const BATCHES: usize = 50_000;
+        let (sender, receiver) = new_ring_buffer::<usize, usize>(4);
+        let producer = thread::spawn(move || {
+            // the keys are pending from the previous batch, so each one coalesces unless the
+            // consumer gets to it first
+            let batch = [(0, 0), (1, 1), (2, 2), (3, 3)];
+            let accepted = (0..BATCHES)
+                .filter(|_| sender.offer_batch_atomic(&batch))
+                .count();
+            (accepted, sender.coalesced_count())
+        });
+
+        let mut received = 0;
+        loop {
+            let disconnected = receiver.is_disconnected();
+            let values = receiver.poll(1);
+            if values.is_empty() && disconnected {
+                break;
+            }
+            received += values.len();
+        }
+        let (accepted, coalesced) = producer.join().unwrap();
+        assert_eq!(4 * accepted, received + coalesced);
+    }
+
+    #[test]
+    fn should_number_every_write_exactly_under_contention() {
+        const OFFERS: u64 = 200_000;
+        let (sender, receiver) = new_ring_buffer::<u64, u64>(4);
+        let producer = thread::spawn(move || {
+            // no offer is ever rejected, so the n-th value is the n-th write
+            for offer in 0..OFFERS {
+                while !sender.offer(offer % 8, offer) {
+                    thread::yield_now();
+                }
+            }
+            sender.coalesced_count()
+        });
+
+        let mut received = 0;
+        loop {
+            let disconnected = receiver.is_disconnected();
+            let entries = receiver.poll_seq(3);
+            if entries.is_empty() {
+                if disconnected {
+                    break;
+                }
+                thread::yield_now();
+            }
+            for (seq, value) in entries {
+                assert_eq!(value, seq);
+                received += 1;
+            }
+        }
+        let coalesced = producer.join().unwrap();
+        assert_eq!(OFFERS as usize, received + coalesced);
+    }
+
+    // Many short runs rather than one long one, so that the few interleavings of a couple of
+    // offers against a poll each get a chance to happen.
+    #[test]
+    fn should_neither_lose_nor_duplicate_in_short_interleavings() {
+        for _ in 0..10_000 {
+            let (sender, receiver) = new_ring_buffer::<usize, usize>(2);
+            let producer = thread::spawn(move || {
+                assert!(sender.offer(1, 1));
+                assert!(sender.offer(2, 2));
+                assert!(sender.offer(1, 3));
+                sender.coalesced_count()
+            });
+
+            let mut received = Vec::new();
+            loop {
+                let disconnected = receiver.is_disconnected();
+                received.extend(receiver.poll(1));
+                let (last_read, first_write, next_write) = receiver.cursors();
+                assert_eq!(last_read + 1, first_write);
+                assert!(first_write <= next_write);
+                if disconnected && receiver.size() == 0 {
+                    break;
+                }
+                thread::yield_now();
+            }
+            let coalesced = producer.join().unwrap();
+            // the latest value for each key always arrives, and only superseded ones go missing
+            assert_eq!(3, received.len() + coalesced);
+            assert_eq!(Some(&3), received.iter().rfind(|&&value| value != 2));
+            assert_eq!(1, received.iter().filter(|&&value| value == 2).count());
+        }
+    }
+
     fn producer_task(sender: Sender<i32, i32>) -> bool {
         for run in 0..100000 {
             for message in 0..10 {
                 let success = sender.offer(message, run * 10 + message);
                 if !success {
-                    sender.offer_value_only(POISON_PILL);
+                    assert!(sender.offer_control(POISON_PILL));
                     return true;
                 }
             }
         }
-        sender.offer_value_only(POISON_PILL);
-        return false;
+        assert!(sender.offer_control(POISON_PILL));
+        false
     }
 
     fn consumer_task(receiver: Receiver<i32, i32>) {
@@ -37,5 +414,4 @@ mod tests {
             }
         }
     }
-
 }
@@ -0,0 +1,5 @@
+#[test]
+fn handles_with_non_send_payloads_cannot_cross_threads() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/*.rs");
+}
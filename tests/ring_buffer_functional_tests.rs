@@ -1,7 +1,16 @@
 #[cfg(test)]
 #[allow(dead_code, unused)]
 mod tests {
-    use coalescing_buffer::ring::{new_ring_buffer, Receiver, Sender};
+    use coalescing_buffer::ring::{
+        new_broadcast_ring_buffer, new_mpsc_ring_buffer, new_ring_buffer, try_new_ring_buffer,
+        Blocking, BusySpin, CoalescePolicy, CoalescingError, CoalescingRingBuffer, Disconnected,
+        Error, OfferError, PollStats, Receiver, RejectReason, RingBufferBuilder, Selector, Sender,
+        StatsHandle, TryRecvError, WaitStrategy, Yielding,
+    };
+    use std::collections::hash_map::RandomState;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
 
     static VOD_SNAPSHOT_1: MarketSnapshot = MarketSnapshot {
         instrument_id: 1,
@@ -29,7 +38,7 @@ mod tests {
     impl MarketSnapshot {
         pub fn new(instrument_id: usize, best_bid: isize, best_ask: isize) -> Self {
             MarketSnapshot {
-                instrument_id: instrument_id,
+                instrument_id,
                 bid: best_bid,
                 ask: best_ask,
             }
@@ -48,20 +57,20 @@ mod tests {
     #[test]
     fn should_reject_new_keys_when_full() {
         let (sender, receiver) = create_buf(2);
-        sender.offer(1, BP_SNAPSHOT.clone());
-        sender.offer(2, VOD_SNAPSHOT_1.clone());
+        sender.offer(1, BP_SNAPSHOT);
+        sender.offer(2, VOD_SNAPSHOT_1);
 
-        assert!(!sender.offer(4, VOD_SNAPSHOT_2.clone()));
+        assert!(!sender.offer(4, VOD_SNAPSHOT_2));
         assert_eq!(2, sender.size());
     }
 
     #[test]
     fn should_accept_existing_keys_when_full() {
         let (sender, receiver) = create_buf(2);
-        sender.offer(1, BP_SNAPSHOT.clone());
-        sender.offer(2, VOD_SNAPSHOT_1.clone());
+        sender.offer(1, BP_SNAPSHOT);
+        sender.offer(2, VOD_SNAPSHOT_1);
 
-        assert!(sender.offer(2, VOD_SNAPSHOT_2.clone()));
+        assert!(sender.offer(2, VOD_SNAPSHOT_2));
         assert_eq!(2, sender.size());
     }
 
@@ -69,74 +78,86 @@ mod tests {
     fn should_return_single_value() {
         let (sender, receiver) = create_buf(2);
 
-        add_key_value(&sender, BP_SNAPSHOT.clone());
-        assert_contains(&receiver, vec![BP_SNAPSHOT.clone()]);
+        add_key_value(&sender, BP_SNAPSHOT);
+        assert_contains(&receiver, vec![BP_SNAPSHOT]);
     }
 
     #[test]
     fn should_return_two_values_with_different_keys() {
         let (sender, receiver) = create_buf(2);
-        add_key_value(&sender, BP_SNAPSHOT.clone());
-        add_key_value(&sender, VOD_SNAPSHOT_1.clone());
+        add_key_value(&sender, BP_SNAPSHOT);
+        add_key_value(&sender, VOD_SNAPSHOT_1);
 
-        assert_contains(&receiver, vec![BP_SNAPSHOT.clone(), VOD_SNAPSHOT_1.clone()]);
+        assert_contains(&receiver, vec![BP_SNAPSHOT, VOD_SNAPSHOT_1]);
     }
 
     #[test]
     fn should_update_values_with_equal_keys() {
         let (sender, receiver) = create_buf(2);
-        add_key_value(&sender, VOD_SNAPSHOT_1.clone());
-        add_key_value(&sender, VOD_SNAPSHOT_2.clone());
-        assert_contains(&receiver, vec![VOD_SNAPSHOT_2.clone()]);
+        add_key_value(&sender, VOD_SNAPSHOT_1);
+        add_key_value(&sender, VOD_SNAPSHOT_2);
+        assert_contains(&receiver, vec![VOD_SNAPSHOT_2]);
+    }
+
+    #[test]
+    fn should_pick_the_value_per_coalesce_policy() {
+        for &(policy, expected) in [
+            (CoalescePolicy::KeepLast, VOD_SNAPSHOT_2),
+            (CoalescePolicy::KeepFirst, VOD_SNAPSHOT_1),
+        ]
+        .iter()
+        {
+            let (sender, receiver) = RingBufferBuilder::new(2).coalesce_policy(policy).build();
+            add_key_value(&sender, VOD_SNAPSHOT_1);
+            add_key_value(&sender, VOD_SNAPSHOT_2);
+            assert_eq!(vec![expected], receiver.poll_all());
+        }
     }
 
     #[test]
     fn should_not_update_values_without_keys() {
         let (sender, receiver) = create_buf(2);
-        add_value(&sender, VOD_SNAPSHOT_1.clone());
-        add_value(&sender, VOD_SNAPSHOT_2.clone());
-        assert_contains(
-            &receiver,
-            vec![VOD_SNAPSHOT_1.clone(), VOD_SNAPSHOT_2.clone()],
-        );
+        add_value(&sender, VOD_SNAPSHOT_1);
+        add_value(&sender, VOD_SNAPSHOT_2);
+        assert_contains(&receiver, vec![VOD_SNAPSHOT_1, VOD_SNAPSHOT_2]);
     }
 
     #[test]
     fn should_update_values_with_equal_keys_and_preserve_ordering() {
         let (sender, receiver) = create_buf(2);
-        add_key_value(&sender, VOD_SNAPSHOT_1.clone());
-        add_key_value(&sender, BP_SNAPSHOT.clone());
-        add_key_value(&sender, VOD_SNAPSHOT_2.clone());
+        add_key_value(&sender, VOD_SNAPSHOT_1);
+        add_key_value(&sender, BP_SNAPSHOT);
+        add_key_value(&sender, VOD_SNAPSHOT_2);
 
-        assert_contains(&receiver, vec![VOD_SNAPSHOT_2.clone(), BP_SNAPSHOT.clone()]);
+        assert_contains(&receiver, vec![VOD_SNAPSHOT_2, BP_SNAPSHOT]);
     }
 
     #[test]
     fn should_not_update_values_if_read_occurs_between_values() {
         let (sender, receiver) = create_buf(2);
 
-        add_key_value(&sender, VOD_SNAPSHOT_1.clone());
-        assert_contains(&receiver, vec![VOD_SNAPSHOT_1.clone()]);
+        add_key_value(&sender, VOD_SNAPSHOT_1);
+        assert_contains(&receiver, vec![VOD_SNAPSHOT_1]);
 
-        add_key_value(&sender, VOD_SNAPSHOT_2.clone());
-        assert_contains(&receiver, vec![VOD_SNAPSHOT_2.clone()]);
+        add_key_value(&sender, VOD_SNAPSHOT_2);
+        assert_contains(&receiver, vec![VOD_SNAPSHOT_2]);
     }
 
     #[test]
     fn should_return_only_the_maximum_number_of_requested_items() {
         let (sender, receiver) = create_buf(10);
-        add_value(&sender, BP_SNAPSHOT.clone());
-        add_value(&sender, VOD_SNAPSHOT_1.clone());
-        add_value(&sender, VOD_SNAPSHOT_2.clone());
+        add_value(&sender, BP_SNAPSHOT);
+        add_value(&sender, VOD_SNAPSHOT_1);
+        add_value(&sender, VOD_SNAPSHOT_2);
 
         let snapshots = receiver.poll(2);
         assert_eq!(2, snapshots.len());
-        assert_eq!(&BP_SNAPSHOT, snapshots.get(0).unwrap());
+        assert_eq!(&BP_SNAPSHOT, snapshots.first().unwrap());
         assert_eq!(&VOD_SNAPSHOT_1, snapshots.get(1).unwrap());
 
         let snapshots = receiver.poll(1);
         assert_eq!(1, snapshots.len());
-        assert_eq!(&VOD_SNAPSHOT_2, snapshots.get(0).unwrap());
+        assert_eq!(&VOD_SNAPSHOT_2, snapshots.first().unwrap());
 
         assert_is_empty(&receiver);
     }
@@ -144,14 +165,14 @@ mod tests {
     #[test]
     fn should_return_all_items_without_request_limit() {
         let (sender, receiver) = create_buf(10);
-        add_value(&sender, BP_SNAPSHOT.clone());
-        add_key_value(&sender, VOD_SNAPSHOT_1.clone());
-        add_key_value(&sender, VOD_SNAPSHOT_2.clone());
+        add_value(&sender, BP_SNAPSHOT);
+        add_key_value(&sender, VOD_SNAPSHOT_1);
+        add_key_value(&sender, VOD_SNAPSHOT_2);
 
         let snapshots = receiver.poll_all();
         assert_eq!(2, snapshots.len());
 
-        assert_eq!(&BP_SNAPSHOT, snapshots.get(0).unwrap());
+        assert_eq!(&BP_SNAPSHOT, snapshots.first().unwrap());
         assert_eq!(&VOD_SNAPSHOT_2, snapshots.get(1).unwrap());
 
         assert_is_empty(&receiver);
@@ -162,19 +183,19 @@ mod tests {
         let (sender, receiver) = create_buf(2);
         assert_eq!(0, sender.rejection_count());
 
-        sender.offer_value_only(BP_SNAPSHOT.clone());
+        sender.offer_value_only(BP_SNAPSHOT);
         assert_eq!(0, sender.rejection_count());
 
-        sender.offer(1, VOD_SNAPSHOT_1.clone());
+        sender.offer(1, VOD_SNAPSHOT_1);
         assert_eq!(0, sender.rejection_count());
 
-        sender.offer(1, VOD_SNAPSHOT_2.clone());
+        sender.offer(1, VOD_SNAPSHOT_2);
         assert_eq!(0, sender.rejection_count());
 
-        sender.offer_value_only(BP_SNAPSHOT.clone());
+        sender.offer_value_only(BP_SNAPSHOT);
         assert_eq!(1, sender.rejection_count());
 
-        sender.offer(2, BP_SNAPSHOT.clone());
+        sender.offer(2, BP_SNAPSHOT);
         assert_eq!(2, sender.rejection_count());
     }
 
@@ -182,10 +203,1206 @@ mod tests {
     fn should_use_object_equality_to_compare_keys() {
         let (sender, receiver) = new_ring_buffer::<String, MarketSnapshot>(2);
 
-        sender.offer(String::from("boo"), BP_SNAPSHOT.clone());
-        sender.offer(String::from("boo"), BP_SNAPSHOT.clone());
+        sender.offer(String::from("boo"), BP_SNAPSHOT);
+        sender.offer(String::from("boo"), BP_SNAPSHOT);
+
+        assert_eq!(1, sender.size());
+    }
+
+    #[test]
+    fn should_wait_until_the_offered_value_is_read() {
+        let (sender, receiver) = create_buf(4);
+        add_key_value(&sender, BP_SNAPSHOT);
+
+        let consumer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            receiver.poll_all()
+        });
+        assert!(sender.offer_and_wait_read(1, VOD_SNAPSHOT_1, Duration::from_secs(10)));
+        assert_eq!(vec![BP_SNAPSHOT, VOD_SNAPSHOT_1], consumer.join().unwrap());
+    }
+
+    #[test]
+    fn should_time_out_when_the_offered_value_is_not_read() {
+        let (sender, receiver) = create_buf(2);
+
+        assert!(!sender.offer_and_wait_read(1, VOD_SNAPSHOT_1, Duration::from_millis(10)));
+        assert_eq!(vec![VOD_SNAPSHOT_1], receiver.poll_all());
+
+        add_key_value(&sender, BP_SNAPSHOT);
+        add_key_value(&sender, VOD_SNAPSHOT_1);
+        assert!(!sender.offer_and_wait_read(3, BP_SNAPSHOT, Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn should_not_wait_for_a_value_that_is_not_delivered() {
+        let (sender, receiver) = RingBufferBuilder::<usize, MarketSnapshot>::new(4)
+            .coalesce_policy(CoalescePolicy::KeepFirst)
+            .build();
+        add_key_value(&sender, VOD_SNAPSHOT_1);
+        let started = Instant::now();
+        assert!(!sender.offer_and_wait_read(1, VOD_SNAPSHOT_2, Duration::from_secs(10)));
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(vec![VOD_SNAPSHOT_1], receiver.poll_all());
+
+        let (sender, receiver) = RingBufferBuilder::<usize, MarketSnapshot>::new(4)
+            .ttl(Duration::from_millis(1))
+            .build();
+        let consumer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            receiver.poll_all()
+        });
+        assert!(!sender.offer_and_wait_read(1, VOD_SNAPSHOT_1, Duration::from_secs(10)));
+        assert_eq!(Vec::<MarketSnapshot>::new(), consumer.join().unwrap());
+    }
+
+    #[test]
+    fn should_report_full_as_an_error() {
+        let (sender, receiver) = create_buf(2);
+        assert_eq!(Ok(()), sender.try_offer(1, VOD_SNAPSHOT_1));
+        assert_eq!(Ok(()), sender.try_offer_value_only(BP_SNAPSHOT));
+
+        assert_eq!(Ok(()), sender.try_offer(1, VOD_SNAPSHOT_2));
+        assert_eq!(
+            Err(OfferError::Full {
+                key: 2,
+                value: BP_SNAPSHOT
+            }),
+            sender.try_offer(2, BP_SNAPSHOT)
+        );
+        assert_eq!(
+            Err(CoalescingError::Full),
+            sender.try_offer_value_only(BP_SNAPSHOT)
+        );
+        assert_eq!(2, sender.rejection_count());
+        assert_eq!("buffer is full", CoalescingError::Full.to_string());
+
+        assert_eq!(vec![VOD_SNAPSHOT_2, BP_SNAPSHOT], receiver.poll_all());
+    }
+
+    #[test]
+    fn should_reject_a_zero_capacity() {
+        match try_new_ring_buffer::<usize, MarketSnapshot>(0) {
+            Err(error) => assert_eq!(CoalescingError::InvalidCapacity(0), error),
+            Ok(_) => panic!("a zero capacity buffer should not be created"),
+        }
+        assert!(try_new_ring_buffer::<usize, MarketSnapshot>(1).is_ok());
+    }
+
+    #[test]
+    fn should_return_remaining_values_and_close_on_into_remaining() {
+        let (sender, receiver) = create_buf(4);
+        add_key_value(&sender, VOD_SNAPSHOT_1);
+        add_value(&sender, BP_SNAPSHOT);
+        add_key_value(&sender, VOD_SNAPSHOT_2);
+
+        assert_eq!(vec![VOD_SNAPSHOT_2, BP_SNAPSHOT], receiver.into_remaining());
+        assert!(sender.is_closed());
+        assert_eq!(0, sender.size());
+        match sender.try_offer(VOD_SNAPSHOT_1.instrument_id, VOD_SNAPSHOT_1) {
+            Err(error @ OfferError::Closed { .. }) => {
+                assert_eq!("buffer is closed", error.to_string());
+                assert_eq!((1, VOD_SNAPSHOT_1), error.into_inner());
+            }
+            other => panic!("expected the offer to be closed, got {:?}", other),
+        }
+        assert!(!sender.offer_value_only(BP_SNAPSHOT));
+        assert_eq!(0, sender.rejection_count());
+    }
+
+    #[test]
+    fn should_close_when_the_receiver_is_dropped() {
+        let (sender, receiver) = create_buf(4);
+        assert!(!sender.is_closed());
+        drop(receiver);
+        assert!(sender.is_closed());
+        assert!(!sender.offer(VOD_SNAPSHOT_1.instrument_id, VOD_SNAPSHOT_1));
+    }
+
+    #[test]
+    fn should_fail_fast_once_the_receiver_is_gone() {
+        let (sender, receiver) = create_buf(4);
+        add_key_value(&sender, VOD_SNAPSHOT_1);
+        drop(receiver);
 
+        // neither coalesced with the pending entry nor stored, and not counted as rejected
+        assert_eq!(
+            Err(OfferError::Closed {
+                key: 1,
+                value: VOD_SNAPSHOT_2
+            }),
+            sender.try_offer(1, VOD_SNAPSHOT_2)
+        );
+        assert_eq!(
+            Err(CoalescingError::Closed),
+            sender.try_offer_value_only(BP_SNAPSHOT)
+        );
+        assert!(!sender.offer_control(BP_SNAPSHOT));
         assert_eq!(1, sender.size());
+        assert_eq!(0, sender.rejection_count());
+    }
+
+    #[test]
+    fn should_still_accept_a_control_message_when_data_offers_are_rejected() {
+        let (mut sender, receiver) = create_buf(2);
+        sender.reserve_control_slots(1);
+        add_key_value(&sender, VOD_SNAPSHOT_1);
+
+        assert!(!sender.offer(BP_SNAPSHOT.instrument_id, BP_SNAPSHOT));
+        assert!(!sender.offer_value_only(BP_SNAPSHOT));
+        assert!(sender.offer_control(BP_SNAPSHOT));
+        assert!(!sender.offer_control(BP_SNAPSHOT));
+        assert_eq!(3, sender.rejection_count());
+
+        assert_eq!(vec![VOD_SNAPSHOT_1, BP_SNAPSHOT], receiver.poll_all());
+    }
+
+    #[test]
+    fn should_block_until_a_value_is_offered() {
+        let (sender, receiver) = create_buf(4);
+        let consumer = thread::spawn(move || receiver.poll_blocking(10));
+
+        thread::sleep(Duration::from_millis(20));
+        add_key_value(&sender, VOD_SNAPSHOT_1);
+        assert_eq!(vec![VOD_SNAPSHOT_1], consumer.join().unwrap());
+    }
+
+    #[test]
+    fn should_not_lose_wakeups_when_polling_blocking() {
+        let (sender, receiver) = new_ring_buffer::<usize, usize>(64);
+        let producer = thread::spawn(move || {
+            for value in 0..10000 {
+                while !sender.offer_value_only(value) {
+                    thread::yield_now();
+                }
+            }
+        });
+
+        let mut received = 0;
+        while received < 10000 {
+            for value in receiver.poll_blocking(3) {
+                assert_eq!(received, value);
+                received += 1;
+            }
+        }
+        producer.join().unwrap();
+    }
+
+    #[test]
+    fn should_return_empty_when_poll_times_out() {
+        let (_sender, receiver) = create_buf(4);
+        let started = Instant::now();
+        let values = receiver.poll_timeout(10, Duration::from_millis(20));
+        assert!(values.is_empty());
+        assert!(started.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn should_return_as_soon_as_a_value_arrives_within_the_timeout() {
+        let (sender, receiver) = create_buf(4);
+        let consumer = thread::spawn(move || receiver.poll_timeout(10, Duration::from_secs(10)));
+
+        thread::sleep(Duration::from_millis(20));
+        add_key_value(&sender, VOD_SNAPSHOT_1);
+        assert_eq!(vec![VOD_SNAPSHOT_1], consumer.join().unwrap());
+    }
+
+    #[test]
+    fn should_deliver_pending_values_before_reporting_disconnection() {
+        let (sender, receiver) = create_buf(4);
+        add_key_value(&sender, VOD_SNAPSHOT_1);
+        add_value(&sender, BP_SNAPSHOT);
+        assert!(!receiver.is_disconnected());
+        drop(sender);
+
+        assert!(receiver.is_disconnected());
+        assert_eq!(Ok(vec![VOD_SNAPSHOT_1, BP_SNAPSHOT]), receiver.recv_all());
+        assert_eq!(Err(Disconnected), receiver.recv_all());
+    }
+
+    #[test]
+    fn should_receive_one_value_at_a_time_like_a_channel() {
+        let (sender, receiver) = create_buf(4);
+        assert_eq!(Err(TryRecvError::Empty), receiver.try_recv());
+        assert_eq!(Ok(()), sender.send(1, VOD_SNAPSHOT_1));
+        assert_eq!(Ok(()), sender.send(1, VOD_SNAPSHOT_2));
+        add_value(&sender, BP_SNAPSHOT);
+        assert_eq!(Ok(VOD_SNAPSHOT_2), receiver.try_recv());
+
+        let consumer = thread::spawn(move || {
+            let mut received = Vec::new();
+            while let Ok(value) = receiver.recv() {
+                received.push(value);
+            }
+            assert_eq!(Err(TryRecvError::Disconnected), receiver.try_recv());
+            received
+        });
+        thread::sleep(Duration::from_millis(20));
+        add_key_value(&sender, VOD_SNAPSHOT_1);
+        drop(sender);
+        assert_eq!(vec![BP_SNAPSHOT, VOD_SNAPSHOT_1], consumer.join().unwrap());
+    }
+
+    #[test]
+    fn should_take_single_values_in_order_past_removed_entries() {
+        let (sender, receiver) = create_buf(8);
+        add_key_value(&sender, VOD_SNAPSHOT_1);
+        add_key_value(&sender, BP_SNAPSHOT);
+        add_value(&sender, VOD_SNAPSHOT_2);
+        let bp_snapshot_2 = MarketSnapshot::new(2, 9, 10);
+        add_key_value(&sender, bp_snapshot_2);
+        assert_eq!(
+            Some(VOD_SNAPSHOT_1),
+            sender.remove(&VOD_SNAPSHOT_1.instrument_id)
+        );
+
+        assert_eq!(Ok(bp_snapshot_2), receiver.try_recv());
+        assert_eq!(Ok(VOD_SNAPSHOT_2), receiver.try_recv());
+        assert_eq!(Err(TryRecvError::Empty), receiver.try_recv());
+        assert_eq!(0, receiver.size());
+    }
+
+    #[test]
+    fn should_extend_with_pairs_and_drop_the_rejected_ones() {
+        let (mut sender, receiver) = create_buf(2);
+        sender.extend(vec![
+            (1, VOD_SNAPSHOT_1),
+            (2, BP_SNAPSHOT),
+            (3, MarketSnapshot::new(3, 1, 2)),
+            (1, VOD_SNAPSHOT_2),
+        ]);
+        assert_eq!(1, sender.rejection_count());
+        assert_eq!(vec![VOD_SNAPSHOT_2, BP_SNAPSHOT], receiver.poll_all());
+    }
+
+    #[test]
+    fn should_deliver_priority_values_ahead_of_buffered_entries() {
+        let (sender, receiver) = RingBufferBuilder::<usize, MarketSnapshot>::new(4)
+            .priority_slots(2)
+            .build();
+        let halt = MarketSnapshot::new(0, 0, 0);
+        add_key_value(&sender, VOD_SNAPSHOT_1);
+        add_value(&sender, BP_SNAPSHOT);
+        assert_eq!(Ok(()), sender.offer_priority(halt));
+        assert_eq!(Ok(()), sender.offer_priority(halt));
+        assert_eq!(Err(Error::Full), sender.offer_priority(halt));
+        assert_eq!(1, sender.rejection_count());
+        // a priority value never coalesces, nor takes up room in the ring
+        add_key_value(&sender, VOD_SNAPSHOT_2);
+        assert_eq!(2, sender.size());
+
+        assert_eq!(vec![halt], receiver.poll(1));
+        assert_eq!(Ok(halt), receiver.try_recv());
+        assert_eq!(vec![VOD_SNAPSHOT_2, BP_SNAPSHOT], receiver.poll_all());
+
+        assert_eq!(Ok(()), sender.offer_priority(halt));
+        add_key_value(&sender, VOD_SNAPSHOT_1);
+        assert_eq!(Ok(()), sender.offer_priority(BP_SNAPSHOT));
+        assert_eq!(
+            vec![halt, BP_SNAPSHOT, VOD_SNAPSHOT_1],
+            receiver.drain(8).collect::<Vec<_>>()
+        );
+
+        // without a priority lane the offer is a misuse, not a rejection
+        let (sender, _receiver) = create_buf(4);
+        assert_eq!(Err(Error::InvalidCapacity(0)), sender.offer_priority(halt));
+        assert_eq!(0, sender.rejection_count());
+        assert_eq!(None, sender.last_rejection());
+    }
+
+    #[test]
+    fn should_report_through_shared_stats_handles() {
+        let (sender, receiver) = create_buf(2);
+        let stats: StatsHandle<usize, MarketSnapshot> = receiver.stats_handle();
+        let shared = Arc::new(sender.stats_handle());
+        add_key_value(&sender, VOD_SNAPSHOT_1);
+        add_key_value(&sender, VOD_SNAPSHOT_2);
+        add_key_value(&sender, BP_SNAPSHOT);
+        assert!(!sender.offer(3, MarketSnapshot::new(3, 1, 2)));
+
+        let watchdogs: Vec<_> = (0..2)
+            .map(|_| {
+                let stats = stats.clone();
+                let shared = shared.clone();
+                thread::spawn(move || {
+                    assert_eq!(2, shared.capacity());
+                    (
+                        stats.size(),
+                        stats.rejection_count(),
+                        shared.coalesced_count(),
+                    )
+                })
+            })
+            .collect();
+        for watchdog in watchdogs {
+            assert_eq!((2, 1, 1), watchdog.join().unwrap());
+        }
+        assert_eq!(vec![VOD_SNAPSHOT_2, BP_SNAPSHOT], receiver.poll_all());
+        assert_eq!(0, stats.size());
+    }
+
+    #[test]
+    fn should_reject_offers_that_cross_the_byte_budget() {
+        let (sender, receiver) = RingBufferBuilder::<usize, String>::new(8)
+            .with_byte_capacity(10, String::len)
+            .build();
+        assert!(sender.offer(1, "abcd".to_string()));
+        assert!(sender.offer_value_only("efgh".to_string()));
+        assert!(!sender.offer(2, "ijk".to_string()));
+        assert!(!sender.offer_value_only("lmn".to_string()));
+        assert_eq!(2, sender.rejection_count());
+        assert_eq!(8, sender.byte_size());
+
+        // draining gives the bytes back
+        assert_eq!(vec!["abcd".to_string()], receiver.poll(1));
+        assert_eq!(4, receiver.byte_size());
+        assert!(sender.offer(2, "ijklmn".to_string()));
+        assert_eq!(10, sender.byte_size());
+        assert_eq!(2, receiver.poll_all().len());
+        assert_eq!(0, receiver.byte_size());
+    }
+
+    #[test]
+    fn should_adjust_the_byte_total_when_coalescing() {
+        let (sender, receiver) = RingBufferBuilder::<usize, String>::new(8)
+            .with_byte_capacity(10, String::len)
+            .build();
+        assert!(sender.offer(1, "abcdefgh".to_string()));
+        assert!(sender.offer(1, "ab".to_string()));
+        assert_eq!(2, sender.byte_size());
+        assert!(sender.offer(2, "cdefgh".to_string()));
+        assert!(sender.offer_value_only("ij".to_string()));
+        assert_eq!(10, sender.byte_size());
+        assert_eq!(0, sender.rejection_count());
+
+        assert_eq!(3, receiver.drain(8).count());
+        assert_eq!(0, receiver.byte_size());
+    }
+
+    #[test]
+    fn should_return_the_sequence_number_of_a_keyless_offer() {
+        let (sender, receiver) = create_buf(2);
+        add_key_value(&sender, VOD_SNAPSHOT_1);
+        add_key_value(&sender, VOD_SNAPSHOT_2);
+        let seq = sender.offer_value_only_seq(BP_SNAPSHOT).unwrap();
+        assert_eq!(None, sender.offer_value_only_seq(BP_SNAPSHOT));
+        assert_eq!(
+            vec![(1, VOD_SNAPSHOT_2), (seq, BP_SNAPSHOT)],
+            receiver.poll_seq(8)
+        );
+        assert_eq!(2, seq);
+    }
+
+    #[test]
+    fn should_drain_the_pending_entries_picked_by_a_predicate() {
+        let (sender, receiver) = create_buf(8);
+        add_key_value(&sender, VOD_SNAPSHOT_1);
+        add_key_value(&sender, BP_SNAPSHOT);
+        add_value(&sender, MarketSnapshot::new(3, 7, 8));
+        add_key_value(&sender, MarketSnapshot::new(4, 9, 10));
+        add_value(&sender, MarketSnapshot::new(5, 11, 12));
+
+        let drained = sender.drain_filter(|key, value| key == Some(&2) || value.bid == 11);
+        assert_eq!(vec![BP_SNAPSHOT, MarketSnapshot::new(5, 11, 12)], drained);
+        assert!(sender.drain_filter(|key, _| key == Some(&2)).is_empty());
+
+        // as after `remove`, an offer for a drained key fills its hole
+        add_key_value(&sender, BP_SNAPSHOT);
+        add_key_value(&sender, VOD_SNAPSHOT_2);
+        assert_eq!(
+            vec![
+                VOD_SNAPSHOT_2,
+                BP_SNAPSHOT,
+                MarketSnapshot::new(3, 7, 8),
+                MarketSnapshot::new(4, 9, 10),
+            ],
+            receiver.poll_all()
+        );
+    }
+
+    #[test]
+    fn should_hold_fresh_entries_back_for_the_flush_window() {
+        let (sender, receiver) = RingBufferBuilder::<usize, MarketSnapshot>::new(8)
+            .flush_window(Duration::from_millis(200))
+            .build();
+        add_key_value(&sender, VOD_SNAPSHOT_1);
+        assert!(receiver.poll(8).is_empty());
+        thread::sleep(Duration::from_millis(300));
+        // coalescing does not restart the wait
+        add_key_value(&sender, VOD_SNAPSHOT_2);
+        add_key_value(&sender, BP_SNAPSHOT);
+        assert_eq!(vec![VOD_SNAPSHOT_2], receiver.poll(8));
+
+        let started = Instant::now();
+        assert_eq!(vec![BP_SNAPSHOT], receiver.poll_blocking(8));
+        assert!(started.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn should_ignore_the_flush_window_when_polling_everything() {
+        let (sender, receiver) = RingBufferBuilder::<usize, MarketSnapshot>::new(8)
+            .flush_window(Duration::from_secs(60))
+            .build();
+        add_key_value(&sender, VOD_SNAPSHOT_1);
+        add_value(&sender, BP_SNAPSHOT);
+        assert!(receiver.poll(8).is_empty());
+        assert_eq!(vec![VOD_SNAPSHOT_1, BP_SNAPSHOT], receiver.poll_all());
+    }
+
+    #[test]
+    fn should_hold_entries_back_from_every_windowed_read() {
+        let (sender, receiver) = RingBufferBuilder::<usize, MarketSnapshot>::new(8)
+            .flush_window(Duration::from_secs(60))
+            .build();
+        add_key_value(&sender, VOD_SNAPSHOT_1);
+        add_value(&sender, BP_SNAPSHOT);
+        assert_eq!(Err(TryRecvError::Empty), receiver.try_recv());
+        assert_eq!(None, receiver.try_iter().next());
+        assert_eq!(0, receiver.drain(8).count());
+        assert_eq!(0, receiver.poll_for_each(8, drop));
+        assert_eq!(0, receiver.for_each_drained(8, drop));
+        assert!(receiver.poll_keyed(8).is_empty());
+        assert!(receiver.poll_seq(8).is_empty());
+        assert!(receiver.poll_bounded_bytes(1024, |_| 1).is_empty());
+        let mut selector = Selector::new();
+        selector.register(&receiver);
+        assert_eq!(None, selector.try_select());
+
+        assert_eq!(2, receiver.take_all().len());
+    }
+
+    #[test]
+    fn should_wait_out_the_flush_window_when_blocking() {
+        let (sender, receiver) = RingBufferBuilder::<usize, MarketSnapshot>::new(8)
+            .flush_window(Duration::from_millis(50))
+            .build();
+        add_key_value(&sender, VOD_SNAPSHOT_1);
+        let started = Instant::now();
+        assert_eq!(Ok(VOD_SNAPSHOT_1), receiver.recv());
+        assert!(started.elapsed() >= Duration::from_millis(40));
+
+        add_key_value(&sender, BP_SNAPSHOT);
+        let mut selector = Selector::new();
+        selector.register(&receiver);
+        let started = Instant::now();
+        assert_eq!(Some(0), selector.select_timeout(Duration::from_secs(5)));
+        assert!(started.elapsed() >= Duration::from_millis(40));
+        assert_eq!(vec![BP_SNAPSHOT], receiver.poll(8));
+    }
+
+    #[test]
+    fn should_grow_while_a_windowed_poll_waits() {
+        for _ in 0..20 {
+            let (mut sender, receiver) = RingBufferBuilder::<usize, usize>::new(2)
+                .flush_window(Duration::from_millis(1))
+                .build();
+            let consumer = thread::spawn(move || {
+                let mut received = Vec::new();
+                while received.len() < 200 {
+                    received.extend(receiver.poll_timeout(64, Duration::from_millis(5)));
+                }
+                received
+            });
+
+            // every grow swaps the slots the waiting consumer looks at
+            for key in 0..200 {
+                while !sender.offer(key, key) {
+                    if sender.capacity() < 256 {
+                        sender.try_grow().unwrap();
+                    }
+                    thread::yield_now();
+                }
+            }
+            assert_eq!((0..200).collect::<Vec<_>>(), consumer.join().unwrap());
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "crossbeam-channel")]
+    fn should_stand_in_for_a_crossbeam_channel() {
+        let (sender, receiver) = create_buf(2);
+        let send = sender.into_crossbeam_sink();
+        assert_eq!(Ok(()), send((1, VOD_SNAPSHOT_1)));
+        assert_eq!(Ok(()), send((1, VOD_SNAPSHOT_2)));
+        assert_eq!(Ok(()), send((2, BP_SNAPSHOT)));
+        assert!(send((3, MarketSnapshot::new(3, 1, 2))).is_err());
+
+        let received: Vec<_> = receiver.try_iter().collect();
+        assert_eq!(vec![VOD_SNAPSHOT_2, BP_SNAPSHOT], received);
+        assert_eq!(None, receiver.try_iter().next());
+    }
+
+    #[test]
+    fn should_drop_entries_that_outlive_their_ttl() {
+        let (sender, receiver) = RingBufferBuilder::<usize, MarketSnapshot>::new(4)
+            .ttl(Duration::from_millis(300))
+            .build();
+        add_key_value(&sender, VOD_SNAPSHOT_1);
+        add_key_value(&sender, BP_SNAPSHOT);
+        thread::sleep(Duration::from_millis(200));
+        // a coalescing write renews the entry
+        add_key_value(&sender, VOD_SNAPSHOT_2);
+        thread::sleep(Duration::from_millis(200));
+        add_key_value(&sender, MarketSnapshot::new(3, 1, 2));
+        // the expired entry held its slot until now
+        assert_eq!(3, receiver.size());
+
+        assert_eq!(
+            vec![VOD_SNAPSHOT_2, MarketSnapshot::new(3, 1, 2)],
+            receiver.poll(8)
+        );
+        assert_eq!(1, receiver.expired_count());
+        assert_eq!(0, receiver.size());
+    }
+
+    #[test]
+    fn should_keep_the_rest_pending_when_a_drained_callback_panics() {
+        let (sender, receiver) = create_buf(4);
+        add_key_value(&sender, VOD_SNAPSHOT_1);
+        add_key_value(&sender, BP_SNAPSHOT);
+        add_value(&sender, MarketSnapshot::new(3, 1, 2));
+
+        let mut seen = Vec::new();
+        assert_eq!(1, receiver.for_each_drained(1, |value| seen.push(value)));
+        assert_eq!(vec![VOD_SNAPSHOT_1], seen);
+
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            receiver.for_each_drained(8, |_| panic!("consumer failed"))
+        }));
+        assert!(panicked.is_err());
+        assert_eq!(1, receiver.size());
+        add_key_value(&sender, VOD_SNAPSHOT_2);
+        assert_eq!(
+            vec![MarketSnapshot::new(3, 1, 2), VOD_SNAPSHOT_2],
+            receiver.poll_all()
+        );
+    }
+
+    #[test]
+    fn should_list_the_keys_with_pending_values() {
+        let (sender, receiver) = create_buf(8);
+        add_key_value(&sender, VOD_SNAPSHOT_1);
+        add_value(&sender, MarketSnapshot::new(5, 1, 2));
+        add_key_value(&sender, BP_SNAPSHOT);
+        add_key_value(&sender, MarketSnapshot::new(3, 1, 2));
+        add_key_value(&sender, VOD_SNAPSHOT_2);
+        sender.remove(&3);
+        assert_eq!(vec![1, 2], receiver.live_keys());
+        assert_eq!(4, receiver.size());
+
+        receiver.poll(1);
+        assert_eq!(vec![2], receiver.live_keys());
+    }
+
+    fn hand_over_with<W: WaitStrategy + Clone + 'static>(strategy: W) {
+        let (sender, receiver) = RingBufferBuilder::<usize, MarketSnapshot>::new(1)
+            .wait_strategy(strategy)
+            .build();
+        let producer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            add_key_value(&sender, VOD_SNAPSHOT_1);
+            // waits for the consumer to make room
+            sender.offer_blocking(2, BP_SNAPSHOT).unwrap();
+        });
+        assert_eq!(vec![VOD_SNAPSHOT_1], receiver.poll_blocking(8));
+        assert_eq!(Ok(BP_SNAPSHOT), receiver.recv());
+        producer.join().unwrap();
+        assert!(receiver.recv().is_err());
+    }
+
+    #[test]
+    fn should_hand_values_over_with_every_wait_strategy() {
+        hand_over_with(BusySpin);
+        hand_over_with(Yielding);
+        hand_over_with(Blocking::new());
+    }
+
+    #[test]
+    fn should_offer_a_batch_only_if_all_of_it_fits() {
+        let (sender, receiver) = create_buf(4);
+        add_key_value(&sender, VOD_SNAPSHOT_1);
+        add_key_value(&sender, BP_SNAPSHOT);
+        let level = |id| (id, MarketSnapshot::new(id, 1, 2));
+
+        // one too many: keys 3, 4 and 5 need a slot each
+        let batch = [level(1), level(3), level(4), level(5)];
+        assert!(!sender.offer_batch_atomic(&batch));
+        assert_eq!(1, sender.rejection_count());
+        assert_eq!(2, receiver.size());
+
+        // exactly fits, as 1 and 2 coalesce and the second 3 with the first
+        let batch = [level(1), level(3), level(2), level(3), level(4)];
+        assert!(sender.offer_batch_atomic(&batch));
+        assert_eq!(
+            vec![level(1).1, level(2).1, level(3).1, level(4).1],
+            receiver.poll_all()
+        );
+    }
+
+    #[test]
+    fn should_grow_for_a_batch_larger_than_the_free_slots() {
+        let (sender, receiver) = RingBufferBuilder::<usize, MarketSnapshot>::new(4)
+            .grow_when_full(8)
+            .build();
+        add_key_value(&sender, VOD_SNAPSHOT_1);
+        let level = |id| (id, MarketSnapshot::new(id, 1, 2));
+
+        let batch: Vec<_> = (2..8).map(level).collect();
+        assert!(sender.offer_batch_atomic(&batch));
+        assert_eq!(8, sender.capacity());
+        assert_eq!(0, sender.rejection_count());
+
+        // past the limit nothing lands
+        assert!(!sender.offer_batch_atomic(&[level(8), level(9)]));
+        assert_eq!(1, sender.rejection_count());
+        assert_eq!(7, receiver.poll_all().len());
+    }
+
+    #[test]
+    fn should_funnel_every_failure_into_one_error_type() {
+        fn relay(
+            sender: &Sender<usize, MarketSnapshot>,
+            receiver: &Receiver<usize, MarketSnapshot>,
+        ) -> Result<MarketSnapshot, Error> {
+            sender.try_offer(1, VOD_SNAPSHOT_1)?;
+            sender.try_offer(2, BP_SNAPSHOT)?;
+            Ok(receiver.try_recv()?)
+        }
+
+        let (sender, receiver) = create_buf(1);
+        assert_eq!(Err(Error::Full), relay(&sender, &receiver));
+        assert_eq!(Ok(VOD_SNAPSHOT_1), receiver.try_recv());
+        assert_eq!(Error::Empty, receiver.try_recv().unwrap_err().into());
+        // the capacity checks fail with the same error
+        let invalid: Error = try_new_ring_buffer::<usize, usize>(0).err().unwrap();
+        let boxed: Box<dyn std::error::Error> = Box::new(invalid);
+        assert_eq!("invalid buffer capacity 0", boxed.to_string());
+    }
+
+    #[test]
+    fn should_coalesce_values_by_their_derived_key() {
+        let (sender, receiver) = create_buf(4);
+        let sender = sender.with_key_fn(|snapshot| snapshot.instrument_id);
+        assert!(sender.offer(VOD_SNAPSHOT_1));
+        assert!(sender.offer(BP_SNAPSHOT));
+        assert!(sender.offer(VOD_SNAPSHOT_2));
+        assert_eq!(1, sender.sender().coalesced_count());
+        assert_eq!(vec![VOD_SNAPSHOT_2, BP_SNAPSHOT], receiver.poll_all());
+    }
+
+    #[test]
+    fn should_tell_why_an_offer_was_rejected() {
+        let (sender, receiver) = RingBufferBuilder::<usize, String>::new(2)
+            .with_byte_capacity(10, String::len)
+            .build();
+        assert_eq!(None, sender.last_rejection());
+        assert!(!sender.offer(1, "abcdefghijk".to_string()));
+        assert_eq!(Some(RejectReason::ByteBudget), sender.last_rejection());
+        assert!(sender.offer(1, "ab".to_string()));
+        assert!(sender.offer(2, "cd".to_string()));
+        assert!(!sender.offer_value_only("ef".to_string()));
+        assert_eq!(Some(RejectReason::Full), sender.last_rejection());
+
+        drop(receiver);
+        assert!(!sender.offer(3, "gh".to_string()));
+        assert_eq!(Some(RejectReason::Closed), sender.last_rejection());
+        let metrics = sender.metrics();
+        assert_eq!(
+            (1, 1, 1),
+            (
+                metrics.full_rejections,
+                metrics.closed_rejections,
+                metrics.byte_budget_rejections
+            )
+        );
+        assert_eq!(2, metrics.rejection_count);
+    }
+
+    #[test]
+    fn should_poll_into_a_slice() {
+        let (sender, receiver) = create_buf(8);
+        add_key_value(&sender, VOD_SNAPSHOT_1);
+        add_key_value(&sender, BP_SNAPSHOT);
+        add_value(&sender, VOD_SNAPSHOT_2);
+
+        // a short slice leaves the rest pending
+        let mut out = [None; 2];
+        assert_eq!(2, receiver.poll_slice(&mut out));
+        assert_eq!([Some(VOD_SNAPSHOT_1), Some(BP_SNAPSHOT)], out);
+        assert_eq!(1, receiver.size());
+
+        // a long one stops at what is pending
+        let mut out = [Some(BP_SNAPSHOT); 4];
+        assert_eq!(1, receiver.poll_slice(&mut out));
+        assert_eq!([Some(VOD_SNAPSHOT_2), None, None, None], out);
+        assert_eq!(0, receiver.poll_slice(&mut out));
+        assert_eq!([None; 4], out);
+    }
+
+    #[test]
+    fn should_take_turns_between_ready_receivers() {
+        let (vod_sender, vod_receiver) = create_buf(4);
+        let (bp_sender, bp_receiver) = create_buf(4);
+        let mut selector = Selector::new();
+        assert_eq!(0, selector.register(&vod_receiver));
+        assert_eq!(1, selector.register(&bp_receiver));
+        assert_eq!(None, selector.try_select());
+
+        add_key_value(&vod_sender, VOD_SNAPSHOT_1);
+        add_key_value(&bp_sender, BP_SNAPSHOT);
+        // neither receiver is polled, yet both get their turn
+        assert_eq!(
+            vec![0, 1, 0, 1],
+            (0..4).map(|_| selector.select()).collect::<Vec<_>>()
+        );
+        assert_eq!(1, bp_receiver.poll_all().len());
+        assert_eq!(Some(0), selector.try_select());
+        assert_eq!(Some(0), selector.try_select());
+    }
+
+    #[test]
+    fn should_select_a_receiver_whose_sender_is_gone() {
+        let (vod_sender, vod_receiver) = create_buf(4);
+        let (bp_sender, bp_receiver) = create_buf(4);
+        let mut selector = Selector::new();
+        selector.register(&vod_receiver);
+        selector.register(&bp_receiver);
+
+        drop(bp_sender);
+        assert_eq!(1, selector.select());
+        assert_eq!(Err(Disconnected), bp_receiver.recv_all());
+        selector.remove(1);
+        assert_eq!(None, selector.select_timeout(Duration::from_millis(10)));
+        add_key_value(&vod_sender, VOD_SNAPSHOT_1);
+        assert_eq!(0, selector.select());
+    }
+
+    #[test]
+    fn should_wake_the_selector_from_any_producer() {
+        let (_vod_sender, vod_receiver) = create_buf(4);
+        let (bp_sender, bp_receiver) = create_buf(4);
+        let producer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            add_key_value(&bp_sender, BP_SNAPSHOT);
+            bp_sender
+        });
+
+        let mut selector = Selector::new();
+        selector.register(&vod_receiver);
+        selector.register(&bp_receiver);
+        assert_eq!(1, selector.select());
+        assert_eq!(vec![BP_SNAPSHOT], bp_receiver.poll_all());
+        drop(producer.join().unwrap());
+    }
+
+    #[test]
+    fn should_end_the_stream_once_the_sender_is_closed() {
+        let (sender, receiver) = create_buf(4);
+        let consumer = thread::spawn(move || {
+            let mut received = Vec::new();
+            loop {
+                let values = receiver.poll_blocking(10);
+                if values.is_empty() {
+                    break;
+                }
+                received.extend(values);
+            }
+            (received, receiver.recv_all())
+        });
+        add_key_value(&sender, VOD_SNAPSHOT_1);
+        add_key_value(&sender, BP_SNAPSHOT);
+        add_value(&sender, VOD_SNAPSHOT_2);
+        sender.close();
+
+        let (received, end) = consumer.join().unwrap();
+        assert_eq!(vec![VOD_SNAPSHOT_1, BP_SNAPSHOT, VOD_SNAPSHOT_2], received);
+        assert_eq!(Err(Disconnected), end);
+    }
+
+    #[test]
+    fn should_stop_blocking_once_the_sender_is_dropped() {
+        let (sender, receiver) = create_buf(4);
+        let consumer = thread::spawn(move || receiver.poll_blocking(10));
+
+        thread::sleep(Duration::from_millis(20));
+        drop(sender);
+        assert!(consumer.join().unwrap().is_empty());
+    }
+
+    #[test]
+    fn should_consume_until_disconnected_without_a_poison_pill() {
+        let (sender, receiver) = new_ring_buffer::<usize, usize>(8);
+        let producer = thread::spawn(move || {
+            for value in 0..10000 {
+                while !sender.offer_value_only(value) {
+                    thread::yield_now();
+                }
+            }
+        });
+
+        let mut received = 0;
+        while let Ok(values) = receiver.recv_all() {
+            for value in values {
+                assert_eq!(received, value);
+                received += 1;
+            }
+        }
+        producer.join().unwrap();
+        assert_eq!(10000, received);
+    }
+
+    #[test]
+    fn should_peek_while_the_producer_coalesces() {
+        let (sender, receiver) = new_ring_buffer::<usize, String>(4);
+        let producer = thread::spawn(move || {
+            for update in 0..20000 {
+                sender.offer(update % 2, update.to_string());
+            }
+            sender
+        });
+
+        for _ in 0..20000 {
+            if let Some(value) = receiver.peek(&0) {
+                assert_eq!(0, value.parse::<usize>().unwrap() % 2);
+            }
+        }
+        let _sender = producer.join().unwrap();
+        assert_eq!(Some(String::from("19998")), receiver.peek(&0));
+        assert_eq!(Some(String::from("19999")), receiver.peek(&1));
+    }
+
+    #[test]
+    fn should_split_a_standalone_buffer_into_handles() {
+        let buffer: CoalescingRingBuffer<usize, MarketSnapshot> = CoalescingRingBuffer::new(2);
+        assert!(buffer.is_empty());
+        assert_eq!(2, buffer.capacity());
+
+        let (sender, receiver) = buffer.split();
+        assert!(sender.offer(1, VOD_SNAPSHOT_1));
+        assert!(sender.offer(1, VOD_SNAPSHOT_2));
+        assert_eq!(vec![VOD_SNAPSHOT_2], receiver.poll_all());
+    }
+
+    #[test]
+    fn should_build_with_an_exact_capacity() {
+        let (sender, receiver) = RingBufferBuilder::<usize, usize>::new(1000)
+            .exact_capacity(true)
+            .build();
+        assert_eq!(1000, sender.capacity());
+
+        for round in 0..3 {
+            for key in 0..1000 {
+                assert!(sender.offer(key, round));
+            }
+            assert!(!sender.offer(1000, round));
+            assert_eq!(vec![round; 1000], receiver.poll_all());
+        }
+    }
+
+    #[test]
+    fn should_chain_the_builder_configuration() {
+        let (sender, receiver) = RingBufferBuilder::<usize, usize>::new(4)
+            .capacity(6)
+            .exact_capacity(true)
+            .with_hash_index(RandomState::new())
+            .build();
+        assert_eq!(6, sender.capacity());
+        assert!(sender.offer(1, 1));
+        assert!(sender.offer(1, 2));
+        assert_eq!(vec![2], receiver.poll_all());
+
+        let (sender, _receiver) = RingBufferBuilder::<usize, usize>::new(6)
+            .exact_capacity(false)
+            .build();
+        assert_eq!(8, sender.capacity());
+    }
+
+    #[test]
+    fn should_free_unread_values_when_both_handles_are_dropped() {
+        let payload = Arc::new(String::from("snapshot"));
+
+        let (sender, receiver) = new_ring_buffer::<usize, Arc<String>>(4);
+        assert!(sender.offer(1, payload.clone()));
+        assert!(sender.offer(1, payload.clone()));
+        assert!(sender.offer_value_only(payload.clone()));
+        assert_eq!(3, Arc::strong_count(&payload));
+        drop(sender);
+        drop(receiver);
+        assert_eq!(1, Arc::strong_count(&payload));
+
+        let (sender, receiver) = new_mpsc_ring_buffer::<usize, Arc<String>>(4);
+        assert!(sender.offer(1, payload.clone()));
+        drop(receiver);
+        drop(sender);
+        assert_eq!(1, Arc::strong_count(&payload));
+    }
+
+    #[test]
+    fn should_broadcast_to_receivers_that_coalesce_independently() {
+        let (sender, receivers) = new_broadcast_ring_buffer::<usize, MarketSnapshot>(4, 2);
+        assert_eq!(2, sender.receiver_count());
+        assert!(sender.offer(1, VOD_SNAPSHOT_1));
+        assert_eq!(vec![VOD_SNAPSHOT_1], receivers[0].poll_all());
+
+        assert!(sender.offer(1, VOD_SNAPSHOT_2));
+        assert!(sender.offer_value_only(BP_SNAPSHOT));
+        assert_eq!(vec![VOD_SNAPSHOT_2, BP_SNAPSHOT], receivers[0].poll_all());
+        // the second receiver never read the first update, so it skips it
+        assert_eq!(vec![VOD_SNAPSHOT_2, BP_SNAPSHOT], receivers[1].poll_all());
+
+        let mut receivers = receivers;
+        drop(receivers.pop());
+        assert_eq!(1, sender.receiver_count());
+        assert!(sender.offer(2, BP_SNAPSHOT));
+        drop(receivers);
+        assert!(sender.is_closed());
+        assert!(!sender.offer(2, BP_SNAPSHOT));
+    }
+
+    #[test]
+    fn should_coalesce_in_place_until_a_broadcast_receiver_reads() {
+        let (sender, receivers) = new_broadcast_ring_buffer::<usize, MarketSnapshot>(2, 2);
+        assert!(sender.offer(1, VOD_SNAPSHOT_1));
+        assert!(sender.offer(1, VOD_SNAPSHOT_2));
+        assert_eq!(1, receivers[0].size());
+        assert_eq!(1, receivers[1].size());
+
+        assert_eq!(vec![VOD_SNAPSHOT_2], receivers[0].poll_all());
+        assert_eq!(vec![VOD_SNAPSHOT_2], receivers[1].poll_all());
+    }
+
+    #[test]
+    fn should_hold_broadcast_slots_until_the_slowest_receiver_reads() {
+        let (sender, receivers) = new_broadcast_ring_buffer::<usize, MarketSnapshot>(2, 2);
+        assert_eq!(2, sender.capacity());
+        assert!(sender.offer(1, VOD_SNAPSHOT_1));
+        assert!(sender.offer(2, BP_SNAPSHOT));
+        assert_eq!(vec![VOD_SNAPSHOT_1, BP_SNAPSHOT], receivers[0].poll_all());
+
+        // the second receiver has read neither, so there is no room for another key
+        assert!(!sender.offer_value_only(BP_SNAPSHOT));
+        assert_eq!(1, sender.rejection_count());
+
+        assert_eq!(vec![VOD_SNAPSHOT_1], receivers[1].poll(1));
+        assert!(sender.offer_value_only(BP_SNAPSHOT));
+        assert_eq!(vec![BP_SNAPSHOT], receivers[0].poll_all());
+        assert_eq!(vec![BP_SNAPSHOT, BP_SNAPSHOT], receivers[1].poll_all());
+    }
+
+    #[test]
+    fn should_tell_broadcast_receivers_the_sender_is_gone() {
+        let (sender, receivers) = new_broadcast_ring_buffer::<usize, MarketSnapshot>(2, 1);
+        assert!(!receivers[0].is_disconnected());
+        drop(sender);
+        assert!(receivers[0].is_disconnected());
+    }
+
+    #[test]
+    fn should_coalesce_values_that_are_not_clone() {
+        #[derive(Debug, PartialEq)]
+        struct Payload(Vec<u8>);
+
+        let (sender, receiver) = new_ring_buffer::<usize, Box<Payload>>(2);
+        assert!(sender.offer(1, Box::new(Payload(vec![1]))));
+        assert!(sender.offer(1, Box::new(Payload(vec![2]))));
+        assert!(sender.offer(2, Box::new(Payload(vec![3]))));
+        assert_eq!(
+            vec![Box::new(Payload(vec![2])), Box::new(Payload(vec![3]))],
+            receiver.poll_all()
+        );
+    }
+
+    #[test]
+    fn should_snapshot_pending_entries_without_consuming_them() {
+        let (sender, receiver) = new_ring_buffer::<usize, usize>(4);
+        sender.offer(1, 10);
+        sender.offer_value_only(20);
+        sender.offer(1, 11);
+
+        let snapshot = receiver.snapshot();
+        assert_eq!(vec![(Some(1), 11), (None, 20)], snapshot.entries);
+        assert_eq!(4, snapshot.capacity);
+        assert_eq!(2, snapshot.size);
+        assert_eq!(0, snapshot.rejection_count);
+        assert_eq!(vec![11, 20], receiver.poll_all());
+        assert!(receiver.snapshot().entries.is_empty());
+    }
+
+    #[test]
+    fn should_report_what_each_batch_coalesced() {
+        let (sender, receiver) = new_ring_buffer::<usize, usize>(4);
+        for value in 0..5 {
+            assert!(sender.offer(value % 2, value));
+        }
+        let (values, stats) = receiver.poll_stats(10);
+        assert_eq!(vec![4, 3], values);
+        assert_eq!(
+            PollStats {
+                delivered: 2,
+                coalesced: 3
+            },
+            stats
+        );
+
+        assert!(sender.offer(1, 5));
+        let (values, stats) = receiver.poll_stats(10);
+        assert_eq!(vec![5], values);
+        assert_eq!(
+            PollStats {
+                delivered: 1,
+                coalesced: 0
+            },
+            stats
+        );
+    }
+
+    #[test]
+    fn should_visit_pending_entries_by_reference() {
+        struct Payload(String);
+
+        let (sender, receiver) = new_ring_buffer::<usize, Payload>(4);
+        sender.offer(1, Payload("a".to_string()));
+        sender.offer_value_only(Payload("b".to_string()));
+        sender.offer(2, Payload("c".to_string()));
+        assert!(sender.remove(&2).is_some());
+
+        let mut visited = Vec::new();
+        receiver.for_each_live(|key, value| visited.push((key.cloned(), value.0.clone())));
+        assert_eq!(
+            vec![(Some(1), "a".to_string()), (None, "b".to_string())],
+            visited
+        );
+        assert_eq!(3, receiver.size());
+        assert_eq!(2, receiver.poll_all().len());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn should_serialize_a_snapshot() {
+        let (sender, receiver) = new_ring_buffer::<usize, usize>(2);
+        sender.offer(1, 10);
+        sender.offer_value_only(20);
+
+        assert_eq!(
+            r#"{"entries":[[1,10],[null,20]],"capacity":2,"size":2,"rejection_count":0}"#,
+            serde_json::to_string(&receiver.snapshot()).unwrap()
+        );
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn should_stream_values_until_the_sender_is_gone() {
+        let (sender, receiver) = new_ring_buffer::<usize, usize>(8);
+        let producer = thread::spawn(move || {
+            for value in 0..1000 {
+                while !sender.offer_value_only(value) {
+                    thread::yield_now();
+                }
+            }
+        });
+
+        let mut stream = receiver.into_stream();
+        let mut received = Vec::new();
+        while let Some(value) = block_on_next(&mut stream) {
+            received.push(value);
+        }
+        producer.join().unwrap();
+        assert_eq!((0..1000).collect::<Vec<_>>(), received);
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn should_yield_pending_values_before_the_stream_ends() {
+        let (sender, receiver) = create_buf(4);
+        add_key_value(&sender, VOD_SNAPSHOT_1);
+        add_key_value(&sender, VOD_SNAPSHOT_2);
+        add_key_value(&sender, BP_SNAPSHOT);
+        drop(sender);
+
+        let mut stream = receiver.into_stream();
+        assert_eq!(Some(VOD_SNAPSHOT_2), block_on_next(&mut stream));
+        assert_eq!(Some(BP_SNAPSHOT), block_on_next(&mut stream));
+        assert_eq!(None, block_on_next(&mut stream));
+    }
+
+    // Polls the stream on the current thread, parking it until woken.
+    #[cfg(feature = "async")]
+    fn block_on_next<S: futures_core::Stream + Unpin>(stream: &mut S) -> Option<S::Item> {
+        use std::pin::Pin;
+        use std::task::{Context, Poll, Wake};
+
+        struct Unparker(thread::Thread);
+
+        impl Wake for Unparker {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+
+        let waker = Arc::new(Unparker(thread::current())).into();
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            match Pin::new(&mut *stream).poll_next(&mut cx) {
+                Poll::Ready(item) => return item,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    #[test]
+    fn should_wait_for_room_instead_of_rejecting() {
+        let (sender, receiver) = new_ring_buffer::<usize, usize>(2);
+        let producer = thread::spawn(move || {
+            for key in 0..1000 {
+                sender.offer_blocking(key, key).unwrap();
+            }
+            sender.rejection_count()
+        });
+
+        let mut received = Vec::new();
+        while received.len() < 1000 {
+            received.extend(receiver.poll(1));
+        }
+        assert_eq!(0, producer.join().unwrap());
+        assert_eq!((0..1000).collect::<Vec<_>>(), received);
+    }
+
+    #[test]
+    fn should_wake_a_waiting_producer_as_a_drain_goes() {
+        let (sender, receiver) = create_buf(2);
+        add_key_value(&sender, VOD_SNAPSHOT_1);
+        add_key_value(&sender, BP_SNAPSHOT);
+        let producer = thread::spawn(move || sender.offer_blocking(3, VOD_SNAPSHOT_2));
+
+        thread::sleep(Duration::from_millis(20));
+        let mut drained = receiver.drain(8);
+        assert_eq!(Some(VOD_SNAPSHOT_1), drained.next());
+        // the drain is still going, and the slot it read is free already
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !producer.is_finished() {
+            assert!(Instant::now() < deadline, "the producer was not woken");
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(Ok(()), producer.join().unwrap());
+        assert_eq!(Some(BP_SNAPSHOT), drained.next());
+    }
+
+    #[test]
+    fn should_stop_waiting_for_room_once_the_receiver_is_gone() {
+        let (sender, receiver) = create_buf(2);
+        add_key_value(&sender, VOD_SNAPSHOT_1);
+        add_key_value(&sender, BP_SNAPSHOT);
+        let producer = thread::spawn(move || sender.offer_blocking(3, VOD_SNAPSHOT_2));
+
+        thread::sleep(Duration::from_millis(20));
+        drop(receiver);
+        assert_eq!(
+            Err(OfferError::Closed {
+                key: 3,
+                value: VOD_SNAPSHOT_2
+            }),
+            producer.join().unwrap()
+        );
     }
 
     fn assert_is_empty(receiver: &Receiver<usize, MarketSnapshot>) {
@@ -205,7 +1422,7 @@ mod tests {
         let ret = (actual.len() == expected.len()) && // zip stops at the shortest
             actual.iter().zip(expected).all(|(a, b)| a == &b);
         println!("Contains 2");
-        return ret;
+        ret
     }
 
     fn add_value(sender: &Sender<usize, MarketSnapshot>, snapshot: MarketSnapshot) {
@@ -0,0 +1,71 @@
+#![cfg(feature = "smallvec")]
+
+use coalescing_buffer::ring::new_ring_buffer;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Counts the allocations made by the thread that is measuring, so that the test harness
+// allocating on other threads does not get in the way.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static MEASURING: Cell<bool> = const { Cell::new(false) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if MEASURING.with(Cell::get) {
+            ALLOCATIONS.fetch_add(1, Ordering::SeqCst);
+        }
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn allocations_during<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    MEASURING.with(|measuring| measuring.set(true));
+    let before = ALLOCATIONS.load(Ordering::SeqCst);
+    let result = f();
+    let allocations = ALLOCATIONS.load(Ordering::SeqCst) - before;
+    MEASURING.with(|measuring| measuring.set(false));
+    (result, allocations)
+}
+
+#[test]
+fn should_poll_a_small_batch_without_allocating() {
+    let (sender, receiver) = new_ring_buffer::<u32, u64>(64);
+    for key in 0..10 {
+        assert!(sender.offer(key, u64::from(key)));
+        assert!(sender.offer(key, u64::from(key) * 10));
+    }
+
+    let (polled, allocations) = allocations_during(|| receiver.poll_small(64));
+    assert_eq!(0, allocations);
+    assert!(!polled.spilled());
+    assert_eq!(
+        (0..10).map(|key| key * 10).collect::<Vec<u64>>(),
+        polled.to_vec()
+    );
+}
+
+#[test]
+fn should_spill_a_large_batch_to_the_heap() {
+    let (sender, receiver) = new_ring_buffer::<u32, u64>(64);
+    for key in 0..40 {
+        assert!(sender.offer(key, u64::from(key)));
+    }
+
+    let polled = receiver.poll_small(32);
+    assert!(polled.spilled());
+    assert_eq!(32, polled.len());
+    assert_eq!(8, receiver.poll_small(64).len());
+}
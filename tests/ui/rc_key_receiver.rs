@@ -0,0 +1,10 @@
+use coalescing_buffer::ring::new_ring_buffer;
+use std::rc::Rc;
+use std::thread;
+
+fn main() {
+    let (_sender, receiver) = new_ring_buffer::<Rc<i32>, i32>(8);
+    thread::spawn(move || {
+        receiver.poll_all();
+    });
+}
@@ -0,0 +1,10 @@
+use coalescing_buffer::ring::new_ring_buffer;
+use std::rc::Rc;
+use std::thread;
+
+fn main() {
+    let (sender, _receiver) = new_ring_buffer::<i32, Rc<i32>>(8);
+    thread::spawn(move || {
+        sender.offer(1, Rc::new(1));
+    });
+}
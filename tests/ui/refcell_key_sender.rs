@@ -0,0 +1,10 @@
+use coalescing_buffer::ring::new_ring_buffer;
+use std::cell::RefCell;
+use std::thread;
+
+fn main() {
+    let (sender, _receiver) = new_ring_buffer::<RefCell<i32>, i32>(8);
+    thread::spawn(move || {
+        sender.offer(RefCell::new(1), 1);
+    });
+}